@@ -1,50 +1,51 @@
 use serde::{Deserialize, Serialize};
 
+use crate::interpolation::BezierCurve;
+
+/// How long a bare pose in `main` holds before the next entry starts
+pub const POSE_DURATION: f32 = 1.0;
+
+/// Most plays a `repeat`, `x` or `loop until` may unroll into
+pub const MAX_REPEATS: usize = 1000;
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct MPLAnimationStatement {
     pub time: f32,
     pub poses: Vec<String>,
+    /// Blend weight of each pose, or empty when the poses are layered with `&`
+    #[serde(default)]
+    pub weights: Vec<f32>,
+    /// Curve of the transition into this keyframe
+    #[serde(default)]
+    pub easing: BezierCurve,
 }
 
 impl MPLAnimationStatement {
-    pub fn from_str(text: &str) -> Result<Self, String> {
-        let text = text.trim();
-
-        let text = if text.ends_with(';') {
-            &text[..text.len() - 1]
-        } else {
-            text
-        };
-
-        // Parse keyframe: "0.5: pose1 & pose2"
-        let colon_pos = text
-            .find(':')
-            .ok_or("Keyframe must have format 'time: poses'")?;
-
-        let time_str = text[..colon_pos].trim();
-        let poses_text = text[colon_pos + 1..].trim();
-
-        // Parse time
-        let time = time_str
-            .parse::<f32>()
-            .map_err(|_| format!("Invalid time value: '{}'", time_str))?;
-
+    pub fn new(time: f32, poses: Vec<String>) -> Result<Self, String> {
         if time < 0.0 {
             return Err("Time must be non-negative".to_string());
         }
 
-        // Parse poses (split by &)
-        let poses: Vec<String> = poses_text
-            .split('&')
-            .map(|s| s.trim().to_string())
-            .filter(|s| !s.is_empty())
-            .collect();
-
         if poses.is_empty() {
             return Err("Keyframe must contain at least one pose".to_string());
         }
 
-        Ok(Self { time, poses })
+        Ok(Self {
+            time,
+            poses,
+            weights: vec![],
+            easing: BezierCurve::default(),
+        })
+    }
+
+    pub fn with_weights(mut self, weights: Vec<f32>) -> Self {
+        self.weights = weights;
+        self
+    }
+
+    pub fn with_easing(mut self, easing: BezierCurve) -> Self {
+        self.easing = easing;
+        self
     }
 }
 
@@ -58,4 +59,101 @@ impl MPLAnimation {
     pub fn new(name: String, statements: Vec<MPLAnimationStatement>) -> Self {
         Self { name, statements }
     }
+
+    /// Length of one play-through: the last keyframe plus the spacing before it, so a
+    /// following animation starts on the next beat (keys at 0, 0.3, 0.6, 0.9 last 1.2s)
+    pub fn duration(&self) -> f32 {
+        track_duration(self.statements.iter().map(|s| s.time).collect())
+    }
+}
+
+/// Duration rule shared by every timed block, see `MPLAnimation::duration`
+pub(crate) fn track_duration(mut times: Vec<f32>) -> f32 {
+    times.sort_by(|a, b| a.total_cmp(b));
+
+    match times.as_slice() {
+        [] => 0.0,
+        [.., previous, last] if last > previous => last + (last - previous),
+        [.., last] if *last > 0.0 => last * 2.0,
+        [.., last] => last + POSE_DURATION,
+    }
+}
+
+/// One statement of the main block: animations and poses started together, either
+/// at an absolute time or right after the previous statement
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MPLMainEntry {
+    pub start: Option<f32>,
+    pub names: Vec<String>,
+    /// Animations or poses whose rotations are added on top of `names`
+    #[serde(default)]
+    pub layers: Vec<String>,
+    /// Plays the statement back to back, once when unset
+    #[serde(default)]
+    pub repeat: Option<MPLRepeat>,
+}
+
+impl MPLMainEntry {
+    /// Start time of every play of this entry when the previous entries end at `cursor`
+    /// and it lasts `cycle`, the time its keyframes must stay before and where the
+    /// next entry starts
+    pub fn layout(&self, cursor: f32, cycle: f32) -> (Vec<f32>, f32, f32) {
+        let start = self.start.unwrap_or(cursor);
+        let (starts, end) = match &self.repeat {
+            Some(repeat) => (repeat.starts(start, cycle), repeat.end()),
+            None => (vec![start], f32::INFINITY),
+        };
+        let next = (start + cycle * starts.len() as f32).min(end.max(start));
+        (starts, end, next)
+    }
+}
+
+/// How often a span plays back to back
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum MPLRepeat {
+    Times {
+        count: u32,
+    },
+    /// Plays until an absolute time, dropping keyframes at or past it
+    Until {
+        time: f32,
+    },
+}
+
+impl MPLRepeat {
+    /// Start time of every play of a `cycle` long span starting at `start`
+    pub fn starts(&self, start: f32, cycle: f32) -> Vec<f32> {
+        match *self {
+            MPLRepeat::Times { count } => (0..count).map(|i| start + cycle * i as f32).collect(),
+            MPLRepeat::Until { time } if cycle > 0.0 => (0..MAX_REPEATS)
+                .map(|i| start + cycle * i as f32)
+                .take_while(|play| *play < time)
+                .collect(),
+            MPLRepeat::Until { .. } => vec![start],
+        }
+    }
+
+    /// Keyframes of every play stay before this time
+    pub fn end(&self) -> f32 {
+        match *self {
+            MPLRepeat::Times { .. } => f32::INFINITY,
+            MPLRepeat::Until { time } => time,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tracks_last_one_more_spacing_past_the_last_key() {
+        assert!((track_duration(vec![0.0, 0.3, 0.6, 0.9]) - 1.2).abs() < 1e-6);
+        assert_eq!(track_duration(vec![2.0, 0.0, 1.5]), 2.5);
+        // A single key is spaced from the start of the track
+        assert_eq!(track_duration(vec![3.0]), 6.0);
+        assert_eq!(track_duration(vec![0.0]), POSE_DURATION);
+        assert_eq!(track_duration(vec![]), 0.0);
+    }
 }
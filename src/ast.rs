@@ -0,0 +1,360 @@
+use serde::{Deserialize, Serialize};
+
+use crate::lexer::Span;
+
+/// Syntax tree of an MPL script, in source order
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MPLScript {
+    pub blocks: Vec<Block>,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Block {
+    Pose(PoseBlock),
+    Mirror(MirrorBlock),
+    Animation(AnimationBlock),
+    Camera(TrackBlock),
+    Light(TrackBlock),
+    Shadow(TrackBlock),
+    Main(MainBlock),
+}
+
+impl Block {
+    pub fn span(&self) -> Span {
+        match self {
+            Block::Pose(block) => block.span,
+            Block::Mirror(block) => block.span,
+            Block::Animation(block) => block.span,
+            Block::Camera(block) => block.span,
+            Block::Light(block) => block.span,
+            Block::Shadow(block) => block.span,
+            Block::Main(block) => block.span,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Ident {
+    pub name: String,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Number {
+    pub value: f32,
+    pub span: Span,
+}
+
+/// `@pose kick_right = mirror kick_left;`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MirrorBlock {
+    pub name: Ident,
+    pub source: Ident,
+    pub span: Span,
+}
+
+/// An operand written out, or `$name` taking the argument of a parameterized pose
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Slot<T> {
+    Value(T),
+    Param(Ident),
+}
+
+impl Slot<Ident> {
+    pub fn span(&self) -> Span {
+        match self {
+            Slot::Value(ident) | Slot::Param(ident) => ident.span,
+        }
+    }
+}
+
+impl Slot<Number> {
+    pub fn span(&self) -> Span {
+        match self {
+            Slot::Value(number) => number.span,
+            Slot::Param(ident) => ident.span,
+        }
+    }
+}
+
+/// `@pose name { bone action direction degrees; morph name weight; ik name off; ... }`,
+/// or `@pose name(param, ...) { ... $param ... }` for a parameterized pose
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PoseBlock {
+    pub name: Ident,
+    #[serde(default)]
+    pub params: Vec<Ident>,
+    pub statements: Vec<PoseStatement>,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum PoseStatement {
+    Bone(BoneStatement),
+    Morph(MorphStatement),
+    Ik(IkStatement),
+    Show(ShowStatement),
+    Reach(ReachStatement),
+    Look(LookStatement),
+}
+
+impl PoseStatement {
+    pub fn span(&self) -> Span {
+        match self {
+            PoseStatement::Bone(statement) => statement.span,
+            PoseStatement::Morph(statement) => statement.span,
+            PoseStatement::Ik(statement) => statement.span,
+            PoseStatement::Show(statement) => statement.span,
+            PoseStatement::Reach(statement) => statement.span,
+            PoseStatement::Look(statement) => statement.span,
+        }
+    }
+
+    /// Every `$param` the statement refers to
+    pub fn params(&self) -> Vec<&Ident> {
+        let (idents, numbers) = match self {
+            PoseStatement::Bone(statement) => (
+                vec![&statement.bone, &statement.action, &statement.direction],
+                vec![&statement.degrees],
+            ),
+            PoseStatement::Morph(statement) => (vec![&statement.morph], vec![&statement.weight]),
+            PoseStatement::Look(statement) => (vec![], statement.values.iter().collect()),
+            PoseStatement::Ik(_) | PoseStatement::Show(_) | PoseStatement::Reach(_) => {
+                (vec![], vec![])
+            }
+        };
+        idents
+            .into_iter()
+            .filter_map(|slot| match slot {
+                Slot::Param(param) => Some(param),
+                Slot::Value(_) => None,
+            })
+            .chain(numbers.into_iter().filter_map(|slot| match slot {
+                Slot::Param(param) => Some(param),
+                Slot::Value(_) => None,
+            }))
+            .collect()
+    }
+}
+
+/// `bone action direction degrees;` or `bone move direction distance [cm|m];`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BoneStatement {
+    pub bone: Slot<Ident>,
+    pub action: Slot<Ident>,
+    pub direction: Slot<Ident>,
+    pub degrees: Slot<Number>,
+    /// Distance unit of a `move`; MMD units when omitted
+    pub unit: Option<Ident>,
+    pub span: Span,
+}
+
+/// `morph smile 0.8;` or `face blink 1;`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MorphStatement {
+    pub morph: Slot<Ident>,
+    pub weight: Slot<Number>,
+    pub span: Span,
+}
+
+/// `ik leg_l off;` switches an IK chain on or off
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct IkStatement {
+    pub ik: Ident,
+    pub state: Ident,
+    pub span: Span,
+}
+
+/// `hand_l reach (x, y, z) [cm|m];` or `foot_r plant ground;`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReachStatement {
+    pub limb: Ident,
+    /// `reach` or `plant`
+    pub verb: Ident,
+    pub target: ReachTarget,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ReachTarget {
+    /// Model-space coordinates; MMD units when the unit is omitted
+    Point {
+        values: Vec<Number>,
+        unit: Option<Ident>,
+        span: Span,
+    },
+    /// `ground`
+    Named(Ident),
+}
+
+/// `look_at (x, y, z) [cm|m] [with bone weight, ...];`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LookStatement {
+    /// Model-space coordinates; MMD units when the unit is omitted
+    pub values: Vec<Slot<Number>>,
+    pub unit: Option<Ident>,
+    /// Shares of the turn overriding the default ones
+    pub weights: Vec<(Ident, Number)>,
+    pub span: Span,
+}
+
+/// `show off;` hides the model
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ShowStatement {
+    pub state: Ident,
+    pub span: Span,
+}
+
+/// `@animation name { time: pose & pose; repeat 4 { ... } ... }`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AnimationBlock {
+    pub name: Ident,
+    pub statements: Vec<AnimationStatement>,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum AnimationStatement {
+    Key(KeyStatement),
+    Repeat(RepeatStatement),
+}
+
+impl AnimationStatement {
+    pub fn span(&self) -> Span {
+        match self {
+            AnimationStatement::Key(statement) => statement.span,
+            AnimationStatement::Repeat(statement) => statement.span,
+        }
+    }
+}
+
+/// `time: pose & pose with easing;`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct KeyStatement {
+    pub time: Number,
+    pub poses: Vec<PoseCall>,
+    /// `with ease_in` or `with bezier(x1, y1, x2, y2)`
+    pub easing: Option<Easing>,
+    pub span: Span,
+}
+
+/// `repeat 4 { ... }` or `loop until 12.0 { ... }`: the body's keyframes, timed from
+/// the start of each cycle, played back to back
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RepeatStatement {
+    pub repetition: Repetition,
+    pub statements: Vec<AnimationStatement>,
+    pub span: Span,
+}
+
+/// How often something plays back to back: `repeat 4` / `x 4`, or `loop until 12.0`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Repetition {
+    Times { count: Number },
+    Until { time: Number },
+}
+
+/// `stand`, or `look(left, 20)` for a parameterized pose, optionally weighted for a
+/// blend as `wave*0.7`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PoseCall {
+    pub pose: Ident,
+    pub args: Vec<Arg>,
+    #[serde(default)]
+    pub weight: Option<Number>,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Arg {
+    Ident(Ident),
+    Number(Number),
+}
+
+impl Arg {
+    pub fn span(&self) -> Span {
+        match self {
+            Arg::Ident(ident) => ident.span,
+            Arg::Number(number) => number.span,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Easing {
+    Preset(Ident),
+    Bezier { points: Vec<Number>, span: Span },
+}
+
+/// `@camera`, `@light` or `@shadow name { time: property value, property value; ... }`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TrackBlock {
+    pub name: Ident,
+    pub statements: Vec<TrackStatement>,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TrackStatement {
+    pub time: Number,
+    pub properties: Vec<Property>,
+    pub easing: Option<Easing>,
+    pub span: Span,
+}
+
+/// `distance 45`, `target (0, 10, 0)`, `perspective off` or `mode 2`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Property {
+    pub name: Ident,
+    pub value: PropertyValue,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum PropertyValue {
+    Number(Number),
+    Vector { values: Vec<Number>, span: Span },
+    Ident(Ident),
+}
+
+impl PropertyValue {
+    pub fn span(&self) -> Span {
+        match self {
+            PropertyValue::Number(number) => number.span,
+            PropertyValue::Vector { span, .. } => *span,
+            PropertyValue::Ident(ident) => ident.span,
+        }
+    }
+}
+
+/// `main { name; a & b; at 2.5: name; walk + breathe; walk x 4; ... }`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MainBlock {
+    pub statements: Vec<MainStatement>,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MainStatement {
+    /// Absolute start time given with `at`; otherwise the entry follows the previous one
+    pub start: Option<Number>,
+    /// Animations or poses started together
+    pub entries: Vec<Ident>,
+    /// Additive layers given with `+`, composed onto the entries
+    #[serde(default)]
+    pub layers: Vec<Ident>,
+    /// `x 4` or `loop until 12.0` after the entries
+    #[serde(default)]
+    pub repetition: Option<Repetition>,
+    pub span: Span,
+}
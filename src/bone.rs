@@ -1,19 +1,91 @@
-use crate::utils::Vector3;
-use std::cell::OnceCell;
+use crate::{skeleton::Skeleton, utils::Vector3};
+use std::cell::{OnceCell, RefCell};
 use std::collections::HashMap;
+use std::fmt;
+use std::sync::Arc;
+
+/// The action that translates a bone instead of rotating it
+pub const MOVE_ACTION: &str = "move";
+
+/// MMD's length unit is about 8 cm
+pub const MMD_UNITS_PER_METER: f32 = 12.5;
+
+/// Convert a distance with a `cm` or `m` suffix to MMD units
+pub fn distance_in_units(value: f32, unit: &str) -> Option<f32> {
+    match unit {
+        "cm" => Some(value * MMD_UNITS_PER_METER / 100.0),
+        "m" => Some(value * MMD_UNITS_PER_METER),
+        _ => None,
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ActionKind {
+    /// `axis` is a rotation axis and `limit` is in degrees
+    Rotation,
+    /// `axis` is a unit direction and `limit` is in MMD units
+    Translation,
+}
+
+impl ActionKind {
+    pub(crate) fn of(action: &str) -> Self {
+        if action == MOVE_ACTION {
+            ActionKind::Translation
+        } else {
+            ActionKind::Rotation
+        }
+    }
+}
 
 #[derive(Debug, Clone)]
 pub struct ActionRule {
     pub axis: Vector3,
     pub limit: f32,
+    pub kind: ActionKind,
+}
+
+/// Bone -> action -> direction -> rule
+pub type BoneRules = HashMap<String, HashMap<String, HashMap<String, ActionRule>>>;
+
+/// Why a `bone action direction degrees` combination was rejected
+#[derive(Debug, Clone, PartialEq)]
+pub enum RuleViolation {
+    UnknownBone,
+    UnknownAction,
+    UnknownDirection,
+    LimitExceeded(f32),
+    /// Negative amounts would turn the other way without that direction's limit
+    Negative,
 }
 
 pub struct BoneActionDatabase {
-    rules: HashMap<String, HashMap<String, HashMap<String, ActionRule>>>,
+    rules: BoneRules,
     all_bones: Vec<String>,
     bone_actions: HashMap<String, Vec<String>>,
     bone_action_directions: HashMap<String, Vec<String>>,
     bone_translations: HashMap<String, String>, // English -> Japanese
+    all_morphs: Vec<String>,
+    morph_translations: HashMap<String, String>, // English -> Japanese
+    all_iks: Vec<String>,
+    ik_translations: HashMap<String, String>, // English -> Japanese
+    mirrors: HashMap<String, String>,         // Left <-> right counterparts
+    skeleton: Skeleton,
+}
+
+impl fmt::Debug for BoneActionDatabase {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("BoneActionDatabase")
+            .field("bones", &self.all_bones.len())
+            .finish_non_exhaustive()
+    }
+}
+
+macro_rules! translations {
+    { $( $en:literal => $jp:literal ),* $(,)? } => {{
+        let mut map = HashMap::new();
+        $( map.insert($en.to_string(), $jp.to_string()); )*
+        map
+    }};
 }
 
 impl BoneActionDatabase {
@@ -22,7 +94,9 @@ impl BoneActionDatabase {
         Self::from_rules(rules)
     }
 
-    fn from_rules(rules: HashMap<String, HashMap<String, HashMap<String, ActionRule>>>) -> Self {
+    /// A database over custom rules, with the built-in bone, morph and IK names and
+    /// the standard skeleton
+    pub fn from_rules(rules: BoneRules) -> Self {
         let all_bones: Vec<String> = rules.keys().cloned().collect();
         let mut bone_actions = HashMap::new();
         let mut bone_action_directions = HashMap::new();
@@ -37,6 +111,17 @@ impl BoneActionDatabase {
         }
 
         let bone_translations = Self::build_translations();
+        let morph_translations = Self::build_morph_translations();
+        let mut all_morphs: Vec<String> = morph_translations.keys().cloned().collect();
+        all_morphs.sort();
+        let ik_translations = Self::build_ik_translations();
+        let mut all_iks: Vec<String> = ik_translations.keys().cloned().collect();
+        all_iks.sort();
+
+        let mut mirrors = HashMap::new();
+        for names in [&all_bones, &all_morphs, &all_iks] {
+            mirrors.extend(Self::mirror_pairs(names));
+        }
 
         Self {
             rules,
@@ -44,10 +129,120 @@ impl BoneActionDatabase {
             bone_actions,
             bone_action_directions,
             bone_translations,
+            all_morphs,
+            morph_translations,
+            all_iks,
+            ik_translations,
+            mirrors,
+            skeleton: Skeleton::standard(),
         }
     }
 
+    /// Add or override English -> Japanese bone names, for bones a custom rig adds
+    pub fn with_bone_names(mut self, names: HashMap<String, String>) -> Self {
+        self.bone_translations.extend(names);
+        self
+    }
+
+    pub fn with_skeleton(mut self, skeleton: Skeleton) -> Self {
+        self.skeleton = skeleton;
+        self
+    }
+
+    /// Pair `x_r` with `x_l`, or with plain `x` when only that exists (`wink`/`wink_r`)
+    fn mirror_pairs(names: &[String]) -> Vec<(String, String)> {
+        let mut pairs = vec![];
+        for name in names {
+            let Some(stem) = name.strip_suffix("_r") else {
+                continue;
+            };
+            let left = format!("{}_l", stem);
+            let other = if names.contains(&left) {
+                left
+            } else if names.iter().any(|n| n == stem) {
+                stem.to_string()
+            } else {
+                continue;
+            };
+            pairs.push((name.clone(), other.clone()));
+            pairs.push((other, name.clone()));
+        }
+        pairs
+    }
+
+    /// The counterpart from `mirrors`, the name itself for center names, or `None`
+    /// for a left or right name without a counterpart
+    fn mirror_of<'a>(&'a self, name: &'a str) -> Option<&'a str> {
+        match self.mirrors.get(name) {
+            Some(other) => Some(other),
+            None if name.ends_with("_l") || name.ends_with("_r") => None,
+            None => Some(name),
+        }
+    }
+
+    /// Left/right counterpart of a bone, the bone itself for center bones
+    pub fn mirror_bone<'a>(&'a self, bone: &'a str) -> Option<&'a str> {
+        self.rules.get(bone)?;
+        self.mirror_of(bone)
+    }
+
+    /// Action and direction of the counterpart bone for a mirror image. The action is
+    /// kept; the direction is the one whose rule reflects this one across the model's
+    /// center (X) plane, rotation axes reflecting to (x, -y, -z) and `move` directions
+    /// to (-x, y, z). When no rule of the action reflects it, e.g. in a custom rig,
+    /// `left` and `right` swap and other directions are kept.
+    pub fn mirror_rule(
+        &self,
+        bone: &str,
+        action: &str,
+        direction: &str,
+    ) -> Result<(String, String, String), String> {
+        let mirror = self
+            .mirror_bone(bone)
+            .ok_or(format!("Bone '{}' has no mirror image", bone))?;
+        let rule = self
+            .get_rule(bone, action, direction)
+            .ok_or(format!("Unknown rule {} {} {}", bone, action, direction))?;
+        let axis = rule.axis.normalize();
+        let reflected = match rule.kind {
+            ActionKind::Rotation => Vector3::new(axis.x, -axis.y, -axis.z),
+            ActionKind::Translation => Vector3::new(-axis.x, axis.y, axis.z),
+        };
+
+        let mut directions = self.directions(mirror, action).unwrap_or_default().to_vec();
+        directions.sort();
+        let reflecting = directions.into_iter().find(|other| {
+            self.get_rule(mirror, action, other).is_some_and(|other| {
+                other.kind == rule.kind && other.axis.normalize().dot(&reflected) > 0.999
+            })
+        });
+        let direction = reflecting.unwrap_or_else(|| {
+            match direction {
+                "left" => "right",
+                "right" => "left",
+                other => other,
+            }
+            .to_string()
+        });
+        Ok((mirror.to_string(), action.to_string(), direction))
+    }
+
+    /// Left/right counterpart of a morph, e.g. `wink_r` for `wink`
+    pub fn mirror_morph<'a>(&'a self, morph: &'a str) -> Option<&'a str> {
+        self.morph_translations.get(morph)?;
+        self.mirror_of(morph)
+    }
+
+    pub fn mirror_ik<'a>(&'a self, ik: &'a str) -> Option<&'a str> {
+        self.ik_translations.get(ik)?;
+        self.mirror_of(ik)
+    }
+
     // Public API
+    pub fn skeleton(&self) -> &Skeleton {
+        &self.skeleton
+    }
+
     pub fn bones(&self) -> &[String] {
         &self.all_bones
     }
@@ -68,21 +263,39 @@ impl BoneActionDatabase {
         direction: &str,
         degrees: f32,
     ) -> Result<(), String> {
-        match self
-            .rules
-            .get(bone)
-            .and_then(|actions| actions.get(action))
-            .and_then(|directions| directions.get(direction))
-        {
-            Some(rule) if degrees <= rule.limit => Ok(()),
-            Some(rule) => Err(format!(
-                "Max {} degrees for {} {} {}",
-                rule.limit, bone, action, direction
-            )),
-            None => Err(format!(
-                "Invalid combination: {} {} {}",
-                bone, action, direction
-            )),
+        self.check(bone, action, direction, degrees)
+            .map_err(|violation| match violation {
+                RuleViolation::LimitExceeded(limit) => format!(
+                    "Max {} degrees for {} {} {}",
+                    limit, bone, action, direction
+                ),
+                RuleViolation::Negative => format!(
+                    "Negative amount {} for {} {} {}",
+                    degrees, bone, action, direction
+                ),
+                _ => format!("Invalid combination: {} {} {}", bone, action, direction),
+            })
+    }
+
+    pub fn check(
+        &self,
+        bone: &str,
+        action: &str,
+        direction: &str,
+        degrees: f32,
+    ) -> Result<(), RuleViolation> {
+        let actions = self.rules.get(bone).ok_or(RuleViolation::UnknownBone)?;
+        let directions = actions.get(action).ok_or(RuleViolation::UnknownAction)?;
+        let rule = directions
+            .get(direction)
+            .ok_or(RuleViolation::UnknownDirection)?;
+
+        if degrees < 0.0 {
+            Err(RuleViolation::Negative)
+        } else if degrees <= rule.limit {
+            Ok(())
+        } else {
+            Err(RuleViolation::LimitExceeded(rule.limit))
         }
     }
 
@@ -104,24 +317,109 @@ impl BoneActionDatabase {
             .map(|(k, _)| k.as_str())
     }
 
-    fn build_translations() -> HashMap<String, String> {
-        macro_rules! translations {
-            { $( $en:literal => $jp:literal ),* $(,)? } => {{
-                let mut map = HashMap::new();
-                $( map.insert($en.to_string(), $jp.to_string()); )*
-                map
-            }};
+    pub fn morphs(&self) -> &[String] {
+        &self.all_morphs
+    }
+
+    /// Japanese morph name for an English one. Japanese names of known morphs are
+    /// accepted as they are.
+    pub fn morph_japanese_name(&self, morph: &str) -> Option<&str> {
+        match self.morph_translations.get_key_value(morph) {
+            Some((_, jp)) => Some(jp.as_str()),
+            None => self
+                .morph_translations
+                .values()
+                .find(|jp| *jp == morph)
+                .map(|jp| jp.as_str()),
+        }
+    }
+
+    pub fn morph_english_name(&self, morph_jp: &str) -> Option<&str> {
+        self.morph_translations
+            .iter()
+            .find(|(_, v)| v.as_str() == morph_jp)
+            .map(|(k, _)| k.as_str())
+    }
+
+    /// IK chains that `ik` statements switch, named after the limb they drive
+    pub fn iks(&self) -> &[String] {
+        &self.all_iks
+    }
+
+    pub fn ik_japanese_name(&self, ik: &str) -> Option<&str> {
+        self.ik_translations.get(ik).map(|s| s.as_str())
+    }
+
+    pub fn ik_english_name(&self, ik_jp: &str) -> Option<&str> {
+        self.ik_translations
+            .iter()
+            .find(|(_, v)| v.as_str() == ik_jp)
+            .map(|(k, _)| k.as_str())
+    }
+
+    /// IK bones of a standard model, keyed by the limb whose FK bends they override
+    pub(crate) fn build_ik_translations() -> HashMap<String, String> {
+        translations! {
+            "leg_l" => "左足ＩＫ",
+            "leg_r" => "右足ＩＫ",
+            "toe_l" => "左つま先ＩＫ",
+            "toe_r" => "右つま先ＩＫ",
+        }
+    }
+
+    /// Common morphs of MMD's standard facial set
+    fn build_morph_translations() -> HashMap<String, String> {
+        translations! {
+            // Eyes
+            "blink" => "まばたき",
+            "smile" => "笑い",
+            "wink" => "ウィンク",
+            "wink_r" => "ウィンク右",
+            "wink2" => "ウィンク２",
+            "wink2_r" => "ｳｨﾝｸ２右",
+            "calm" => "なごみ",
+            "surprised" => "びっくり",
+            "half_closed" => "じと目",
+            "pupil_small" => "瞳小",
+            // Mouth
+            "a" => "あ",
+            "i" => "い",
+            "u" => "う",
+            "e" => "え",
+            "o" => "お",
+            "grin" => "にやり",
+            "mouth_smile" => "にっこり",
+            "mouth_wide" => "ワ",
+            "mouth_triangle" => "▲",
+            "mouth_w" => "ω",
+            // Brows
+            "serious" => "真面目",
+            "troubled" => "困る",
+            "cheerful" => "にこり",
+            "angry" => "怒り",
+            "brow_up" => "上",
+            "brow_down" => "下",
+            // Other
+            "blush" => "照れ",
         }
+    }
 
+    pub(crate) fn build_translations() -> HashMap<String, String> {
         translations! {
             "base" => "全ての親",
             "center" => "センター",
+            "groove" => "グルーブ",
+            "leg_ik_l" => "左足ＩＫ",
+            "leg_ik_r" => "右足ＩＫ",
             "upper_body" => "上半身",
             "upper_body2" => "上半身2",
             "lower_body" => "下半身",
             "waist" => "腰",
             "neck" => "首",
             "head" => "頭",
+            "eyes" => "両目",
+            "eye_l" => "左目",
+            "eye_r" => "右目",
             "shoulder_l" => "左肩",
             "shoulder_r" => "右肩",
             "arm_l" => "左腕",
@@ -175,7 +473,7 @@ impl BoneActionDatabase {
         }
     }
 
-    fn build_rules() -> HashMap<String, HashMap<String, HashMap<String, ActionRule>>> {
+    pub(crate) fn build_rules() -> BoneRules {
         macro_rules! rules {
             {
                 $( $bone:literal => {
@@ -193,6 +491,7 @@ impl BoneActionDatabase {
                             directions.insert($direction.to_string(), ActionRule {
                                 axis: Vector3::new($x, $y, $z),
                                 limit: $limit,
+                                kind: ActionKind::of($action),
                             });
                         )*
                         actions.insert($action.to_string(), directions);
@@ -217,6 +516,15 @@ impl BoneActionDatabase {
                     "left" => [0.0, 0.0, -1.0], 180.0,
                     "right" => [0.0, 0.0, 1.0], 180.0,
                 },
+                // MMD models face -Z, so their left is +X
+                "move" => {
+                    "up" => [0.0, 1.0, 0.0], 100.0,
+                    "down" => [0.0, -1.0, 0.0], 100.0,
+                    "left" => [1.0, 0.0, 0.0], 100.0,
+                    "right" => [-1.0, 0.0, 0.0], 100.0,
+                    "forward" => [0.0, 0.0, -1.0], 100.0,
+                    "backward" => [0.0, 0.0, 1.0], 100.0,
+                },
             },
             "center" => {
                 "bend" => {
@@ -230,7 +538,41 @@ impl BoneActionDatabase {
                 "sway" => {
                     "left" => [0.0, 0.0, -1.0], 180.0,
                     "right" => [0.0, 0.0, 1.0], 180.0,
-                }
+                },
+                "move" => {
+                    "up" => [0.0, 1.0, 0.0], 20.0,
+                    "down" => [0.0, -1.0, 0.0], 15.0,
+                    "left" => [1.0, 0.0, 0.0], 50.0,
+                    "right" => [-1.0, 0.0, 0.0], 50.0,
+                    "forward" => [0.0, 0.0, -1.0], 50.0,
+                    "backward" => [0.0, 0.0, 1.0], 50.0,
+                },
+            },
+            "groove" => {
+                "move" => {
+                    "up" => [0.0, 1.0, 0.0], 10.0,
+                    "down" => [0.0, -1.0, 0.0], 10.0,
+                },
+            },
+            "leg_ik_l" => {
+                "move" => {
+                    "up" => [0.0, 1.0, 0.0], 20.0,
+                    "down" => [0.0, -1.0, 0.0], 2.0,
+                    "left" => [1.0, 0.0, 0.0], 10.0,
+                    "right" => [-1.0, 0.0, 0.0], 10.0,
+                    "forward" => [0.0, 0.0, -1.0], 15.0,
+                    "backward" => [0.0, 0.0, 1.0], 15.0,
+                },
+            },
+            "leg_ik_r" => {
+                "move" => {
+                    "up" => [0.0, 1.0, 0.0], 20.0,
+                    "down" => [0.0, -1.0, 0.0], 2.0,
+                    "left" => [1.0, 0.0, 0.0], 10.0,
+                    "right" => [-1.0, 0.0, 0.0], 10.0,
+                    "forward" => [0.0, 0.0, -1.0], 15.0,
+                    "backward" => [0.0, 0.0, 1.0], 15.0,
+                },
             },
             "head" => {
                 "bend" => {
@@ -246,6 +588,36 @@ impl BoneActionDatabase {
                     "right" => [0.0, 0.0, 1.0], 30.0,
                 },
             },
+            "eyes" => {
+                "bend" => {
+                    "forward" => [-1.0, 0.0, 0.0], 25.0,
+                    "backward" => [1.0, 0.0, 0.0], 25.0,
+                },
+                "turn" => {
+                    "left" => [0.0, -1.0, 0.0], 35.0,
+                    "right" => [0.0, 1.0, 0.0], 35.0,
+                },
+            },
+            "eye_l" => {
+                "bend" => {
+                    "forward" => [-1.0, 0.0, 0.0], 25.0,
+                    "backward" => [1.0, 0.0, 0.0], 25.0,
+                },
+                "turn" => {
+                    "left" => [0.0, -1.0, 0.0], 35.0,
+                    "right" => [0.0, 1.0, 0.0], 35.0,
+                },
+            },
+            "eye_r" => {
+                "bend" => {
+                    "forward" => [-1.0, 0.0, 0.0], 25.0,
+                    "backward" => [1.0, 0.0, 0.0], 25.0,
+                },
+                "turn" => {
+                    "left" => [0.0, -1.0, 0.0], 35.0,
+                    "right" => [0.0, 1.0, 0.0], 35.0,
+                },
+            },
             "neck" => {
                 "bend" => {
                     "forward" => [-1.0, 0.0, 0.0], 45.0,
@@ -333,8 +705,8 @@ impl BoneActionDatabase {
                     "backward" => [0.0, 0.0, -1.0], 90.0,
                 },
                 "sway" => {
-                    "left" => [0.0, 1.0, 0.0], 90.0,
-                    "right" => [0.0, -1.0, 0.0], 90.0,
+                    "left" => [0.0, -1.0, 0.0], 90.0,
+                    "right" => [0.0, 1.0, 0.0], 90.0,
                 },
             },
             "arm_l" => {
@@ -392,11 +764,11 @@ impl BoneActionDatabase {
             "wrist_r" => {
                 "bend" => {
                     "forward" => [0.0, 0.0, 1.0], 60.0,
-                    "backward" => [-1.0, 0.0, -1.0], 30.0,
+                    "backward" => [1.0, 0.0, 1.0], 30.0,
                 },
                 "sway" => {
-                    "left" => [-1.0, -1.0, 0.0], 15.0,
-                    "right" => [1.0, -1.0, 0.0], 15.0,
+                    "left" => [1.0, -1.0, 0.0], 15.0,
+                    "right" => [-1.0, -1.0, 0.0], 15.0,
                 },
             },
             "wrist_twist_l" => {
@@ -524,6 +896,10 @@ impl BoneActionDatabase {
                     "forward" => [-1.0, -1.0, 0.0], 90.0,
                     "backward" => [1.0, 1.0, 0.0], 15.0,
                 },
+                "sway" => {
+                    "left" => [0.0, 0.0, 1.0], 45.0,
+                    "right" => [0.0, 0.0, -1.0], 45.0,
+                },
             },
             "thumb_1_l" => {
                 "bend" => {
@@ -543,8 +919,8 @@ impl BoneActionDatabase {
                     "backward" => [0.0, 0.0, 1.0], 15.0,
                 },
                 "sway" => {
-                    "left" => [0.0, 0.0, 1.0], 45.0,
-                    "right" => [0.0, 0.0, -1.0], 45.0,
+                    "left" => [0.0, 1.0, 0.0], 45.0,
+                    "right" => [0.0, -1.0, 0.0], 45.0,
                 },
             },
             "index_1_l" => {
@@ -565,8 +941,8 @@ impl BoneActionDatabase {
                     "backward" => [0.0, 0.0, 1.0], 15.0,
                 },
                 "sway" => {
-                    "left" => [0.0, 0.0, 1.0], 45.0,
-                    "right" => [0.0, 0.0, -1.0], 45.0,
+                    "left" => [0.0, 1.0, 0.0], 45.0,
+                    "right" => [0.0, -1.0, 0.0], 45.0,
                 },
             },
             "middle_0_l" => {
@@ -575,8 +951,8 @@ impl BoneActionDatabase {
                     "backward" => [0.0, 0.0, 1.0], 15.0,
                 },
                 "sway" => {
-                    "left" => [0.0, 0.0, 1.0], 45.0,
-                    "right" => [0.0, 0.0, -1.0], 45.0,
+                    "left" => [0.0, 1.0, 0.0], 45.0,
+                    "right" => [0.0, -1.0, 0.0], 45.0,
                 },
             },
             "middle_1_l" => {
@@ -597,8 +973,8 @@ impl BoneActionDatabase {
                     "backward" => [0.0, 0.0, 1.0], 15.0,
                 },
                 "sway" => {
-                    "left" => [0.0, 0.0, 1.0], 45.0,
-                    "right" => [0.0, 0.0, -1.0], 45.0,
+                    "left" => [0.0, 1.0, 0.0], 45.0,
+                    "right" => [0.0, -1.0, 0.0], 45.0,
                 },
             },
             "ring_1_l" => {
@@ -619,8 +995,8 @@ impl BoneActionDatabase {
                     "backward" => [0.0, 0.0, 1.0], 15.0,
                 },
                 "sway" => {
-                    "left" => [0.0, 0.0, 1.0], 45.0,
-                    "right" => [0.0, 0.0, -1.0], 45.0,
+                    "left" => [0.0, 1.0, 0.0], 45.0,
+                    "right" => [0.0, -1.0, 0.0], 45.0,
                 },
             },
             "pinky_1_l" => {
@@ -664,8 +1040,8 @@ impl BoneActionDatabase {
                     "backward" => [0.0, 0.0, -1.0], 15.0,
                 },
                 "sway" => {
-                    "left" => [1.0, 0.0, 0.0], 15.0,
-                    "right" => [-1.0, 0.0, 0.0], 15.0,
+                    "left" => [0.0, 1.0, 0.0], 15.0,
+                    "right" => [0.0, -1.0, 0.0], 15.0,
                 },
             },
             "index_1_r" => {
@@ -686,8 +1062,8 @@ impl BoneActionDatabase {
                     "backward" => [0.0, 0.0, -1.0], 15.0,
                 },
                 "sway" => {
-                    "left" => [1.0, 0.0, 0.0], 45.0,
-                    "right" => [-1.0, 0.0, 0.0], 45.0,
+                    "left" => [0.0, 1.0, 0.0], 45.0,
+                    "right" => [0.0, -1.0, 0.0], 45.0,
                 },
             },
             "middle_1_r" => {
@@ -708,8 +1084,8 @@ impl BoneActionDatabase {
                     "backward" => [0.0, 0.0, -1.0], 15.0,
                 },
                 "sway" => {
-                    "left" => [1.0, 0.0, 0.0], 45.0,
-                    "right" => [-1.0, 0.0, 0.0], 45.0,
+                    "left" => [0.0, 1.0, 0.0], 45.0,
+                    "right" => [0.0, -1.0, 0.0], 45.0,
                 },
             },
             "ring_1_r" => {
@@ -730,8 +1106,8 @@ impl BoneActionDatabase {
                     "backward" => [0.0, 0.0, -1.0], 15.0,
                 },
                 "sway" => {
-                    "left" => [1.0, 0.0, 0.0], 45.0,
-                    "right" => [-1.0, 0.0, 0.0], 45.0,
+                    "left" => [0.0, 1.0, 0.0], 45.0,
+                    "right" => [0.0, -1.0, 0.0], 45.0,
                 },
             },
             "pinky_1_r" => {
@@ -751,9 +1127,40 @@ impl BoneActionDatabase {
 }
 
 thread_local! {
-    static BONE_DB: OnceCell<BoneActionDatabase> = OnceCell::new();
+    static BONE_DB: OnceCell<BoneActionDatabase> = const { OnceCell::new() };
+    static SCOPED_BONE_DB: RefCell<Option<Arc<BoneActionDatabase>>> = const { RefCell::new(None) };
 }
 
+/// Run `f` against the database set by `using_bone_db`, or the built-in one
 pub fn with_bone_db<T>(f: impl FnOnce(&BoneActionDatabase) -> T) -> T {
-    BONE_DB.with(|db| f(db.get_or_init(|| BoneActionDatabase::new())))
+    match SCOPED_BONE_DB.with(|scoped| scoped.borrow().clone()) {
+        Some(db) => f(&db),
+        None => BONE_DB.with(|db| f(db.get_or_init(BoneActionDatabase::new))),
+    }
+}
+
+/// Make `with_bone_db` use `db` on this thread while `f` runs
+pub fn using_bone_db<T>(db: &Arc<BoneActionDatabase>, f: impl FnOnce() -> T) -> T {
+    // Restore the outer database even if `f` panics
+    struct Restore(Option<Arc<BoneActionDatabase>>);
+    impl Drop for Restore {
+        fn drop(&mut self) {
+            SCOPED_BONE_DB.with(|scoped| *scoped.borrow_mut() = self.0.take());
+        }
+    }
+
+    let _restore = Restore(SCOPED_BONE_DB.with(|scoped| scoped.replace(Some(db.clone()))));
+    f()
+}
+
+/// English name of a bone, or the Japanese name for bones MPL does not know
+pub(crate) fn english_name(name_jp: &str) -> String {
+    with_bone_db(|db| db.english_name(name_jp).map(str::to_string))
+        .unwrap_or_else(|| name_jp.to_string())
+}
+
+/// English name of a morph, or the Japanese name for morphs MPL does not know
+pub(crate) fn morph_english_name(name_jp: &str) -> String {
+    with_bone_db(|db| db.morph_english_name(name_jp).map(str::to_string))
+        .unwrap_or_else(|| name_jp.to_string())
 }
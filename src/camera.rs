@@ -0,0 +1,87 @@
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    animation::track_duration,
+    interpolation::{BezierCurve, CameraInterpolation},
+    mpl::MPLCameraFrame,
+    utils::Vector3,
+};
+
+/// Name MMD gives camera and light motions, which belong to no model
+pub const CAMERA_MODEL_NAME: &str = "カメラ・照明";
+
+/// One `@camera` keyframe. Properties left out keep their previous value.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct MPLCameraStatement {
+    pub time: f32,
+    pub distance: Option<f32>,
+    pub target: Option<Vector3>,
+    /// Degrees around X, Y and Z
+    pub rotation: Option<Vector3>,
+    pub fov: Option<u32>,
+    pub perspective: Option<bool>,
+    /// Curve of the transition into this keyframe, on every channel
+    #[serde(default)]
+    pub easing: BezierCurve,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MPLCamera {
+    pub name: String,
+    pub statements: Vec<MPLCameraStatement>,
+}
+
+impl MPLCamera {
+    pub fn new(name: String, statements: Vec<MPLCameraStatement>) -> Self {
+        Self { name, statements }
+    }
+
+    /// Same rule as `MPLAnimation::duration`
+    pub fn duration(&self) -> f32 {
+        track_duration(self.statements.iter().map(|s| s.time).collect())
+    }
+
+    /// Frames relative to the start of the block, starting from MMD's default camera
+    pub fn to_camera_frames(&self) -> Vec<MPLCameraFrame> {
+        let mut statements: Vec<&MPLCameraStatement> = self.statements.iter().collect();
+        statements.sort_by(|a, b| a.time.total_cmp(&b.time));
+
+        let mut frame = MPLCameraFrame {
+            time: 0.0,
+            distance: -45.0,
+            target: Vector3::new(0.0, 10.0, 0.0),
+            rotation: Vector3::new(0.0, 0.0, 0.0),
+            interpolation: CameraInterpolation::default(),
+            fov: 30,
+            perspective: true,
+        };
+
+        let mut frames = vec![];
+        for statement in statements {
+            frame.time = statement.time;
+            // VMD stores the distance in front of the target as a negative value
+            if let Some(distance) = statement.distance {
+                frame.distance = -distance;
+            }
+            if let Some(target) = statement.target {
+                frame.target = target;
+            }
+            if let Some(rotation) = statement.rotation {
+                frame.rotation = Vector3::new(
+                    rotation.x.to_radians(),
+                    rotation.y.to_radians(),
+                    rotation.z.to_radians(),
+                );
+            }
+            if let Some(fov) = statement.fov {
+                frame.fov = fov;
+            }
+            if let Some(perspective) = statement.perspective {
+                frame.perspective = perspective;
+            }
+            frame.interpolation = CameraInterpolation::uniform(statement.easing);
+            frames.push(frame.clone());
+        }
+        frames
+    }
+}
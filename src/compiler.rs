@@ -1,326 +1,2406 @@
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Arc;
 
-use crate::{animation::MPLAnimation, mpl::MPLKeyFrame, pose::MPLPose};
+use crate::{
+    animation::{
+        track_duration, MPLAnimation, MPLAnimationStatement, MPLMainEntry, MPLRepeat, MAX_REPEATS,
+        POSE_DURATION,
+    },
+    ast::{self, Block, MPLScript},
+    camera::{MPLCamera, MPLCameraStatement},
+    diagnostic::{codes, similar_names, Diagnostic, Severity},
+    distance_in_units,
+    interpolation::{BezierCurve, BoneInterpolation},
+    layer::add_layer,
+    lexer::{Lexer, Span},
+    lighting::{MPLLight, MPLLightStatement, MPLShadow, MPLShadowStatement},
+    look::LOOK_WEIGHTS,
+    mpl::{
+        MPLBoneFrame, MPLCameraFrame, MPLIkFrame, MPLIkState, MPLKeyFrame, MPLLightFrame,
+        MPLMorphFrame, MPLShadowFrame,
+    },
+    parser::Parser,
+    pose::{
+        MPLIkStatement, MPLLookStatement, MPLMorphStatement, MPLPose, MPLPoseStatement,
+        MPLReachStatement, MPLReachTarget,
+    },
+    reach::{limbs, REACH_TOLERANCE},
+    using_bone_db,
+    utils::{Quaternion, Vector3},
+    vmd::{check_fps, FrameConflict, VMDMotion, DEFAULT_FPS},
+    with_bone_db, BoneActionDatabase, RuleViolation, MOVE_ACTION,
+};
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct MPLScript {
+/// Arguments of one call to a parameterized pose, by parameter name
+type Bindings<'a> = HashMap<&'a str, &'a ast::Arg>;
+
+/// Resolved poses, animations, camera and lighting tracks and main sequence of a script
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct MPLProgram {
     pub poses: HashMap<String, MPLPose>,
+    /// Parameterized poses; each distinct call is resolved into `poses`
+    #[serde(default)]
+    pub templates: HashMap<String, ast::PoseBlock>,
     pub animations: HashMap<String, MPLAnimation>,
-    pub main: Vec<String>,
+    #[serde(default)]
+    pub cameras: HashMap<String, MPLCamera>,
+    #[serde(default)]
+    pub lights: HashMap<String, MPLLight>,
+    #[serde(default)]
+    pub shadows: HashMap<String, MPLShadow>,
+    pub main: Vec<MPLMainEntry>,
+    /// Rules the program was checked against; keyframes are built with them too
+    #[serde(skip)]
+    bone_db: Option<Arc<BoneActionDatabase>>,
 }
 
-impl MPLScript {
+impl MPLProgram {
     pub fn new() -> Self {
-        Self {
-            poses: HashMap::new(),
-            animations: HashMap::new(),
-            main: vec![],
+        Self::default()
+    }
+
+    /// Build keyframes against `db` instead of the built-in database
+    pub fn with_bone_database(mut self, db: Arc<BoneActionDatabase>) -> Self {
+        self.bone_db = Some(db);
+        self
+    }
+
+    /// Run `f` against the program's bone database, if it carries one
+    fn scope<T>(&self, f: impl FnOnce() -> T) -> T {
+        match &self.bone_db {
+            Some(db) => using_bone_db(db, f),
+            None => f(),
         }
     }
+
+    /// What kind of block defines `name`, if any
+    pub fn kind_of(&self, name: &str) -> Option<&'static str> {
+        if self.poses.contains_key(name) || self.templates.contains_key(name) {
+            Some("pose")
+        } else if self.animations.contains_key(name) {
+            Some("animation")
+        } else if self.cameras.contains_key(name) {
+            Some("camera")
+        } else if self.lights.contains_key(name) {
+            Some("light")
+        } else if self.shadows.contains_key(name) {
+            Some("shadow")
+        } else {
+            None
+        }
+    }
+
+    /// Every name main can refer to
+    fn names(&self) -> impl Iterator<Item = &str> {
+        self.animations
+            .keys()
+            .map(|k| k.as_str())
+            .chain(self.pose_names())
+            .chain(
+                self.cameras
+                    .keys()
+                    .chain(self.lights.keys())
+                    .chain(self.shadows.keys())
+                    .map(|k| k.as_str()),
+            )
+    }
+
+    /// Poses as written in the script, without the instances of parameterized ones
+    fn pose_names(&self) -> impl Iterator<Item = &str> {
+        self.poses
+            .keys()
+            .filter(|k| !k.contains('('))
+            .chain(self.templates.keys())
+            .map(|k| k.as_str())
+    }
+
+    /// Lay the main block out on one timeline: statements play one after another,
+    /// `a & b` start together, `at t:` starts at an absolute time and `x 4` or
+    /// `loop until t` play a statement back to back. Returns every play of a statement
+    /// with its index in main, its start time and the time its keyframes must stay before.
+    fn timeline(&self) -> Vec<(usize, f32, f32, &MPLMainEntry)> {
+        let mut timeline = vec![];
+        let mut cursor = 0.0;
+
+        for (index, entry) in self.main.iter().enumerate() {
+            let cycle = self.cycle(&entry.names, &entry.layers);
+            let (starts, end, next) = entry.layout(cursor, cycle);
+            cursor = next;
+            timeline.extend(starts.into_iter().map(|start| (index, start, end, entry)));
+        }
+
+        timeline
+    }
+
+    /// Each entry name of the timeline with its start and end time; layers only change
+    /// bone and morph keyframes and are left out
+    fn schedule(&self) -> Vec<(usize, &str, f32, f32)> {
+        self.timeline()
+            .into_iter()
+            .flat_map(|(index, start, end, entry)| {
+                entry
+                    .names
+                    .iter()
+                    .map(move |name| (index, name.as_str(), start, end))
+            })
+            .collect()
+    }
+
+    /// Frames of the tracks `frames` finds for each scheduled name, moved to the
+    /// name's start and tagged with the index of the main statement playing them
+    fn place<T>(
+        &self,
+        frames: impl Fn(&str) -> Option<Vec<T>>,
+        time: fn(&mut T) -> &mut f32,
+    ) -> Vec<(usize, T)> {
+        let mut placed = vec![];
+        for (index, name, start, end) in self.schedule() {
+            for mut frame in frames(name).unwrap_or_default() {
+                *time(&mut frame) += start;
+                if *time(&mut frame) < end {
+                    placed.push((index, frame));
+                }
+            }
+        }
+        placed
+    }
+
+    /// How long one play of entries started together lasts
+    fn cycle(&self, names: &[String], layers: &[String]) -> f32 {
+        names
+            .iter()
+            .chain(layers)
+            .map(|name| self.duration(name))
+            .fold(0.0, f32::max)
+    }
+
+    fn duration(&self, name: &str) -> f32 {
+        if let Some(anim) = self.animations.get(name) {
+            anim.duration()
+        } else if self.poses.contains_key(name) {
+            POSE_DURATION
+        } else if let Some(camera) = self.cameras.get(name) {
+            camera.duration()
+        } else if let Some(light) = self.lights.get(name) {
+            light.duration()
+        } else if let Some(shadow) = self.shadows.get(name) {
+            shadow.duration()
+        } else {
+            0.0
+        }
+    }
+
     pub fn to_key_frames(&self) -> Vec<MPLKeyFrame> {
+        self.scope(|| self.build_key_frames())
+    }
+
+    fn build_key_frames(&self) -> Vec<MPLKeyFrame> {
+        let key_frames = self.placed_key_frames();
+        merge_key_frames(key_frames.into_iter().map(|(_, k)| k).collect())
+    }
+
+    /// Keyframes of every play on the timeline before merging, tagged with the index
+    /// of the main statement playing them
+    fn placed_key_frames(&self) -> Vec<(usize, MPLKeyFrame)> {
         let mut key_frames = vec![];
-        for name in &self.main {
-            if self.animations.contains_key(name) {
-                let anim = self.animations.get(name).unwrap();
+        for (index, start, end, entry) in self.timeline() {
+            let mut clip: Vec<MPLKeyFrame> = entry
+                .names
+                .iter()
+                .flat_map(|name| self.clip(name))
+                .collect();
+            for layer in &entry.layers {
+                clip = add_layer(&clip, &self.clip(layer));
+            }
+
+            key_frames.extend(
+                clip.into_iter()
+                    .map(|mut key_frame| {
+                        key_frame.time += start;
+                        (index, key_frame)
+                    })
+                    .filter(|(_, key_frame)| key_frame.time < end),
+            );
+        }
+        key_frames
+    }
+
+    pub fn to_camera_frames(&self) -> Vec<MPLCameraFrame> {
+        let mut frames: Vec<MPLCameraFrame> = self
+            .place(
+                |name| {
+                    self.cameras
+                        .get(name)
+                        .map(|camera| camera.to_camera_frames())
+                },
+                |f| &mut f.time,
+            )
+            .into_iter()
+            .map(|(_, frame)| frame)
+            .collect();
+        frames.sort_by(|a, b| a.time.total_cmp(&b.time));
+        frames
+    }
+
+    pub fn to_light_frames(&self) -> Vec<MPLLightFrame> {
+        let mut frames: Vec<MPLLightFrame> = self
+            .place(
+                |name| self.lights.get(name).map(|light| light.to_light_frames()),
+                |f| &mut f.time,
+            )
+            .into_iter()
+            .map(|(_, frame)| frame)
+            .collect();
+        frames.sort_by(|a, b| a.time.total_cmp(&b.time));
+        frames
+    }
+
+    pub fn to_shadow_frames(&self) -> Vec<MPLShadowFrame> {
+        let mut frames: Vec<MPLShadowFrame> = self
+            .place(
+                |name| {
+                    self.shadows
+                        .get(name)
+                        .map(|shadow| shadow.to_shadow_frames())
+                },
+                |f| &mut f.time,
+            )
+            .into_iter()
+            .map(|(_, frame)| frame)
+            .collect();
+        frames.sort_by(|a, b| a.time.total_cmp(&b.time));
+        frames
+    }
+
+    /// Model keyframes together with the camera and lighting work, ready for `VMDWriter`
+    pub fn to_motion(&self) -> VMDMotion {
+        VMDMotion {
+            model_name: String::new(),
+            fps: DEFAULT_FPS,
+            key_frames: self.to_key_frames(),
+            camera_frames: self.to_camera_frames(),
+            light_frames: self.to_light_frames(),
+            shadow_frames: self.to_shadow_frames(),
+            ik_frames: self.to_ik_frames(),
+        }
+    }
+
+    /// Show/IK frames wherever a keyframe plays a pose with `ik` or `show` statements.
+    /// Every frame lists all IK chains so earlier switches carry over.
+    pub fn to_ik_frames(&self) -> Vec<MPLIkFrame> {
+        self.scope(|| self.build_ik_frames())
+    }
+
+    fn build_ik_frames(&self) -> Vec<MPLIkFrame> {
+        let mut switches: Vec<(f32, MPLPose)> = vec![];
+        for (_, name, start, end) in self.schedule() {
+            if let Some(anim) = self.animations.get(name) {
                 for statement in &anim.statements {
-                    let mut bone_frames = vec![];
-                    if statement.poses.len() == 1 {
-                        let pose_name = statement.poses[0].clone();
-                        let pose = self.poses.get(&pose_name).unwrap();
-                        bone_frames.extend(pose.to_bone_frames());
-                    } else {
-                        let mut pose_statements = vec![];
-                        for pose_name in &statement.poses {
-                            let pose = self.poses.get(pose_name).unwrap();
-                            pose_statements.extend(pose.statements.clone());
-                        }
-                        let pose = MPLPose::new("composite".to_string(), pose_statements);
-                        bone_frames.extend(pose.to_bone_frames());
+                    if start + statement.time < end {
+                        switches.push((start + statement.time, self.composite(statement)));
                     }
-                    key_frames.push(MPLKeyFrame::new(statement.time, bone_frames, vec![]));
                 }
-            } else if self.poses.contains_key(name) {
-                let pose = self.poses.get(name).unwrap();
-                key_frames.push(MPLKeyFrame::new(0.0, pose.to_bone_frames(), vec![]));
+            } else if let Some(pose) = self.poses.get(name) {
+                switches.push((start, pose.clone()));
             }
         }
-        key_frames
+        switches.retain(|(_, pose)| !pose.ik.is_empty() || pose.show.is_some());
+        switches.sort_by(|a, b| a.0.total_cmp(&b.0));
+
+        let mut show = true;
+        let mut ik_states: Vec<MPLIkState> = with_bone_db(|db| {
+            db.iks()
+                .iter()
+                .map(|ik| MPLIkState {
+                    name_en: ik.clone(),
+                    name_jp: db.ik_japanese_name(ik).unwrap_or(ik).to_string(),
+                    enabled: true,
+                })
+                .collect()
+        });
+
+        let mut frames: Vec<MPLIkFrame> = vec![];
+        for (time, pose) in switches {
+            show = pose.show.unwrap_or(show);
+            for ik in &pose.ik {
+                if let Some(state) = ik_states.iter_mut().find(|s| s.name_en == ik.ik) {
+                    state.enabled = ik.enabled;
+                }
+            }
+            let frame = MPLIkFrame {
+                time,
+                show,
+                ik_states: ik_states.clone(),
+            };
+            match frames.last_mut() {
+                Some(last) if (last.time - time).abs() < f32::EPSILON => *last = frame,
+                _ => frames.push(frame),
+            }
+        }
+        frames
+    }
+
+    /// Keyframes of one animation or pose relative to its start
+    fn clip(&self, name: &str) -> Vec<MPLKeyFrame> {
+        if let Some(anim) = self.animations.get(name) {
+            anim.statements
+                .iter()
+                .map(|statement| {
+                    let interpolation = BoneInterpolation::uniform(statement.easing);
+                    let (bone_frames, morph_frames) = if statement.weights.is_empty() {
+                        let pose = self.composite(statement);
+                        (pose.to_bone_frames(), pose.to_morph_frames())
+                    } else {
+                        self.blend(statement)
+                    };
+                    let bone_frames = bone_frames
+                        .into_iter()
+                        .map(|frame| frame.with_interpolation(interpolation))
+                        .collect();
+                    MPLKeyFrame::new(statement.time, bone_frames, morph_frames)
+                })
+                .collect()
+        } else if let Some(pose) = self.poses.get(name) {
+            vec![MPLKeyFrame::new(
+                0.0,
+                pose.to_bone_frames(),
+                pose.to_morph_frames(),
+            )]
+        } else {
+            vec![]
+        }
+    }
+
+    /// Weighted per-bone blend of the poses of a keyframe. A bone or morph a pose leaves
+    /// out counts as at rest for that pose, and weight left below 1 goes to rest too.
+    fn blend(&self, statement: &MPLAnimationStatement) -> (Vec<MPLBoneFrame>, Vec<MPLMorphFrame>) {
+        struct Blend {
+            name_jp: String,
+            rotations: Vec<(Quaternion, f32)>,
+            position: Vector3,
+        }
+
+        let mut bones: BTreeMap<String, Blend> = BTreeMap::new();
+        let mut morphs: BTreeMap<String, MPLMorphFrame> = BTreeMap::new();
+        for (name, &weight) in statement.poses.iter().zip(&statement.weights) {
+            let Some(pose) = self.poses.get(name) else {
+                continue;
+            };
+            for frame in pose.to_bone_frames() {
+                let blend = bones.entry(frame.name_en()).or_insert_with(|| Blend {
+                    name_jp: frame.name_jp(),
+                    rotations: vec![],
+                    position: Vector3::new(0.0, 0.0, 0.0),
+                });
+                let position = frame.position();
+                blend.rotations.push((frame.rotation(), weight));
+                blend.position = Vector3::new(
+                    blend.position.x + position.x * weight,
+                    blend.position.y + position.y * weight,
+                    blend.position.z + position.z * weight,
+                );
+            }
+            for frame in pose.to_morph_frames() {
+                morphs
+                    .entry(frame.name_en.clone())
+                    .or_insert_with(|| MPLMorphFrame {
+                        weight: 0.0,
+                        ..frame.clone()
+                    })
+                    .weight += frame.weight * weight;
+            }
+        }
+
+        let bone_frames = bones
+            .into_iter()
+            .map(|(name_en, mut blend)| {
+                let posed: f32 = blend.rotations.iter().map(|(_, w)| w).sum();
+                blend
+                    .rotations
+                    .push((Quaternion::identity(), (1.0 - posed).max(0.0)));
+                MPLBoneFrame::new(
+                    name_en,
+                    blend.name_jp,
+                    blend.position,
+                    Quaternion::blend(&blend.rotations),
+                )
+            })
+            .collect();
+        (bone_frames, morphs.into_values().collect())
+    }
+
+    /// The poses of a keyframe combined into one
+    fn composite(&self, statement: &MPLAnimationStatement) -> MPLPose {
+        if statement.poses.len() == 1 {
+            if let Some(pose) = self.poses.get(&statement.poses[0]) {
+                return pose.clone();
+            }
+        }
+
+        let mut pose_statements = vec![];
+        let mut morphs = vec![];
+        let mut ik = vec![];
+        let mut look = None;
+        let mut reaches = vec![];
+        let mut show = None;
+        for pose_name in &statement.poses {
+            if let Some(pose) = self.poses.get(pose_name) {
+                pose_statements.extend(pose.statements.clone());
+                morphs.extend(pose.morphs.clone());
+                ik.extend(pose.ik.clone());
+                look = pose.look.clone().or(look);
+                reaches.extend(pose.reaches.clone());
+                show = pose.show.or(show);
+            }
+        }
+        MPLPose::new("composite".to_string(), pose_statements)
+            .with_morphs(morphs)
+            .with_ik(ik, show)
+            .with_look(look)
+            .with_reaches(reaches)
+    }
+}
+
+/// Sort keyframes by time and combine the ones that land on the same instant
+fn merge_key_frames(mut key_frames: Vec<MPLKeyFrame>) -> Vec<MPLKeyFrame> {
+    key_frames.sort_by(|a, b| a.time.total_cmp(&b.time));
+
+    let mut merged: Vec<MPLKeyFrame> = vec![];
+    for key_frame in key_frames {
+        match merged.last_mut() {
+            Some(last) if (last.time - key_frame.time).abs() < f32::EPSILON => {
+                last.bone_frames.extend(key_frame.bone_frames);
+                last.morph_frames.extend(key_frame.morph_frames);
+            }
+            _ => merged.push(key_frame),
+        }
+    }
+    merged
+}
+
+/// Everything learned from one pass over a script: the syntax tree that parsed, the
+/// program resolved from it and every diagnostic found along the way
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MPLCompilation {
+    pub script: MPLScript,
+    pub program: MPLProgram,
+    pub diagnostics: Vec<Diagnostic>,
+}
+
+impl MPLCompilation {
+    pub fn has_errors(&self) -> bool {
+        self.diagnostics
+            .iter()
+            .any(|d| d.severity == Severity::Error)
     }
 }
 
-enum BlockType {
-    None,
-    Pose,
-    Animation,
-    Main,
+#[derive(Clone)]
+pub struct MPLCompiler {
+    /// Rules to compile against instead of the built-in database
+    bone_db: Option<Arc<BoneActionDatabase>>,
+    /// Frame rate the motion is written at, which keyframes must be a frame apart in
+    fps: f32,
 }
 
-pub struct MPLCompiler {}
+impl Default for MPLCompiler {
+    fn default() -> Self {
+        Self::new()
+    }
+}
 
 impl MPLCompiler {
     pub fn new() -> Self {
-        Self {}
+        Self {
+            bone_db: None,
+            fps: DEFAULT_FPS,
+        }
+    }
+
+    /// Validate and build poses against `db`, e.g. one loaded from a `RigProfile`
+    pub fn with_bone_database(mut self, db: BoneActionDatabase) -> Self {
+        self.bone_db = Some(Arc::new(db));
+        self
     }
 
-    pub fn compile(&self, text: &str) -> Result<Vec<MPLKeyFrame>, String> {
-        let mut in_block = false;
-        let mut brace_count = 0;
-        let mut current_block = String::new();
-        let mut script = MPLScript::new();
-        let mut block_type = BlockType::None;
+    /// Check that keyframes are a frame apart at `fps` instead of 30, the rate the
+    /// motion will be written at
+    pub fn with_fps(mut self, fps: f32) -> Self {
+        self.fps = fps;
+        self
+    }
+
+    /// Run `f` against this compiler's bone database, for work on poses and
+    /// statements outside the compiler. A checked `MPLProgram` carries the database.
+    pub fn scope<T>(&self, f: impl FnOnce() -> T) -> T {
+        match &self.bone_db {
+            Some(db) => using_bone_db(db, f),
+            None => f(),
+        }
+    }
+
+    /// Parse a script into its syntax tree without validating it
+    pub fn parse(&self, text: &str) -> (MPLScript, Vec<Diagnostic>) {
+        let (tokens, mut diagnostics) = Lexer::new(text).tokenize();
+        let mut parser = Parser::new(tokens);
+        let script = parser.parse_script();
+        diagnostics.extend(parser.into_diagnostics());
+        (script, diagnostics)
+    }
+
+    /// Parse and validate a script, collecting every problem instead of stopping at the first
+    pub fn check(&self, text: &str) -> MPLCompilation {
+        let (script, mut diagnostics) = self.parse(text);
+        let program = self.scope(|| self.analyze(&script, &mut diagnostics));
+        diagnostics.sort_by_key(|d| d.span.start.offset);
+
+        MPLCompilation {
+            script,
+            program,
+            diagnostics,
+        }
+    }
 
-        for (line_number, line) in text.lines().enumerate() {
-            let trimmed = line.trim();
-            if trimmed.is_empty() {
+    pub fn compile(&self, text: &str) -> Result<Vec<MPLKeyFrame>, Vec<Diagnostic>> {
+        let compilation = self.check(text);
+        if compilation.has_errors() {
+            return Err(compilation.diagnostics);
+        }
+        Ok(compilation.program.to_key_frames())
+    }
+
+    /// Compile every track of a script: model keyframes, camera work and lighting
+    pub fn compile_motion(&self, text: &str) -> Result<VMDMotion, Vec<Diagnostic>> {
+        let compilation = self.check(text);
+        if compilation.has_errors() {
+            return Err(compilation.diagnostics);
+        }
+        Ok(VMDMotion {
+            fps: self.fps,
+            ..compilation.program.to_motion()
+        })
+    }
+
+    /// Resolve and validate a parsed script. Invalid statements and references are
+    /// reported and left out of the returned program.
+    pub fn analyze(&self, script: &MPLScript, diagnostics: &mut Vec<Diagnostic>) -> MPLProgram {
+        let mut program = MPLProgram {
+            bone_db: self.bone_db.clone(),
+            ..MPLProgram::new()
+        };
+        let mut main_block: Option<&ast::MainBlock> = None;
+        let mut main_spans = vec![];
+
+        for block in &script.blocks {
+            match block {
+                Block::Pose(block) if !block.params.is_empty() => {
+                    Self::check_template(block, diagnostics);
+                    if Self::check_name(&program, &block.name, "pose", diagnostics) {
+                        program
+                            .templates
+                            .insert(block.name.name.clone(), block.clone());
+                    }
+                }
+                Block::Pose(block) => {
+                    let pose = self.parse_pose(block, &Bindings::new(), diagnostics);
+                    if Self::check_name(&program, &block.name, "pose", diagnostics) {
+                        program.poses.insert(pose.name.clone(), pose);
+                    }
+                }
+                Block::Mirror(block) => {
+                    let pose = self.mirror_pose(block, &program, diagnostics);
+                    if let Some(pose) = pose {
+                        if Self::check_name(&program, &block.name, "pose", diagnostics) {
+                            program.poses.insert(pose.name.clone(), pose);
+                        }
+                    }
+                }
+                Block::Animation(block) => {
+                    let animation = self.parse_animation(block, &mut program, diagnostics);
+                    if Self::check_name(&program, &block.name, "animation", diagnostics) {
+                        program.animations.insert(animation.name.clone(), animation);
+                    }
+                }
+                Block::Camera(block) => {
+                    let camera = self.parse_camera(block, diagnostics);
+                    if Self::check_name(&program, &block.name, "camera", diagnostics) {
+                        program.cameras.insert(camera.name.clone(), camera);
+                    }
+                }
+                Block::Light(block) => {
+                    let light = self.parse_light(block, diagnostics);
+                    if Self::check_name(&program, &block.name, "light", diagnostics) {
+                        program.lights.insert(light.name.clone(), light);
+                    }
+                }
+                Block::Shadow(block) => {
+                    let shadow = self.parse_shadow(block, diagnostics);
+                    if Self::check_name(&program, &block.name, "shadow", diagnostics) {
+                        program.shadows.insert(shadow.name.clone(), shadow);
+                    }
+                }
+                Block::Main(block) => {
+                    if main_block.is_some() {
+                        diagnostics.push(
+                            Diagnostic::error(
+                                codes::DUPLICATE_MAIN,
+                                "Duplicate main block",
+                                block.span,
+                            )
+                            .with_suggestion("merge the entries into a single main block"),
+                        );
+                        continue;
+                    }
+                    main_block = Some(block);
+                    (program.main, main_spans) = self
+                        .parse_main(block, &program, diagnostics)
+                        .into_iter()
+                        .unzip();
+                }
+            }
+        }
+
+        if main_block.is_none() && !script.blocks.is_empty() {
+            diagnostics.push(
+                Diagnostic::warning(
+                    codes::MISSING_MAIN,
+                    "Script has no main block, nothing will be played",
+                    script.span,
+                )
+                .with_suggestion("add 'main { ...; }' listing the animations to play"),
+            );
+        }
+
+        self.check_conflicts(&program, &main_spans, diagnostics);
+        program
+    }
+
+    /// Tracks keyed twice on one frame at the compiler's frame rate, reported on the
+    /// latest main statement involved
+    fn check_conflicts(
+        &self,
+        program: &MPLProgram,
+        spans: &[Span],
+        diagnostics: &mut Vec<Diagnostic>,
+    ) {
+        // An unusable frame rate is reported when the motion is written
+        if check_fps(self.fps).is_err() {
+            return;
+        }
+        let frame = |time: f32| (time * self.fps).round().max(0.0) as u32;
+
+        let mut keys: BTreeMap<(String, u32), Vec<(f32, usize)>> = BTreeMap::new();
+        for (index, key_frame) in program.scope(|| program.placed_key_frames()) {
+            let names = key_frame
+                .bone_frames
+                .iter()
+                .map(|b| b.name_en())
+                .chain(key_frame.morph_frames.iter().map(|m| m.name_en.clone()));
+            for name in names {
+                keys.entry((name, frame(key_frame.time)))
+                    .or_default()
+                    .push((key_frame.time, index));
+            }
+        }
+        let tracks = [
+            (
+                "camera",
+                program
+                    .place(
+                        |name| program.cameras.get(name).map(|c| c.to_camera_frames()),
+                        |f| &mut f.time,
+                    )
+                    .into_iter()
+                    .map(|(index, f)| (index, f.time))
+                    .collect::<Vec<_>>(),
+            ),
+            (
+                "light",
+                program
+                    .place(
+                        |name| program.lights.get(name).map(|l| l.to_light_frames()),
+                        |f| &mut f.time,
+                    )
+                    .into_iter()
+                    .map(|(index, f)| (index, f.time))
+                    .collect(),
+            ),
+            (
+                "shadow",
+                program
+                    .place(
+                        |name| program.shadows.get(name).map(|s| s.to_shadow_frames()),
+                        |f| &mut f.time,
+                    )
+                    .into_iter()
+                    .map(|(index, f)| (index, f.time))
+                    .collect(),
+            ),
+        ];
+        for (name, times) in tracks {
+            for (index, time) in times {
+                keys.entry((name.to_string(), frame(time)))
+                    .or_default()
+                    .push((time, index));
+            }
+        }
+
+        for ((name, frame), plays) in keys {
+            if plays.len() < 2 {
                 continue;
             }
-            if trimmed.starts_with("@pose")
-                || trimmed.starts_with("@animation")
-                || trimmed.starts_with("main")
-            {
-                if in_block {
-                    return Err(format!(
-                        "Line {}: Nested block is not allowed",
-                        line_number + 1
-                    ));
+            let latest = plays.iter().map(|(_, index)| *index).max().unwrap_or(0);
+            let conflict = FrameConflict {
+                name,
+                frame,
+                times: plays.iter().map(|(time, _)| *time).collect(),
+            };
+            diagnostics.push(
+                Diagnostic::error(
+                    codes::FRAME_CONFLICT,
+                    format!("Conflicting keyframes: {}", conflict),
+                    spans[latest],
+                )
+                .with_suggestion("space the keyframes at least one frame apart"),
+            );
+        }
+    }
+
+    /// Names are shared by every kind of block; the first definition wins
+    fn check_name(
+        program: &MPLProgram,
+        name: &ast::Ident,
+        kind: &str,
+        diagnostics: &mut Vec<Diagnostic>,
+    ) -> bool {
+        let message = match program.kind_of(&name.name) {
+            None => return true,
+            Some(existing) if existing == kind => {
+                format!("Duplicate {} name: '{}'", kind, name.name)
+            }
+            Some(existing) => {
+                let article = if existing.starts_with('a') { "an" } else { "a" };
+                format!(
+                    "Name '{}' already used by {} {}",
+                    name.name, article, existing
+                )
+            }
+        };
+        diagnostics.push(Diagnostic::error(codes::DUPLICATE_NAME, message, name.span));
+        false
+    }
+
+    /// Resolve a pose, with `args` filling in the `$params` of a parameterized one
+    fn parse_pose(
+        &self,
+        block: &ast::PoseBlock,
+        args: &Bindings,
+        diagnostics: &mut Vec<Diagnostic>,
+    ) -> MPLPose {
+        let mut statements = Vec::new();
+        let mut morphs = Vec::new();
+        let mut ik = Vec::new();
+        let mut reaches = Vec::new();
+        let mut reach_spans = Vec::new();
+        let mut look = None;
+        let mut show = None;
+
+        for statement in &block.statements {
+            match statement {
+                ast::PoseStatement::Bone(statement) => {
+                    match Self::check_bone_statement(statement, args) {
+                        Ok(statement) => statements.push(statement),
+                        Err(diagnostic) => diagnostics.push(diagnostic),
+                    }
+                }
+                ast::PoseStatement::Morph(statement) => {
+                    match Self::check_morph_statement(statement, args) {
+                        Ok(morph) => morphs.push(morph),
+                        Err(diagnostic) => diagnostics.push(diagnostic),
+                    }
+                }
+                ast::PoseStatement::Ik(statement) => match Self::check_ik_statement(statement) {
+                    Ok(statement) => ik.push(statement),
+                    Err(diagnostic) => diagnostics.push(diagnostic),
+                },
+                ast::PoseStatement::Show(statement) => match Self::switch(&statement.state) {
+                    Ok(state) => show = Some(state),
+                    Err(diagnostic) => diagnostics.push(diagnostic),
+                },
+                ast::PoseStatement::Reach(statement) => {
+                    match Self::check_reach_statement(statement) {
+                        Ok(reach) => {
+                            reaches.push(reach);
+                            reach_spans.push(statement.span);
+                        }
+                        Err(diagnostic) => diagnostics.push(diagnostic),
+                    }
                 }
-                in_block = true;
-                match trimmed.split_whitespace().next().unwrap() {
-                    "@pose" => block_type = BlockType::Pose,
-                    "@animation" => block_type = BlockType::Animation,
-                    "main" => block_type = BlockType::Main,
-                    _ => return Err(format!("Line {}: Invalid block type", line_number + 1)),
+                ast::PoseStatement::Look(statement) => {
+                    match Self::check_look_statement(statement, args) {
+                        Ok(checked) => look = Some((checked, statement.span)),
+                        Err(diagnostic) => diagnostics.push(diagnostic),
+                    }
                 }
             }
+        }
 
-            if !in_block {
-                return Err(format!(
-                    "Line {}: Invalid text outside of block",
-                    line_number + 1
-                ));
+        if block.statements.is_empty() {
+            diagnostics.push(Diagnostic::error(
+                codes::EMPTY_BLOCK,
+                "Pose must contain at least one statement",
+                block.span,
+            ));
+        }
+
+        let pose = MPLPose::new(block.name.name.clone(), statements)
+            .with_morphs(morphs)
+            .with_ik(ik, show)
+            .with_look(look.as_ref().map(|(look, _)| look.clone()))
+            .with_reaches(reaches);
+        if let (Some((_, span)), Some(e)) = (look, pose.look_error()) {
+            diagnostics.push(Diagnostic::error(codes::INVALID_LOOK, e, span));
+        }
+        for ((reach, span), miss) in pose
+            .reaches
+            .iter()
+            .zip(reach_spans)
+            .zip(pose.reach_misses())
+        {
+            match miss {
+                Err(e) => diagnostics.push(Diagnostic::error(codes::INVALID_REACH, e, span)),
+                Ok(miss) if miss > REACH_TOLERANCE => diagnostics.push(
+                    Diagnostic::warning(
+                        codes::UNREACHABLE_TARGET,
+                        format!(
+                            "{} cannot reach its target, it stops {:.2} units short",
+                            reach.limb, miss
+                        ),
+                        span,
+                    )
+                    .with_suggestion("move the target closer or within the bone limits"),
+                ),
+                Ok(_) => {}
             }
-            current_block.push_str(line);
-            current_block.push('\n');
+        }
+        pose
+    }
+
+    fn check_reach_statement(
+        statement: &ast::ReachStatement,
+    ) -> Result<MPLReachStatement, Diagnostic> {
+        let limb = &statement.limb;
+        let plant = match statement.verb.name.as_str() {
+            "reach" => false,
+            "plant" => true,
+            verb => {
+                return Err(Diagnostic::error(
+                    codes::INVALID_REACH,
+                    format!("Expected 'reach' or 'plant' but found '{}'", verb),
+                    statement.verb.span,
+                ))
+            }
+        };
+
+        let target = match &statement.target {
+            ast::ReachTarget::Named(name) if name.name == "ground" => MPLReachTarget::Ground,
+            ast::ReachTarget::Named(name) => {
+                return Err(Diagnostic::error(
+                    codes::INVALID_REACH,
+                    format!("Unknown target '{}'", name.name),
+                    name.span,
+                )
+                .with_suggestion("use a point like '(4, 14, -3)' or 'ground'"))
+            }
+            ast::ReachTarget::Point { values, span, .. } if values.len() != 3 => {
+                return Err(Diagnostic::error(
+                    codes::INVALID_REACH,
+                    format!("Target needs 3 coordinates, found {}", values.len()),
+                    *span,
+                ))
+            }
+            ast::ReachTarget::Point { values, unit, .. } => {
+                // Coordinates may be given in cm or m like `move` distances
+                let scale = match unit {
+                    None => 1.0,
+                    Some(unit) => distance_in_units(1.0, &unit.name).ok_or_else(|| {
+                        Diagnostic::error(
+                            codes::INVALID_UNIT,
+                            format!("Unknown unit '{}'", unit.name),
+                            unit.span,
+                        )
+                        .with_suggestion("use 'cm', 'm' or no unit for MMD units")
+                    })?,
+                };
+                MPLReachTarget::Point {
+                    position: Vector3::new(
+                        values[0].value * scale,
+                        values[1].value * scale,
+                        values[2].value * scale,
+                    ),
+                }
+            }
+        };
+
+        MPLReachStatement::new(&limb.name, target, plant).map_err(|e| {
+            let known = limbs();
+            Diagnostic::error(codes::UNKNOWN_BONE, e, limb.span)
+                .with_suggestions(similar_names(&limb.name, known.iter().copied()))
+                .with_suggestion(format!("limbs: {}", known.join(", ")))
+        })
+    }
+
+    fn check_look_statement(
+        statement: &ast::LookStatement,
+        args: &Bindings,
+    ) -> Result<MPLLookStatement, Diagnostic> {
+        let values = statement
+            .values
+            .iter()
+            .map(|slot| Self::bind_number(slot, args))
+            .collect::<Result<Vec<_>, _>>()?;
+        if values.len() != 3 {
+            return Err(Diagnostic::error(
+                codes::INVALID_LOOK,
+                format!("Target needs 3 coordinates, found {}", values.len()),
+                statement.span,
+            ));
+        }
+        // Coordinates may be given in cm or m like `move` distances
+        let scale = match &statement.unit {
+            None => 1.0,
+            Some(unit) => distance_in_units(1.0, &unit.name).ok_or_else(|| {
+                Diagnostic::error(
+                    codes::INVALID_UNIT,
+                    format!("Unknown unit '{}'", unit.name),
+                    unit.span,
+                )
+                .with_suggestion("use 'cm', 'm' or no unit for MMD units")
+            })?,
+        };
+        let target = Vector3::new(
+            values[0].value * scale,
+            values[1].value * scale,
+            values[2].value * scale,
+        );
+
+        let known: Vec<&str> = LOOK_WEIGHTS.iter().map(|(bone, _)| *bone).collect();
+        for (bone, weight) in &statement.weights {
+            if !known.contains(&bone.name.as_str()) {
+                return Err(Diagnostic::error(
+                    codes::UNKNOWN_BONE,
+                    format!("Bone '{}' cannot share a look", bone.name),
+                    bone.span,
+                )
+                .with_suggestions(similar_names(&bone.name, known.iter().copied()))
+                .with_suggestion(format!("look bones: {}", known.join(", "))));
+            }
+            if !(0.0..=1.0).contains(&weight.value) {
+                return Err(Diagnostic::error(
+                    codes::LIMIT_EXCEEDED,
+                    format!("Look weight {} must be between 0 and 1", weight.value),
+                    weight.span,
+                )
+                .with_suggestion(format!("use {}", weight.value.clamp(0.0, 1.0))));
+            }
+        }
+
+        let weights = statement
+            .weights
+            .iter()
+            .map(|(bone, weight)| (bone.name.clone(), weight.value))
+            .collect();
+        MPLLookStatement::new(target, weights).map_err(|e| {
+            Diagnostic::error(codes::INVALID_LOOK, e, statement.span)
+                .with_suggestion("lower the weights so they add up to 1 at most")
+        })
+    }
+
+    fn check_ik_statement(statement: &ast::IkStatement) -> Result<MPLIkStatement, Diagnostic> {
+        let ik = &statement.ik.name;
+        let enabled = Self::switch(&statement.state)?;
+        MPLIkStatement::new(ik, enabled).map_err(|e| {
+            let known = with_bone_db(|db| db.iks().to_vec());
+            Diagnostic::error(codes::UNKNOWN_BONE, e, statement.ik.span)
+                .with_suggestions(similar_names(ik, known.iter().map(|k| k.as_str())))
+                .with_suggestion(format!("IK names: {}", known.join(", ")))
+        })
+    }
 
-            brace_count += line.chars().filter(|&c| c == '{').count() as i32;
-            brace_count -= line.chars().filter(|&c| c == '}').count() as i32;
+    /// Mirror image of an earlier pose. Statements whose mirror image the bone
+    /// database rejects are reported at the source pose name and left out.
+    fn mirror_pose(
+        &self,
+        block: &ast::MirrorBlock,
+        program: &MPLProgram,
+        diagnostics: &mut Vec<Diagnostic>,
+    ) -> Option<MPLPose> {
+        let source = &block.source;
+        let Some(pose) = program.poses.get(&source.name) else {
+            let message = if program.templates.contains_key(&source.name) {
+                format!("Cannot mirror parameterized pose '{}'", source.name)
+            } else {
+                format!("Mirror of unknown pose '{}'", source.name)
+            };
+            diagnostics.push(
+                Diagnostic::error(codes::UNKNOWN_REFERENCE, message, source.span)
+                    .with_suggestions(similar_names(&source.name, program.pose_names())),
+            );
+            return None;
+        };
 
-            if brace_count < 0 {
-                return Err(format!(
-                    "Line {}: Unexpected closing brace",
-                    line_number + 1
+        let mut report = |statement: String, e: String| {
+            diagnostics.push(Diagnostic::error(
+                codes::INVALID_MIRROR,
+                format!("Cannot mirror '{}': {}", statement.trim_end_matches(';'), e),
+                source.span,
+            ))
+        };
+        let mut statements = vec![];
+        for statement in &pose.statements {
+            match statement.mirrored() {
+                Ok(mirrored) => statements.push(mirrored),
+                Err(e) => report(statement.to_string(), e),
+            }
+        }
+        let mut morphs = vec![];
+        for morph in &pose.morphs {
+            match morph.mirrored() {
+                Ok(mirrored) => morphs.push(mirrored),
+                Err(e) => report(morph.to_string(), e),
+            }
+        }
+        let mut ik = vec![];
+        for switch in &pose.ik {
+            match switch.mirrored() {
+                Ok(mirrored) => ik.push(mirrored),
+                Err(e) => report(switch.to_string(), e),
+            }
+        }
+        let mut reaches = vec![];
+        for reach in &pose.reaches {
+            match reach.mirrored() {
+                Ok(mirrored) => reaches.push(mirrored),
+                Err(e) => report(reach.to_string(), e),
+            }
+        }
+
+        Some(
+            MPLPose::new(block.name.name.clone(), statements)
+                .with_morphs(morphs)
+                .with_ik(ik, pose.show)
+                .with_look(pose.look.as_ref().map(MPLLookStatement::mirrored))
+                .with_reaches(reaches),
+        )
+    }
+
+    /// Parameters of a pose that is only resolved when an animation calls it
+    fn check_template(block: &ast::PoseBlock, diagnostics: &mut Vec<Diagnostic>) {
+        for (i, param) in block.params.iter().enumerate() {
+            if block.params[..i].iter().any(|p| p.name == param.name) {
+                diagnostics.push(Diagnostic::error(
+                    codes::DUPLICATE_NAME,
+                    format!("Duplicate parameter '{}'", param.name),
+                    param.span,
                 ));
             }
-            if brace_count == 0 {
-                match block_type {
-                    BlockType::Pose => {
-                        let pose = self.parse_pose(&current_block)?;
+        }
 
-                        // Check for duplicate pose name
-                        if script.poses.contains_key(&pose.name) {
-                            return Err(format!("Duplicate pose name: '{}'", pose.name));
-                        }
-                        if script.animations.contains_key(&pose.name)
-                            || script.poses.contains_key(&pose.name)
-                        {
-                            return Err(format!(
-                                "Name '{}' already used by an animation",
-                                pose.name
-                            ));
-                        }
+        let params: Vec<&str> = block.params.iter().map(|p| p.name.as_str()).collect();
+        for statement in &block.statements {
+            for param in statement.params() {
+                if !params.contains(&param.name.as_str()) {
+                    diagnostics.push(Self::unknown_param(param, params.iter().copied()));
+                }
+            }
+        }
 
-                        script.poses.insert(pose.name.clone(), pose);
-                    }
-                    BlockType::Animation => {
-                        let animation = self.parse_animation(&current_block)?;
-
-                        // Check for duplicate animation name
-                        if script.animations.contains_key(&animation.name)
-                            || script.poses.contains_key(&animation.name)
-                        {
-                            return Err(format!("Duplicate animation name: '{}'", animation.name));
-                        }
+        if block.statements.is_empty() {
+            diagnostics.push(Diagnostic::error(
+                codes::EMPTY_BLOCK,
+                "Pose must contain at least one statement",
+                block.span,
+            ));
+        }
+    }
 
-                        if script.poses.contains_key(&animation.name) {
-                            return Err(format!(
-                                "Name '{}' already used by a pose",
-                                animation.name
-                            ));
-                        }
+    /// Instantiate the pose an animation keyframe calls. A parameterized pose is
+    /// resolved once per distinct argument list, which is checked like a written-out
+    /// pose, and stored under a name like `look(left, 20)`.
+    fn resolve_call(
+        &self,
+        call: &ast::PoseCall,
+        animation: &str,
+        program: &mut MPLProgram,
+        diagnostics: &mut Vec<Diagnostic>,
+    ) -> Option<String> {
+        let name = &call.pose.name;
+        let Some(template) = program.templates.get(name) else {
+            if !program.poses.contains_key(name) {
+                diagnostics.push(
+                    Diagnostic::error(
+                        codes::UNKNOWN_REFERENCE,
+                        format!(
+                            "Animation '{}' references unknown pose '{}'",
+                            animation, name
+                        ),
+                        call.pose.span,
+                    )
+                    .with_suggestions(similar_names(name, program.pose_names())),
+                );
+                return None;
+            }
+            if !call.args.is_empty() {
+                diagnostics.push(
+                    Diagnostic::error(
+                        codes::INVALID_ARGUMENT,
+                        format!("Pose '{}' takes no arguments", name),
+                        call.span,
+                    )
+                    .with_suggestion(format!("use '{}'", name)),
+                );
+                return None;
+            }
+            return Some(name.clone());
+        };
 
-                        // Validate that all referenced poses exist
-                        for statement in &animation.statements {
-                            for pose_name in &statement.poses {
-                                if !script.poses.contains_key(pose_name) {
-                                    return Err(format!(
-                                        "Animation '{}' references unknown pose '{}'",
-                                        animation.name, pose_name
-                                    ));
-                                }
-                            }
-                        }
+        let params: Vec<&str> = template.params.iter().map(|p| p.name.as_str()).collect();
+        if call.args.len() != params.len() {
+            diagnostics.push(
+                Diagnostic::error(
+                    codes::INVALID_ARGUMENT,
+                    format!(
+                        "Pose '{}' takes {} argument{}, found {}",
+                        name,
+                        params.len(),
+                        if params.len() == 1 { "" } else { "s" },
+                        call.args.len()
+                    ),
+                    call.span,
+                )
+                .with_suggestion(format!(
+                    "call it as '{}({})'",
+                    name,
+                    params.join(", ")
+                )),
+            );
+            return None;
+        }
 
-                        script.animations.insert(animation.name.clone(), animation);
-                    }
-                    BlockType::Main => {
-                        let main = self.parse_main(&current_block)?;
-
-                        // Validate that all referenced animations/poses exist
-                        for reference in &main {
-                            if !script.animations.contains_key(reference)
-                                && !script.poses.contains_key(reference)
-                            {
-                                return Err(format!(
-                                    "Main references unknown animation or pose '{}'",
-                                    reference
-                                ));
-                            }
-                        }
+        let args: Vec<String> = call
+            .args
+            .iter()
+            .map(|arg| match arg {
+                ast::Arg::Ident(ident) => ident.name.clone(),
+                ast::Arg::Number(number) => number.value.to_string(),
+            })
+            .collect();
+        let instance = format!("{}({})", name, args.join(", "));
+        if !program.poses.contains_key(&instance) {
+            let bindings: Bindings = params.iter().copied().zip(call.args.iter()).collect();
+            let mut found = vec![];
+            let mut pose = self.parse_pose(template, &bindings, &mut found);
+            pose.name = instance.clone();
+            program.poses.insert(instance.clone(), pose);
 
-                        script.main = main;
-                    }
-                    BlockType::None => {}
+            // Mistakes outside the arguments come up again with every argument list
+            for diagnostic in found {
+                if !diagnostics.iter().any(|d| {
+                    d.code == diagnostic.code
+                        && d.span == diagnostic.span
+                        && d.message == diagnostic.message
+                }) {
+                    diagnostics.push(diagnostic);
                 }
-                current_block.clear();
-                in_block = false;
-                block_type = BlockType::None;
             }
         }
+        Some(instance)
+    }
+
+    fn unknown_param<'a>(
+        param: &ast::Ident,
+        params: impl IntoIterator<Item = &'a str>,
+    ) -> Diagnostic {
+        Diagnostic::error(
+            codes::UNKNOWN_REFERENCE,
+            format!("Unknown parameter '${}'", param.name),
+            param.span,
+        )
+        .with_suggestions(similar_names(&param.name, params))
+    }
+
+    /// The argument a `$param` stands for
+    fn bind<'a>(param: &ast::Ident, args: &Bindings<'a>) -> Result<&'a ast::Arg, Diagnostic> {
+        args.get(param.name.as_str())
+            .copied()
+            .ok_or_else(|| Self::unknown_param(param, args.keys().copied()))
+    }
 
-        if in_block {
-            return Err("Unclosed block".to_string());
+    fn bind_ident(slot: &ast::Slot<ast::Ident>, args: &Bindings) -> Result<ast::Ident, Diagnostic> {
+        match slot {
+            ast::Slot::Value(ident) => Ok(ident.clone()),
+            ast::Slot::Param(param) => match Self::bind(param, args)? {
+                ast::Arg::Ident(ident) => Ok(ident.clone()),
+                ast::Arg::Number(number) => Err(Diagnostic::error(
+                    codes::INVALID_ARGUMENT,
+                    format!(
+                        "Parameter '${}' takes a name, found {}",
+                        param.name, number.value
+                    ),
+                    number.span,
+                )),
+            },
         }
+    }
 
-        Ok(script.to_key_frames())
+    fn bind_number(
+        slot: &ast::Slot<ast::Number>,
+        args: &Bindings,
+    ) -> Result<ast::Number, Diagnostic> {
+        match slot {
+            ast::Slot::Value(number) => Ok(number.clone()),
+            ast::Slot::Param(param) => match Self::bind(param, args)? {
+                ast::Arg::Number(number) => Ok(number.clone()),
+                ast::Arg::Ident(ident) => Err(Diagnostic::error(
+                    codes::INVALID_ARGUMENT,
+                    format!(
+                        "Parameter '${}' takes a number, found '{}'",
+                        param.name, ident.name
+                    ),
+                    ident.span,
+                )),
+            },
+        }
     }
 
-    fn parse_pose(&self, text: &str) -> Result<MPLPose, String> {
-        let mut pose_name = String::new();
-        let mut statements = Vec::new();
+    fn check_morph_statement(
+        statement: &ast::MorphStatement,
+        args: &Bindings,
+    ) -> Result<MPLMorphStatement, Diagnostic> {
+        let morph = Self::bind_ident(&statement.morph, args)?;
+        let weight = Self::bind_number(&statement.weight, args)?;
 
-        for (line_number, line) in text.lines().enumerate() {
-            let trimmed = line.trim();
-            if trimmed.is_empty() || trimmed == "{" || trimmed == "}" {
-                continue;
+        if with_bone_db(|db| db.morph_japanese_name(&morph.name).is_none()) {
+            let known = with_bone_db(|db| db.morphs().to_vec());
+            return Err(Diagnostic::error(
+                codes::UNKNOWN_MORPH,
+                format!("Unknown morph '{}'", morph.name),
+                morph.span,
+            )
+            .with_suggestions(similar_names(&morph.name, known.iter().map(|m| m.as_str()))));
+        }
+        if !(0.0..=1.0).contains(&weight.value) {
+            return Err(Diagnostic::error(
+                codes::LIMIT_EXCEEDED,
+                format!("Morph weight {} must be between 0 and 1", weight.value),
+                weight.span,
+            )
+            .with_suggestion(format!("use {}", weight.value.clamp(0.0, 1.0))));
+        }
+
+        MPLMorphStatement::new(&morph.name, weight.value)
+            .map_err(|e| Diagnostic::error(codes::UNKNOWN_MORPH, e, statement.span))
+    }
+
+    /// Validate a bone statement against the bone database, pointing at the offending
+    /// token, or at the argument a `$param` took. Amounts are converted to database
+    /// units: degrees, or MMD units for `move`.
+    fn check_bone_statement(
+        statement: &ast::BoneStatement,
+        args: &Bindings,
+    ) -> Result<MPLPoseStatement, Diagnostic> {
+        let bone = Self::bind_ident(&statement.bone, args)?;
+        let action = Self::bind_ident(&statement.action, args)?;
+        let direction = Self::bind_ident(&statement.direction, args)?;
+        let amount = Self::bind_number(&statement.degrees, args)?;
+        let degrees = Self::check_bone_amount(&bone, &action, &direction, &amount, statement)?;
+
+        MPLPoseStatement::new(&bone.name, &action.name, &direction.name, degrees)
+            .map_err(|e| Diagnostic::error(codes::UNKNOWN_BONE, e, statement.span))
+    }
+
+    /// Returns the amount in database units
+    fn check_bone_amount(
+        bone: &ast::Ident,
+        action: &ast::Ident,
+        direction: &ast::Ident,
+        amount: &ast::Number,
+        statement: &ast::BoneStatement,
+    ) -> Result<f32, Diagnostic> {
+        let (bone_span, action_span, direction_span) = (bone.span, action.span, direction.span);
+        let (bone, action, direction) = (&bone.name, &action.name, &direction.name);
+
+        // Distances may be given in cm or m, limits are stored in MMD units
+        let (degrees, scale, unit_name) = match &statement.unit {
+            None if action == MOVE_ACTION => (amount.value, 1.0, "MMD units"),
+            None => (amount.value, 1.0, "degrees"),
+            Some(unit) if action != MOVE_ACTION => {
+                return Err(Diagnostic::error(
+                    codes::INVALID_UNIT,
+                    format!("Only 'move' distances take a unit, found '{}'", unit.name),
+                    unit.span,
+                )
+                .with_suggestion(format!("remove '{}'", unit.name)))
             }
+            Some(unit) => match distance_in_units(1.0, &unit.name) {
+                Some(scale) => (amount.value * scale, scale, unit.name.as_str()),
+                None => {
+                    return Err(Diagnostic::error(
+                        codes::INVALID_UNIT,
+                        format!("Unknown unit '{}'", unit.name),
+                        unit.span,
+                    )
+                    .with_suggestion("use 'cm', 'm' or no unit for MMD units"))
+                }
+            },
+        };
 
-            if trimmed.starts_with("@pose") {
-                let name_part = trimmed.trim_end_matches('{').trim();
-                pose_name = name_part
-                    .split_whitespace()
-                    .nth(1)
-                    .ok_or(format!("Line {}: Missing pose name", line_number + 1))?
-                    .to_string();
+        with_bone_db(|db| {
+            db.check(bone, action, direction, degrees)
+                .map(|_| degrees)
+                .map_err(|violation| match violation {
+                    RuleViolation::UnknownBone => Diagnostic::error(
+                        codes::UNKNOWN_BONE,
+                        format!("Unknown bone '{}'", bone),
+                        bone_span,
+                    )
+                    .with_suggestions(similar_names(bone, db.bones().iter().map(|b| b.as_str()))),
+                    RuleViolation::UnknownAction => {
+                        let mut actions = db.actions(bone).unwrap_or_default().to_vec();
+                        actions.sort();
+                        Diagnostic::error(
+                            codes::UNKNOWN_ACTION,
+                            format!("Bone '{}' cannot {}", bone, action),
+                            action_span,
+                        )
+                        .with_suggestion(format!("valid actions: {}", actions.join(", ")))
+                    }
+                    RuleViolation::UnknownDirection => {
+                        let mut directions =
+                            db.directions(bone, action).unwrap_or_default().to_vec();
+                        directions.sort();
+                        Diagnostic::error(
+                            codes::UNKNOWN_DIRECTION,
+                            format!("Bone '{}' cannot {} {}", bone, action, direction),
+                            direction_span,
+                        )
+                        .with_suggestion(format!("valid directions: {}", directions.join(", ")))
+                    }
+                    RuleViolation::LimitExceeded(limit) => {
+                        let limit = limit / scale;
+                        Diagnostic::error(
+                            codes::LIMIT_EXCEEDED,
+                            format!(
+                                "Max {} {} for {} {} {}",
+                                limit, unit_name, bone, action, direction
+                            ),
+                            amount.span,
+                        )
+                        .with_suggestion(format!("use at most {}", limit))
+                    }
+                    RuleViolation::Negative => Diagnostic::error(
+                        codes::LIMIT_EXCEEDED,
+                        format!("Amount {} must not be negative", amount.value),
+                        amount.span,
+                    )
+                    .with_suggestion(format!("use {} with the opposite direction", -amount.value)),
+                })
+        })
+    }
+
+    fn parse_animation(
+        &self,
+        block: &ast::AnimationBlock,
+        program: &mut MPLProgram,
+        diagnostics: &mut Vec<Diagnostic>,
+    ) -> MPLAnimation {
+        let statements =
+            self.animation_statements(&block.statements, &block.name.name, program, diagnostics);
+
+        if block.statements.is_empty() {
+            diagnostics.push(Diagnostic::error(
+                codes::EMPTY_BLOCK,
+                "Animation must contain at least one statement",
+                block.span,
+            ));
+        }
+
+        MPLAnimation::new(block.name.name.clone(), statements)
+    }
+
+    /// Keyframes of an animation body with every repeat unrolled. A repeat starts on
+    /// the beat after the keyframes before it, by the rule of `MPLAnimation::duration`.
+    fn animation_statements(
+        &self,
+        statements: &[ast::AnimationStatement],
+        animation: &str,
+        program: &mut MPLProgram,
+        diagnostics: &mut Vec<Diagnostic>,
+    ) -> Vec<MPLAnimationStatement> {
+        let mut keys: Vec<MPLAnimationStatement> = Vec::new();
+
+        for statement in statements {
+            let repeat = match statement {
+                ast::AnimationStatement::Key(statement) => {
+                    keys.extend(self.key_statement(statement, animation, program, diagnostics));
+                    continue;
+                }
+                ast::AnimationStatement::Repeat(repeat) => repeat,
+            };
+
+            if repeat.statements.is_empty() {
+                diagnostics.push(Diagnostic::error(
+                    codes::EMPTY_BLOCK,
+                    "Repeat must contain at least one statement",
+                    repeat.span,
+                ));
                 continue;
             }
 
-            if trimmed.ends_with(';') {
-                let stmt_text = trimmed.trim_end_matches(';').trim();
-                if !stmt_text.is_empty() {
-                    match crate::pose::MPLPoseStatement::from_str(stmt_text) {
-                        Ok(stmt) => statements.push(stmt),
-                        Err(e) => return Err(format!("Line {}: {}", line_number + 1, e)),
+            let body =
+                self.animation_statements(&repeat.statements, animation, program, diagnostics);
+            let start = track_duration(keys.iter().map(|k| k.time).collect());
+            let cycle = track_duration(body.iter().map(|k| k.time).collect());
+            let repetition =
+                match Self::check_repetition(&repeat.repetition, start, cycle, repeat.span) {
+                    Ok(repetition) => repetition,
+                    Err(diagnostic) => {
+                        diagnostics.push(diagnostic);
+                        continue;
                     }
+                };
+
+            for offset in repetition.starts(start, cycle) {
+                keys.extend(
+                    body.iter()
+                        .filter(|key| offset + key.time < repetition.end())
+                        .map(|key| MPLAnimationStatement {
+                            time: offset + key.time,
+                            ..key.clone()
+                        }),
+                );
+            }
+        }
+
+        keys
+    }
+
+    fn key_statement(
+        &self,
+        statement: &ast::KeyStatement,
+        animation: &str,
+        program: &mut MPLProgram,
+        diagnostics: &mut Vec<Diagnostic>,
+    ) -> Option<MPLAnimationStatement> {
+        if statement.time.value < 0.0 {
+            diagnostics.push(Diagnostic::error(
+                codes::NEGATIVE_TIME,
+                "Time must be non-negative",
+                statement.time.span,
+            ));
+            return None;
+        }
+
+        // Validate that all referenced poses exist
+        let poses: Vec<String> = statement
+            .poses
+            .iter()
+            .filter_map(|call| self.resolve_call(call, animation, program, diagnostics))
+            .collect();
+        if poses.len() != statement.poses.len() {
+            return None;
+        }
+
+        let easing = match &statement.easing {
+            Some(easing) => match Self::resolve_easing(easing) {
+                Ok(curve) => curve,
+                Err(diagnostic) => {
+                    diagnostics.push(diagnostic);
+                    return None;
                 }
-            } else {
-                return Err(format!(
-                    "Line {}: Statement must end with semicolon",
-                    line_number + 1
+            },
+            None => BezierCurve::default(),
+        };
+
+        let weights = match Self::check_weights(statement) {
+            Ok(weights) => weights,
+            Err(diagnostic) => {
+                diagnostics.push(diagnostic);
+                return None;
+            }
+        };
+
+        match MPLAnimationStatement::new(statement.time.value, poses) {
+            Ok(statement) => Some(statement.with_easing(easing).with_weights(weights)),
+            Err(e) => {
+                diagnostics.push(Diagnostic::error(
+                    codes::UNEXPECTED_TOKEN,
+                    e,
+                    statement.span,
                 ));
+                None
+            }
+        }
+    }
+
+    /// `repeat n` needs a whole count of at least 1 and `loop until t` an end after the
+    /// start, which is where the previous statements end unless `at` gives it; neither
+    /// may unroll into more than `MAX_REPEATS` plays
+    fn check_repetition(
+        repetition: &ast::Repetition,
+        start: f32,
+        cycle: f32,
+        statement_span: Span,
+    ) -> Result<MPLRepeat, Diagnostic> {
+        let (plays, span) = match repetition {
+            ast::Repetition::Times { count } => {
+                if count.value < 1.0 || count.value.fract() != 0.0 {
+                    return Err(Diagnostic::error(
+                        codes::INVALID_REPEAT,
+                        format!(
+                            "Repeat count must be a whole number of at least 1, got {}",
+                            count.value
+                        ),
+                        count.span,
+                    ));
+                }
+                (count.value, count.span)
+            }
+            ast::Repetition::Until { time } => {
+                if time.value <= start {
+                    return Err(Diagnostic::error(
+                        codes::INVALID_REPEAT,
+                        format!("Loop end {} is not after its start {}", time.value, start),
+                        statement_span,
+                    )
+                    .with_suggestion(format!("loop until a time after {}", start)));
+                }
+                let plays = if cycle > 0.0 {
+                    ((time.value - start) / cycle).ceil()
+                } else {
+                    1.0
+                };
+                (plays, time.span)
+            }
+        };
+
+        if plays > MAX_REPEATS as f32 {
+            return Err(Diagnostic::error(
+                codes::LIMIT_EXCEEDED,
+                format!("{} plays exceed the limit of {}", plays, MAX_REPEATS),
+                span,
+            ));
+        }
+
+        Ok(match repetition {
+            ast::Repetition::Times { count } => MPLRepeat::Times {
+                count: count.value as u32,
+            },
+            ast::Repetition::Until { time } => MPLRepeat::Until { time: time.value },
+        })
+    }
+
+    /// Blend weights of a keyframe, empty when no pose is weighted. Every pose of a
+    /// blend needs a weight from 0 to 1 and together they may not exceed 1.
+    fn check_weights(statement: &ast::KeyStatement) -> Result<Vec<f32>, Diagnostic> {
+        if statement.poses.iter().all(|call| call.weight.is_none()) {
+            return Ok(vec![]);
+        }
+
+        let mut weights = vec![];
+        for call in &statement.poses {
+            let Some(weight) = &call.weight else {
+                return Err(Diagnostic::error(
+                    codes::INVALID_BLEND,
+                    format!("Pose '{}' needs a blend weight", call.pose.name),
+                    call.span,
+                )
+                .with_suggestion(format!(
+                    "weight every pose of a blend, e.g. '{}*0.5'",
+                    call.pose.name
+                )));
+            };
+            if !(0.0..=1.0).contains(&weight.value) {
+                return Err(Diagnostic::error(
+                    codes::LIMIT_EXCEEDED,
+                    format!("Blend weight {} must be between 0 and 1", weight.value),
+                    weight.span,
+                )
+                .with_suggestion(format!("use {}", weight.value.clamp(0.0, 1.0))));
             }
+            weights.push(weight.value);
         }
 
-        if pose_name.is_empty() {
-            return Err("No pose declaration found".to_string());
+        let total: f32 = weights.iter().sum();
+        if total > 1.0 + 1e-4 {
+            return Err(Diagnostic::error(
+                codes::LIMIT_EXCEEDED,
+                format!("Blend weights add up to {}, more than 1", total),
+                statement.span,
+            )
+            .with_suggestion("weight left below 1 blends toward the rest pose"));
         }
+        Ok(weights)
+    }
 
-        if statements.is_empty() {
-            return Err("Pose must contain at least one statement".to_string());
+    fn resolve_easing(easing: &ast::Easing) -> Result<BezierCurve, Diagnostic> {
+        match easing {
+            ast::Easing::Preset(name) => BezierCurve::preset(&name.name).ok_or_else(|| {
+                Diagnostic::error(
+                    codes::INVALID_EASING,
+                    format!("Unknown easing '{}'", name.name),
+                    name.span,
+                )
+                .with_suggestion(format!(
+                    "use one of {} or bezier(x1, y1, x2, y2)",
+                    BezierCurve::PRESETS.join(", ")
+                ))
+            }),
+            ast::Easing::Bezier { points, span } => match points.as_slice() {
+                [x1, y1, x2, y2] => BezierCurve::new(x1.value, y1.value, x2.value, y2.value)
+                    .map_err(|e| Diagnostic::error(codes::INVALID_EASING, e, *span)),
+                _ => Err(Diagnostic::error(
+                    codes::INVALID_EASING,
+                    format!("bezier takes 4 control points, found {}", points.len()),
+                    *span,
+                )),
+            },
         }
+    }
 
-        Ok(MPLPose::new(pose_name, statements))
+    fn parse_camera(
+        &self,
+        block: &ast::TrackBlock,
+        diagnostics: &mut Vec<Diagnostic>,
+    ) -> MPLCamera {
+        let statements = Self::track_statements(
+            block,
+            "camera",
+            &["distance", "target", "rotation", "fov", "perspective"],
+            true,
+            diagnostics,
+            |time, easing| MPLCameraStatement {
+                time,
+                easing,
+                ..Default::default()
+            },
+            |camera, property| {
+                match property.name.name.as_str() {
+                    "distance" => {
+                        camera.distance = Some(Self::number_property(property, 0.0, f32::MAX)?)
+                    }
+                    "target" => camera.target = Some(Self::vector_property(property, None)?),
+                    "rotation" => camera.rotation = Some(Self::vector_property(property, None)?),
+                    "fov" => {
+                        camera.fov =
+                            Some(Self::number_property(property, 1.0, 125.0)?.round() as u32)
+                    }
+                    _ => camera.perspective = Some(Self::switch_property(property)?),
+                }
+                Ok(())
+            },
+        );
+        MPLCamera::new(block.name.name.clone(), statements)
     }
 
-    fn parse_animation(&self, text: &str) -> Result<MPLAnimation, String> {
-        let mut animation_name = String::new();
+    fn parse_light(&self, block: &ast::TrackBlock, diagnostics: &mut Vec<Diagnostic>) -> MPLLight {
+        let statements = Self::track_statements(
+            block,
+            "light",
+            &["color", "direction"],
+            false,
+            diagnostics,
+            |time, _| MPLLightStatement {
+                time,
+                ..Default::default()
+            },
+            |light, property| {
+                match property.name.name.as_str() {
+                    "color" => {
+                        light.color = Some(Self::vector_property(property, Some((0.0, 255.0)))?)
+                    }
+                    _ => {
+                        light.direction = Some(Self::vector_property(property, Some((-1.0, 1.0)))?)
+                    }
+                }
+                Ok(())
+            },
+        );
+        MPLLight::new(block.name.name.clone(), statements)
+    }
+
+    fn parse_shadow(
+        &self,
+        block: &ast::TrackBlock,
+        diagnostics: &mut Vec<Diagnostic>,
+    ) -> MPLShadow {
+        let statements = Self::track_statements(
+            block,
+            "shadow",
+            &["mode", "distance"],
+            false,
+            diagnostics,
+            |time, _| MPLShadowStatement {
+                time,
+                ..Default::default()
+            },
+            |shadow, property| {
+                match property.name.name.as_str() {
+                    "mode" => shadow.mode = Some(Self::shadow_mode(property)?),
+                    _ => shadow.distance = Some(Self::number_property(property, 0.0, 9999.0)?),
+                }
+                Ok(())
+            },
+        );
+        MPLShadow::new(block.name.name.clone(), statements)
+    }
+
+    /// Keyframes of a `@camera`, `@light` or `@shadow` block. Each keyframe is built by
+    /// `new` and filled in by `set`, which only sees names listed in `properties`;
+    /// keyframes with an invalid property or easing are reported and dropped.
+    fn track_statements<T>(
+        block: &ast::TrackBlock,
+        kind: &str,
+        properties: &[&str],
+        eases: bool,
+        diagnostics: &mut Vec<Diagnostic>,
+        new: impl Fn(f32, BezierCurve) -> T,
+        set: impl Fn(&mut T, &ast::Property) -> Result<(), Diagnostic>,
+    ) -> Vec<T> {
+        let title = format!("{}{}", kind[..1].to_uppercase(), &kind[1..]);
         let mut statements = Vec::new();
 
-        for (line_number, line) in text.lines().enumerate() {
-            let trimmed = line.trim();
-            if trimmed.is_empty() || trimmed == "{" || trimmed == "}" {
+        for statement in &block.statements {
+            if statement.time.value < 0.0 {
+                diagnostics.push(Diagnostic::error(
+                    codes::NEGATIVE_TIME,
+                    "Time must be non-negative",
+                    statement.time.span,
+                ));
                 continue;
             }
 
-            if trimmed.starts_with("@animation") {
-                let name_part = trimmed.trim_end_matches('{').trim();
-                animation_name = name_part
-                    .split_whitespace()
-                    .nth(1)
-                    .ok_or(format!("Line {}: Missing animation name", line_number + 1))?
-                    .to_string();
-                continue;
+            let mut valid = true;
+            let easing = match &statement.easing {
+                None => BezierCurve::default(),
+                Some(easing) if !eases => {
+                    let span = match easing {
+                        ast::Easing::Preset(name) => name.span,
+                        ast::Easing::Bezier { span, .. } => *span,
+                    };
+                    diagnostics.push(
+                        Diagnostic::error(
+                            codes::INVALID_EASING,
+                            format!("{} keyframes cannot ease", title),
+                            span,
+                        )
+                        .with_suggestion("remove the 'with' clause"),
+                    );
+                    valid = false;
+                    BezierCurve::default()
+                }
+                Some(easing) => Self::resolve_easing(easing).unwrap_or_else(|diagnostic| {
+                    diagnostics.push(diagnostic);
+                    valid = false;
+                    BezierCurve::default()
+                }),
+            };
+
+            let mut keyframe = new(statement.time.value, easing);
+            for property in &statement.properties {
+                let name = property.name.name.as_str();
+                let result = if properties.contains(&name) {
+                    set(&mut keyframe, property)
+                } else {
+                    Err(Diagnostic::error(
+                        codes::INVALID_PROPERTY,
+                        format!("Unknown {} property '{}'", kind, name),
+                        property.name.span,
+                    )
+                    .with_suggestions(similar_names(name, properties.iter().copied()))
+                    .with_suggestion(format!(
+                        "{} properties: {}",
+                        kind,
+                        properties.join(", ")
+                    )))
+                };
+                if let Err(diagnostic) = result {
+                    diagnostics.push(diagnostic);
+                    valid = false;
+                }
+            }
+
+            if valid {
+                statements.push(keyframe);
+            }
+        }
+
+        if block.statements.is_empty() {
+            diagnostics.push(Diagnostic::error(
+                codes::EMPTY_BLOCK,
+                format!("{} must contain at least one statement", title),
+                block.span,
+            ));
+        }
+
+        statements
+    }
+
+    /// A number property within `min..=max`
+    fn number_property(property: &ast::Property, min: f32, max: f32) -> Result<f32, Diagnostic> {
+        match &property.value {
+            ast::PropertyValue::Number(number) if (min..=max).contains(&number.value) => {
+                Ok(number.value)
             }
+            ast::PropertyValue::Number(number) => Err(Diagnostic::error(
+                codes::LIMIT_EXCEEDED,
+                format!(
+                    "'{}' must be between {} and {}",
+                    property.name.name, min, max
+                ),
+                number.span,
+            )
+            .with_suggestion(format!("use {}", number.value.clamp(min, max)))),
+            value => Err(Diagnostic::error(
+                codes::INVALID_PROPERTY,
+                format!("'{}' takes a number", property.name.name),
+                value.span(),
+            )),
+        }
+    }
 
-            if trimmed.ends_with(';') {
-                let stmt_text = trimmed.trim_end_matches(';').trim();
-                if !stmt_text.is_empty() {
-                    match crate::animation::MPLAnimationStatement::from_str(stmt_text) {
-                        Ok(stmt) => statements.push(stmt),
-                        Err(e) => return Err(format!("Line {}: {}", line_number + 1, e)),
+    /// An `(x, y, z)` property, optionally with every component within a range
+    fn vector_property(
+        property: &ast::Property,
+        range: Option<(f32, f32)>,
+    ) -> Result<Vector3, Diagnostic> {
+        match &property.value {
+            ast::PropertyValue::Vector { values, .. } if values.len() == 3 => {
+                if let Some((min, max)) = range {
+                    if let Some(value) = values.iter().find(|v| !(min..=max).contains(&v.value)) {
+                        return Err(Diagnostic::error(
+                            codes::LIMIT_EXCEEDED,
+                            format!(
+                                "'{}' values must be between {} and {}",
+                                property.name.name, min, max
+                            ),
+                            value.span,
+                        )
+                        .with_suggestion(format!("use {}", value.value.clamp(min, max))));
                     }
                 }
-            } else {
-                return Err(format!(
-                    "Line {}: Statement must end with semicolon",
-                    line_number + 1
-                ));
+                Ok(Vector3::new(
+                    values[0].value,
+                    values[1].value,
+                    values[2].value,
+                ))
             }
+            value => Err(Diagnostic::error(
+                codes::INVALID_PROPERTY,
+                format!("'{}' takes three values (x, y, z)", property.name.name),
+                value.span(),
+            )),
         }
+    }
 
-        if animation_name.is_empty() {
-            return Err("No animation declaration found".to_string());
+    /// Self shadow `mode off`, `mode 1` or `mode 2`
+    fn shadow_mode(property: &ast::Property) -> Result<u8, Diagnostic> {
+        match &property.value {
+            ast::PropertyValue::Ident(ident) if ident.name == "off" => Ok(0),
+            ast::PropertyValue::Number(number) if number.value == 1.0 || number.value == 2.0 => {
+                Ok(number.value as u8)
+            }
+            value => Err(Diagnostic::error(
+                codes::INVALID_PROPERTY,
+                "Shadow 'mode' must be 'off', 1 or 2",
+                value.span(),
+            )),
         }
+    }
 
-        if statements.is_empty() {
-            return Err("Animation must contain at least one statement".to_string());
+    /// `on` or `off` after `ik name` or `show`
+    fn switch(state: &ast::Ident) -> Result<bool, Diagnostic> {
+        match state.name.as_str() {
+            "on" => Ok(true),
+            "off" => Ok(false),
+            name => Err(Diagnostic::error(
+                codes::INVALID_PROPERTY,
+                format!("Expected 'on' or 'off', found '{}'", name),
+                state.span,
+            )),
         }
+    }
 
-        Ok(MPLAnimation::new(animation_name, statements))
+    /// An `on`/`off` property
+    fn switch_property(property: &ast::Property) -> Result<bool, Diagnostic> {
+        match &property.value {
+            ast::PropertyValue::Ident(ident) if ident.name == "on" => Ok(true),
+            ast::PropertyValue::Ident(ident) if ident.name == "off" => Ok(false),
+            value => Err(Diagnostic::error(
+                codes::INVALID_PROPERTY,
+                format!("'{}' must be 'on' or 'off'", property.name.name),
+                value.span(),
+            )),
+        }
     }
 
-    fn parse_main(&self, text: &str) -> Result<Vec<String>, String> {
-        let mut animations = Vec::new();
+    /// The entries of the main block with the span of the statement each comes from
+    fn parse_main(
+        &self,
+        block: &ast::MainBlock,
+        program: &MPLProgram,
+        diagnostics: &mut Vec<Diagnostic>,
+    ) -> Vec<(MPLMainEntry, Span)> {
+        if block.statements.is_empty() {
+            diagnostics.push(Diagnostic::error(
+                codes::EMPTY_BLOCK,
+                "Main block must contain at least one animation reference",
+                block.span,
+            ));
+        }
 
-        for (line_number, line) in text.lines().enumerate() {
-            let trimmed = line.trim();
-            if trimmed.is_empty() || trimmed == "{" || trimmed == "}" {
-                continue;
+        let mut main = Vec::new();
+        // Where the statements so far end, to check loops against their actual start
+        let mut cursor = 0.0;
+        for statement in &block.statements {
+            if let Some(start) = &statement.start {
+                if start.value < 0.0 {
+                    diagnostics.push(Diagnostic::error(
+                        codes::NEGATIVE_TIME,
+                        "Start time must be non-negative",
+                        start.span,
+                    ));
+                    continue;
+                }
             }
 
-            // Skip main declaration
-            if trimmed == "main" || trimmed.starts_with("main") {
-                continue;
+            // Validate that all referenced animations/poses exist
+            let names: Vec<String> = statement
+                .entries
+                .iter()
+                .filter_map(|entry| self.main_reference(entry, program, diagnostics))
+                .collect();
+
+            // Layers only add bone and morph motion
+            let mut layers = Vec::new();
+            for layer in &statement.layers {
+                let Some(name) = self.main_reference(layer, program, diagnostics) else {
+                    continue;
+                };
+                match program.kind_of(&name) {
+                    Some("animation" | "pose") => layers.push(name),
+                    kind => diagnostics.push(
+                        Diagnostic::error(
+                            codes::INVALID_ARGUMENT,
+                            format!(
+                                "Cannot layer {} '{}', only animations and poses",
+                                kind.unwrap_or("block"),
+                                name
+                            ),
+                            layer.span,
+                        )
+                        .with_suggestion(format!("play it alongside with '& {}'", name)),
+                    ),
+                }
             }
 
-            // Parse animation/pose reference (must end with semicolon)
-            if trimmed.ends_with(';') {
-                let name = trimmed.trim_end_matches(';').trim();
-                if !name.is_empty() {
-                    animations.push(name.to_string());
+            let cycle = program.cycle(&names, &layers);
+            let repeat = match &statement.repetition {
+                Some(repetition) => {
+                    let start = statement.start.as_ref().map_or(cursor, |start| start.value);
+                    match Self::check_repetition(repetition, start, cycle, statement.span) {
+                        Ok(repeat) => Some(repeat),
+                        Err(diagnostic) => {
+                            diagnostics.push(diagnostic);
+                            continue;
+                        }
+                    }
+                }
+                None => None,
+            };
+
+            let entry = MPLMainEntry {
+                start: statement.start.as_ref().map(|start| start.value),
+                names,
+                layers,
+                repeat,
+            };
+            cursor = entry.layout(cursor, cycle).2;
+            main.push((entry, statement.span));
+        }
+
+        main
+    }
+
+    /// The name a main statement refers to, if it names something main can play
+    fn main_reference(
+        &self,
+        entry: &ast::Ident,
+        program: &MPLProgram,
+        diagnostics: &mut Vec<Diagnostic>,
+    ) -> Option<String> {
+        if let Some(template) = program.templates.get(&entry.name) {
+            let params: Vec<&str> = template.params.iter().map(|p| p.name.as_str()).collect();
+            diagnostics.push(
+                Diagnostic::error(
+                    codes::INVALID_ARGUMENT,
+                    format!("Pose '{}' takes arguments", entry.name),
+                    entry.span,
+                )
+                .with_suggestion(format!(
+                    "call '{}({})' from an animation",
+                    entry.name,
+                    params.join(", ")
+                )),
+            );
+            return None;
+        }
+        if program.kind_of(&entry.name).is_some() {
+            return Some(entry.name.clone());
+        }
+
+        let known = program.names();
+        diagnostics.push(
+            Diagnostic::error(
+                codes::UNKNOWN_REFERENCE,
+                format!("Main references unknown animation or pose '{}'", entry.name),
+                entry.span,
+            )
+            .with_suggestions(similar_names(&entry.name, known)),
+        );
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vmd::VMDWriter;
+
+    /// Diagnostics of a script with the text each one points at
+    fn diagnostics(text: &str) -> Vec<(String, String)> {
+        MPLCompiler::new()
+            .check(text)
+            .diagnostics
+            .into_iter()
+            .map(|d| {
+                let span = &text[d.span.start.offset as usize..d.span.end.offset as usize];
+                (d.code, span.to_string())
+            })
+            .collect()
+    }
+
+    #[test]
+    fn parallel_entries_keying_one_bone_are_reported_on_main() {
+        let text = "@pose a { head turn left 10; } @pose b { head turn right 10; } \
+                    main { a; a & b; }";
+        assert_eq!(
+            diagnostics(text),
+            [(codes::FRAME_CONFLICT.to_string(), "a & b;".to_string())]
+        );
+
+        let text = "@pose a { head turn left 10; } @pose b { neck turn right 10; } \
+                    main { a & b; at 0.5: a; }";
+        assert!(diagnostics(text).is_empty());
+    }
+
+    #[test]
+    fn main_entries_are_laid_out_one_after_another() {
+        let text = "@pose p { head turn left 10; } @pose q { neck turn left 10; } \
+                    @animation a { 0: p; 0.5: q; } @animation b { 3.0: p; } \
+                    main { a; b; a & b; at 2.5: a; a; }";
+        let compilation = MPLCompiler::new().check(text);
+        assert!(compilation.diagnostics.is_empty());
+
+        let starts: Vec<(usize, f32)> = compilation
+            .program
+            .timeline()
+            .into_iter()
+            .map(|(index, start, _, _)| (index, start))
+            .collect();
+        // `a` lasts 1s and `b`, with a single key at 3s, lasts 6s; `a & b` lasts as
+        // long as `b`, and the entry after an absolute start follows it
+        assert_eq!(starts, [(0, 0.0), (1, 1.0), (2, 7.0), (3, 2.5), (4, 3.5)]);
+    }
+
+    /// Times of the keyframes moving `bone`
+    fn bone_times(text: &str, bone: &str) -> Vec<f32> {
+        let key_frames = MPLCompiler::new().compile(text).unwrap();
+        key_frames
+            .iter()
+            .filter(|key_frame| key_frame.bone_frames.iter().any(|b| b.name_en() == bone))
+            .map(|key_frame| key_frame.time)
+            .collect()
+    }
+
+    #[test]
+    fn repeats_play_back_to_back() {
+        let poses = "@pose p { head turn left 10; } @pose q { neck turn left 10; } \
+                     @animation w { 0: p; 0.5: q; } ";
+
+        let text = format!("{} main {{ w x 3; }}", poses);
+        assert_eq!(bone_times(&text, "head"), [0.0, 1.0, 2.0]);
+        assert_eq!(bone_times(&text, "neck"), [0.5, 1.5, 2.5]);
+
+        // The third play starts before 2.2s, but its neck key would land past it
+        let text = format!("{} main {{ w loop until 2.2; }}", poses);
+        assert_eq!(bone_times(&text, "head"), [0.0, 1.0, 2.0]);
+        assert_eq!(bone_times(&text, "neck"), [0.5, 1.5]);
+
+        let text = format!(
+            "{} @animation r {{ 0: q; repeat 2 {{ 0: p; 0.5: q; }} }} main {{ r; }}",
+            poses
+        );
+        assert_eq!(bone_times(&text, "head"), [1.0, 2.0]);
+        assert_eq!(bone_times(&text, "neck"), [0.0, 1.5, 2.5]);
+    }
+
+    #[test]
+    fn blends_are_a_normalized_weighted_average() {
+        let poses = "@pose a { head turn left 40; } @pose b { head bend forward 20; } ";
+        let head = |main: &str| {
+            let text = format!("{} @animation m {{ 0: {}; }} main {{ m; }}", poses, main);
+            let key_frames = MPLCompiler::new().compile(&text).unwrap();
+            let frame = key_frames[0]
+                .bone_frames
+                .iter()
+                .find(|b| b.name_en() == "head");
+            frame.unwrap().rotation()
+        };
+        let nlerp = |parts: &[(Quaternion, f32)]| {
+            let mut sum = [0.0f32; 4];
+            for (q, weight) in parts {
+                for (total, value) in sum.iter_mut().zip([q.x, q.y, q.z, q.w]) {
+                    *total += value * weight;
                 }
-            } else {
-                return Err(format!(
-                    "Line {}: Animation reference must end with semicolon",
-                    line_number + 1
-                ));
             }
+            let length = sum.iter().map(|v| v * v).sum::<f32>().sqrt();
+            Quaternion::new(
+                sum[0] / length,
+                sum[1] / length,
+                sum[2] / length,
+                sum[3] / length,
+            )
+        };
+        let (a, b) = (head("a"), head("b"));
+
+        let expected = nlerp(&[(a, 0.7), (b, 0.3)]);
+        assert!(head("a*0.7 & b*0.3").angle_to(&expected) < 0.1);
+        // Weight left below 1 goes to the rest pose
+        let expected = nlerp(&[(a, 0.5), (Quaternion::identity(), 0.5)]);
+        assert!(head("a*0.5").angle_to(&expected) < 0.1);
+
+        let text = format!(
+            "{} @animation m {{ 0: a*0.7 & b*0.5; }} main {{ m; }}",
+            poses
+        );
+        assert_eq!(
+            diagnostics(&text),
+            [(
+                codes::LIMIT_EXCEEDED.to_string(),
+                "0: a*0.7 & b*0.5;".to_string()
+            )]
+        );
+    }
+
+    #[test]
+    fn morphs_are_written_by_their_japanese_names() {
+        let motion = MPLCompiler::new()
+            .compile_motion("@pose a { morph smile 0.8; morph wink_r 1; morph a 0.5; } main { a; }")
+            .unwrap();
+        let names = |key_frames: &[MPLKeyFrame]| {
+            let mut names: Vec<(String, String)> = key_frames
+                .iter()
+                .flat_map(|k| &k.morph_frames)
+                .map(|m| (m.name_en.clone(), m.name_jp.clone()))
+                .collect();
+            names.sort();
+            names
+        };
+        let expected = [("a", "あ"), ("smile", "笑い"), ("wink_r", "ウィンク右")]
+            .map(|(en, jp)| (en.to_string(), jp.to_string()));
+        assert_eq!(names(&motion.key_frames), expected);
+
+        let bytes = VMDWriter::from(motion).create_vmd().unwrap();
+        let read = crate::vmd::VMDReader::new(&bytes).read().unwrap();
+        assert_eq!(names(&read.key_frames), expected);
+    }
+
+    #[test]
+    fn move_distances_are_written_in_mmd_units() {
+        let motion = MPLCompiler::new()
+            .compile_motion(
+                "@pose a { center move up 10 cm; center move forward 0.4 m; \
+                 groove move up 2; } main { a; }",
+            )
+            .unwrap();
+        let bytes = VMDWriter::from(motion).create_vmd().unwrap();
+        let read = crate::vmd::VMDReader::new(&bytes).read().unwrap();
+        let position = |bone: &str| {
+            let mut frames = read.key_frames.iter().flat_map(|k| &k.bone_frames);
+            frames.find(|b| b.name_en() == bone).unwrap().position()
+        };
+
+        let center = position("center");
+        let expected = Vector3::new(
+            0.0,
+            distance_in_units(10.0, "cm").unwrap(),
+            -distance_in_units(0.4, "m").unwrap(),
+        );
+        assert!(center.sub(&expected).length() < 1e-5);
+        assert!((center.y - 0.1 * crate::MMD_UNITS_PER_METER).abs() < 1e-5);
+        assert!(
+            position("groove")
+                .sub(&Vector3::new(0.0, 2.0, 0.0))
+                .length()
+                < 1e-5
+        );
+    }
+
+    #[test]
+    fn key_easing_is_written_to_the_interpolation_block() {
+        let motion = MPLCompiler::new()
+            .compile_motion(
+                "@pose a { head turn left 10; } @pose b { head turn right 10; } \
+                 @animation m { 0: a; 0.5: b with ease_in_out; } main { m; }",
+            )
+            .unwrap();
+        let bytes = VMDWriter::from(motion).create_vmd().unwrap();
+
+        // Header and bone count, then 111-byte bone frames ending in the 64-byte block
+        let blocks: Vec<(u32, &[u8])> = bytes[54..54 + 2 * 111]
+            .chunks(111)
+            .map(|frame| {
+                let number = u32::from_le_bytes(frame[15..19].try_into().unwrap());
+                (number, &frame[47..])
+            })
+            .collect();
+        let block = |number: u32| blocks.iter().find(|(n, _)| *n == number).unwrap().1;
+
+        let ease = BezierCurve::preset("ease_in_out").unwrap();
+        assert_eq!(block(15), BoneInterpolation::uniform(ease).to_bytes());
+        // The first row holds x1, y1, x2 and y2 of the X, Y, Z and rotation curves
+        let row: Vec<u8> = [ease.x1, ease.y1, ease.x2, ease.y2]
+            .into_iter()
+            .flat_map(|value| [value; 4])
+            .collect();
+        assert_eq!(block(15)[..16], row);
+        let linear = BoneInterpolation::uniform(BezierCurve::LINEAR).to_bytes();
+        assert_eq!(block(0), linear);
+    }
+
+    #[test]
+    fn diagnostics_point_at_the_offending_text() {
+        let cases = [
+            ("hed turn left 10;", codes::UNKNOWN_BONE, "hed"),
+            ("head turn up 10;", codes::UNKNOWN_DIRECTION, "up"),
+            ("head turn left 100;", codes::LIMIT_EXCEEDED, "100"),
+        ];
+        for (statement, code, span) in cases {
+            let text = format!("@pose a {{ {} }} main {{ a; }}", statement);
+            assert_eq!(diagnostics(&text), [(code.to_string(), span.to_string())]);
+        }
+
+        let pose = "@pose a { head turn left 10; } ";
+        let cases = [
+            ("main { b; }", codes::UNKNOWN_REFERENCE, "b"),
+            ("main { a x 0; }", codes::INVALID_REPEAT, "0"),
+            (
+                "@pose a { neck bend forward 5; } main { a; }",
+                codes::DUPLICATE_NAME,
+                "a",
+            ),
+        ];
+        for (rest, code, span) in cases {
+            let text = format!("{}{}", pose, rest);
+            assert_eq!(diagnostics(&text), [(code.to_string(), span.to_string())]);
         }
 
-        if animations.is_empty() {
-            return Err("Main block must contain at least one animation reference".to_string());
+        assert_eq!(
+            diagnostics("@pose a { head turn left 10 } main { a; }"),
+            [
+                (
+                    codes::EMPTY_BLOCK.to_string(),
+                    "@pose a { head turn left 10 }".to_string()
+                ),
+                (codes::UNEXPECTED_TOKEN.to_string(), "}".to_string())
+            ]
+        );
+    }
+
+    #[test]
+    fn checked_program_keeps_the_compiler_rules() {
+        let profile = "[bones.head.turn.left]\naxis = [1.0, 0.0, 0.0]\nlimit = 80\n";
+        let db = crate::RigProfile::from_toml(profile)
+            .unwrap()
+            .to_database()
+            .unwrap();
+        let compilation = MPLCompiler::new()
+            .with_bone_database(db)
+            .check("@pose a { head turn left 30; } main { a; }");
+        assert!(!compilation.has_errors());
+
+        let key_frames = compilation.program.to_key_frames();
+        let rotation = key_frames[0].bone_frames[0].rotation();
+        let expected = Quaternion::from_axis_angle(Vector3::new(1.0, 0.0, 0.0), 30.0);
+        assert!(rotation.angular_distance(&expected) < 1e-3);
+    }
+
+    #[test]
+    fn conflicts_are_checked_at_the_target_frame_rate() {
+        let text = "@pose a { head turn left 10; } @pose b { head turn right 10; } \
+                    @animation w { 0.0: a; 0.016: b; } main { w; }";
+        let at_30 = MPLCompiler::new().compile_motion(text).unwrap_err();
+        assert_eq!(at_30[0].code, codes::FRAME_CONFLICT);
+
+        let motion = MPLCompiler::new()
+            .with_fps(60.0)
+            .compile_motion(text)
+            .unwrap();
+        assert_eq!(motion.fps, 60.0);
+        assert!(VMDWriter::from(motion).create_vmd().is_ok());
+    }
+
+    #[test]
+    fn pose_calls_are_checked_against_the_database() {
+        let template = "@pose look(dir, amount) { head turn $dir $amount; }";
+        for (call, code, at) in [
+            ("look(up, 20)", codes::UNKNOWN_DIRECTION, "up"),
+            ("look(left, 500)", codes::LIMIT_EXCEEDED, "500"),
+            ("look(left, -20)", codes::LIMIT_EXCEEDED, "-20"),
+            ("look(left)", codes::INVALID_ARGUMENT, "look(left)"),
+            ("look(20, left)", codes::INVALID_ARGUMENT, "20"),
+        ] {
+            let text = format!("{} @animation w {{ 0: {}; }} main {{ w; }}", template, call);
+            assert_eq!(diagnostics(&text), [(code.to_string(), at.to_string())]);
         }
+        let text = format!(
+            "{} @animation w {{ 0: look(left, 20); }} main {{ w; }}",
+            template
+        );
+        assert!(diagnostics(&text).is_empty());
+    }
+
+    #[test]
+    fn negative_amounts_are_rejected() {
+        assert_eq!(
+            diagnostics("@pose a { head turn left -30; } main { a; }"),
+            [(codes::LIMIT_EXCEEDED.to_string(), "-30".to_string())]
+        );
+        assert!(MPLPoseStatement::new("head", "turn", "left", -500.0).is_err());
+    }
 
-        Ok(animations)
+    #[test]
+    fn loop_ending_before_its_sequential_start_is_reported() {
+        let text = "@pose a { head turn left 10; } @animation w { 0: a; 2: a; } \
+                    main { w; w; w loop until 1.0; }";
+        assert_eq!(
+            diagnostics(text),
+            [(
+                codes::INVALID_REPEAT.to_string(),
+                "w loop until 1.0;".to_string()
+            )]
+        );
     }
 }
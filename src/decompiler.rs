@@ -0,0 +1,337 @@
+use std::collections::HashMap;
+
+use crate::{
+    bone::with_bone_db,
+    interpolation::BezierCurve,
+    mpl::{MPLBoneFrame, MPLKeyFrame},
+    pose::{MPLPose, MPLPoseStatement},
+    utils::{Quaternion, Vector3},
+    vmd::VMDReader,
+};
+
+/// Turns a motion back into an editable MPL script: one `@pose` per distinct keyframe,
+/// an `@animation` placing them on the timeline and a `main` block playing it.
+/// Bones are matched against `with_bone_db`; run inside `MPLCompiler::scope` for a
+/// custom rig.
+pub struct MPLDecompiler {
+    name: String,
+    tolerance: f32,
+    distance_tolerance: f32,
+}
+
+impl Default for MPLDecompiler {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MPLDecompiler {
+    pub fn new() -> Self {
+        Self {
+            name: "motion".to_string(),
+            tolerance: 1.0,
+            distance_tolerance: 0.05,
+        }
+    }
+
+    /// Name of the generated animation; poses are named `<name>_pose_<n>`. Characters
+    /// a name can't hold become `_`, so `"my motion"` is written as `my_motion`.
+    pub fn with_name(mut self, name: &str) -> Self {
+        self.name = identifier(name);
+        self
+    }
+
+    /// Largest per-bone difference in degrees for two keyframes to share a pose
+    pub fn with_tolerance(mut self, degrees: f32) -> Self {
+        self.tolerance = degrees;
+        self
+    }
+
+    /// Largest per-bone offset difference in MMD units for two keyframes to share a pose
+    pub fn with_distance_tolerance(mut self, distance: f32) -> Self {
+        self.distance_tolerance = distance;
+        self
+    }
+
+    pub fn decompile_vmd(
+        &self,
+        data: &[u8],
+        fps: f32,
+    ) -> Result<String, Box<dyn std::error::Error>> {
+        let motion = VMDReader::new(data).with_fps(fps).read()?;
+        Ok(self.decompile(&motion.key_frames))
+    }
+
+    pub fn decompile(&self, key_frames: &[MPLKeyFrame]) -> String {
+        // Regroup bone frames by time, files often list them bone by bone
+        let mut frames_by_time: Vec<(f32, Vec<MPLBoneFrame>)> = vec![];
+        let mut morph_frames = 0;
+        for key_frame in key_frames {
+            morph_frames += key_frame.morph_frames.len();
+            if key_frame.bone_frames.is_empty() {
+                continue;
+            }
+            match frames_by_time
+                .iter_mut()
+                .find(|(t, _)| *t == key_frame.time)
+            {
+                Some((_, frames)) => frames.extend(key_frame.bone_frames.iter().cloned()),
+                None => frames_by_time.push((key_frame.time, key_frame.bone_frames.clone())),
+            }
+        }
+        frames_by_time.sort_by(|a, b| a.0.total_cmp(&b.0));
+
+        let mut poses: Vec<(MPLPose, Vec<MPLBoneFrame>)> = vec![];
+        let mut timeline: Vec<(f32, String, BezierCurve)> = vec![];
+        let mut skipped = vec![];
+        for (time, frames) in frames_by_time {
+            let name = match poses
+                .iter()
+                .find(|(_, existing)| self.same_pose(existing, &frames))
+            {
+                Some((pose, _)) => pose.name.clone(),
+                None => {
+                    let name = format!("{}_pose_{}", self.name, poses.len() + 1);
+                    let pose =
+                        rest_statements(MPLPose::from_bone_frames(&name, frames.clone()), &frames);
+                    if pose.statements.is_empty() {
+                        skipped.push(format_number(time));
+                        continue;
+                    }
+                    poses.push((pose, frames.clone()));
+                    name
+                }
+            };
+            timeline.push((time, name, easing(&frames)));
+        }
+
+        let mut header = vec![format!(
+            "decompiled from {} keyframes into {} poses",
+            timeline.len() + skipped.len(),
+            poses.len()
+        )];
+        if !skipped.is_empty() {
+            header.push(format!(
+                "skipped keyframes without supported bones at {}",
+                skipped.join(", ")
+            ));
+        }
+        if morph_frames > 0 {
+            header.push(format!("skipped {} morph frames", morph_frames));
+        }
+
+        let mut script: Vec<String> = header.iter().map(|line| format!("// {}", line)).collect();
+        script.push(String::new());
+        for (pose, _) in &poses {
+            script.push(pose.to_block());
+            script.push(String::new());
+        }
+
+        if !timeline.is_empty() {
+            script.push(format!("@animation {} {{", self.name));
+            for (time, pose, curve) in &timeline {
+                script.push(format!(
+                    "    {}: {}{};",
+                    format_number(*time),
+                    pose,
+                    format_easing(curve)
+                ));
+            }
+            script.push("}".to_string());
+            script.push(String::new());
+            script.push(format!("main {{\n    {};\n}}", self.name));
+        }
+
+        script.join("\n")
+    }
+
+    /// Same bones, each rotated and moved within the tolerances
+    fn same_pose(&self, a: &[MPLBoneFrame], b: &[MPLBoneFrame]) -> bool {
+        let transforms: HashMap<String, (Quaternion, Vector3)> = a
+            .iter()
+            .map(|f| (f.name_en(), (f.rotation(), f.position())))
+            .collect();
+        a.len() == b.len()
+            && b.iter().all(|frame| {
+                transforms.get(&frame.name_en()).is_some_and(|(q, p)| {
+                    q.angle_to(&frame.rotation()) <= self.tolerance
+                        && p.sub(&frame.position()).length() <= self.distance_tolerance
+                })
+            })
+    }
+}
+
+/// Keep bones keyed at rest with a zero statement so they still get a keyframe
+fn rest_statements(mut pose: MPLPose, frames: &[MPLBoneFrame]) -> MPLPose {
+    for frame in frames {
+        let bone = frame.name_en();
+        if pose.statements.iter().any(|s| s.bone == bone) {
+            continue;
+        }
+        let rest = with_bone_db(|db| {
+            let mut actions = db.actions(&bone)?.to_vec();
+            actions.sort();
+            let action = actions.first()?.clone();
+            let mut directions = db.directions(&bone, &action)?.to_vec();
+            directions.sort();
+            Some((action, directions.first()?.clone()))
+        });
+        if let Some((action, direction)) = rest {
+            pose.statements.push(MPLPoseStatement {
+                bone,
+                action,
+                direction,
+                degrees: 0.0,
+            });
+        }
+    }
+    pose
+}
+
+/// The rotation curve most bones of a keyframe use
+fn easing(frames: &[MPLBoneFrame]) -> BezierCurve {
+    let mut counts: Vec<(BezierCurve, usize)> = vec![];
+    for frame in frames {
+        let curve = frame.interpolation().rotation;
+        match counts.iter_mut().find(|(c, _)| *c == curve) {
+            Some((_, count)) => *count += 1,
+            None => counts.push((curve, 1)),
+        }
+    }
+    counts
+        .into_iter()
+        .max_by_key(|(_, count)| *count)
+        .map(|(curve, _)| curve)
+        .unwrap_or_default()
+}
+
+fn format_easing(curve: &BezierCurve) -> String {
+    if *curve == BezierCurve::LINEAR {
+        return String::new();
+    }
+    if let Some(name) = BezierCurve::PRESETS
+        .iter()
+        .find(|name| BezierCurve::preset(name) == Some(*curve))
+    {
+        return format!(" with {}", name);
+    }
+    let points = [curve.x1, curve.y1, curve.x2, curve.y2]
+        .iter()
+        .map(|&b| {
+            // Prefer two decimals when they still round to the same byte
+            let short = (b as f32 / 1.27).round() / 100.0;
+            if (short * 127.0).round() as u8 == b {
+                format_number(short)
+            } else {
+                format_number(b as f32 / 127.0)
+            }
+        })
+        .collect::<Vec<String>>()
+        .join(", ");
+    format!(" with bezier({})", points)
+}
+
+/// Shortest decimal that still maps back to the same frame or curve byte
+fn format_number(value: f32) -> String {
+    let text = format!("{:.3}", value);
+    let text = text.trim_end_matches('0').trim_end_matches('.');
+    if text.is_empty() || text == "-0" {
+        "0".to_string()
+    } else {
+        text.to_string()
+    }
+}
+
+/// `name` made into an MPL identifier
+fn identifier(name: &str) -> String {
+    let name: String = name
+        .chars()
+        .map(|c| {
+            if c.is_alphanumeric() || c == '_' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    match name.chars().next() {
+        None => "motion".to_string(),
+        Some(c) if c.is_alphabetic() || c == '_' => name,
+        Some(_) => format!("_{}", name),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn center(y: f32) -> Vec<MPLBoneFrame> {
+        vec![MPLBoneFrame::new(
+            "center".to_string(),
+            "センター".to_string(),
+            Vector3::new(0.0, y, 0.0),
+            Quaternion::identity(),
+        )]
+    }
+
+    #[test]
+    fn keyframes_differing_only_in_position_keep_separate_poses() {
+        let key_frames = vec![
+            MPLKeyFrame::new(0.0, center(0.0), vec![]),
+            MPLKeyFrame::new(0.5, center(3.0), vec![]),
+            MPLKeyFrame::new(1.0, center(0.0), vec![]),
+        ];
+        let script = MPLDecompiler::new().decompile(&key_frames);
+
+        assert!(script.contains("into 2 poses"), "{}", script);
+        assert!(script.contains("center move up 3;"), "{}", script);
+        assert!(script.contains("1: motion_pose_1"), "{}", script);
+    }
+
+    /// Rotation of every bone keyed at each time
+    fn rotations(key_frames: &[MPLKeyFrame]) -> Vec<(f32, String, Quaternion)> {
+        let mut rotations: Vec<_> = key_frames
+            .iter()
+            .flat_map(|k| {
+                k.bone_frames
+                    .iter()
+                    .map(|b| (k.time, b.name_en(), b.rotation()))
+            })
+            .collect();
+        rotations.sort_by(|a, b| a.0.total_cmp(&b.0).then(a.1.cmp(&b.1)));
+        rotations
+    }
+
+    #[test]
+    fn decompiled_scripts_compile_to_the_same_motion() {
+        let compiler = crate::MPLCompiler::new();
+        let key_frames = compiler
+            .compile(
+                "@pose a { head turn left 30; neck bend forward 10; } \
+                 @pose b { head turn right 20; neck bend forward 10; } \
+                 @animation m { 0: a; 0.5: b; 1: a; } main { m; }",
+            )
+            .unwrap();
+
+        for name in ["my motion", "2nd", "", "ダンス"] {
+            let decompiler = MPLDecompiler::new().with_name(name);
+            let script = decompiler.decompile(&key_frames);
+            let compiled = compiler.compile(&script).unwrap_or_else(|diagnostics| {
+                panic!("{:?} in\n{}", diagnostics, script);
+            });
+
+            let (expected, actual) = (rotations(&key_frames), rotations(&compiled));
+            assert_eq!(expected.len(), actual.len(), "{}", script);
+            for ((time, bone, q), (actual_time, actual_bone, actual_q)) in
+                expected.iter().zip(&actual)
+            {
+                assert_eq!((time, bone), (actual_time, actual_bone));
+                assert!(
+                    q.angle_to(actual_q) <= decompiler.tolerance + 0.01,
+                    "{}",
+                    script
+                );
+            }
+        }
+    }
+}
@@ -0,0 +1,141 @@
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+use crate::lexer::Span;
+
+/// Stable codes identifying each kind of diagnostic
+pub mod codes {
+    // Syntax
+    pub const UNEXPECTED_CHARACTER: &str = "E0001";
+    pub const INVALID_NUMBER: &str = "E0002";
+    pub const UNEXPECTED_TOKEN: &str = "E0003";
+    pub const INVALID_BLOCK: &str = "E0004";
+    pub const UNCLOSED_BLOCK: &str = "E0005";
+    pub const UNTERMINATED_COMMENT: &str = "E0006";
+
+    // Statement validation
+    pub const UNKNOWN_BONE: &str = "E0101";
+    pub const UNKNOWN_ACTION: &str = "E0102";
+    pub const UNKNOWN_DIRECTION: &str = "E0103";
+    pub const LIMIT_EXCEEDED: &str = "E0104";
+    pub const EMPTY_BLOCK: &str = "E0105";
+    pub const NEGATIVE_TIME: &str = "E0106";
+    pub const INVALID_EASING: &str = "E0107";
+    pub const UNKNOWN_MORPH: &str = "E0108";
+    pub const INVALID_UNIT: &str = "E0109";
+    pub const INVALID_PROPERTY: &str = "E0110";
+    pub const INVALID_MIRROR: &str = "E0111";
+    pub const INVALID_BLEND: &str = "E0112";
+    pub const INVALID_REPEAT: &str = "E0113";
+    pub const INVALID_REACH: &str = "E0114";
+    pub const INVALID_LOOK: &str = "E0115";
+
+    // Name resolution
+    pub const DUPLICATE_NAME: &str = "E0201";
+    pub const UNKNOWN_REFERENCE: &str = "E0202";
+    pub const DUPLICATE_MAIN: &str = "E0203";
+    pub const INVALID_ARGUMENT: &str = "E0204";
+
+    // Output
+    pub const VMD_EXPORT: &str = "E0301";
+    pub const FRAME_CONFLICT: &str = "E0302";
+
+    // Warnings
+    pub const MISSING_MAIN: &str = "W0001";
+    pub const UNREACHABLE_TARGET: &str = "W0002";
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Diagnostic {
+    pub code: String,
+    pub severity: Severity,
+    pub message: String,
+    pub span: Span,
+    pub suggestions: Vec<String>,
+}
+
+impl Diagnostic {
+    pub fn error(code: &str, message: impl Into<String>, span: Span) -> Self {
+        Self {
+            code: code.to_string(),
+            severity: Severity::Error,
+            message: message.into(),
+            span,
+            suggestions: vec![],
+        }
+    }
+
+    pub fn warning(code: &str, message: impl Into<String>, span: Span) -> Self {
+        Self {
+            severity: Severity::Warning,
+            ..Self::error(code, message, span)
+        }
+    }
+
+    pub fn with_suggestion(mut self, suggestion: impl Into<String>) -> Self {
+        self.suggestions.push(suggestion.into());
+        self
+    }
+
+    pub fn with_suggestions(mut self, suggestions: impl IntoIterator<Item = String>) -> Self {
+        self.suggestions.extend(suggestions);
+        self
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let severity = match self.severity {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+        };
+        write!(
+            f,
+            "{}[{}] Line {}, column {}: {}",
+            severity, self.code, self.span.start.line, self.span.start.column, self.message
+        )
+    }
+}
+
+impl std::error::Error for Diagnostic {}
+
+/// "Did you mean" suggestions: candidates within a small edit distance of `name`
+pub fn similar_names<'a>(name: &str, candidates: impl IntoIterator<Item = &'a str>) -> Vec<String> {
+    let max_distance = (name.chars().count() / 3).max(1);
+    let mut matches: Vec<(usize, &str)> = candidates
+        .into_iter()
+        .map(|candidate| (edit_distance(name, candidate), candidate))
+        .filter(|(distance, _)| *distance <= max_distance)
+        .collect();
+    matches.sort();
+    matches
+        .into_iter()
+        .take(3)
+        .map(|(_, candidate)| format!("did you mean '{}'?", candidate))
+        .collect()
+}
+
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+
+    for (i, ca) in a.chars().enumerate() {
+        let mut current = vec![i + 1; b.len() + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let cost = if ca == *cb { 0 } else { 1 };
+            current[j + 1] = (previous[j] + cost)
+                .min(previous[j + 1] + 1)
+                .min(current[j] + 1);
+        }
+        previous = current;
+    }
+
+    previous[b.len()]
+}
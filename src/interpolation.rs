@@ -0,0 +1,268 @@
+use serde::{Deserialize, Serialize};
+
+/// Cubic Bezier easing from (0, 0) to (127, 127), with the two control points stored
+/// in VMD's 0..=127 byte range
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BezierCurve {
+    pub x1: u8,
+    pub y1: u8,
+    pub x2: u8,
+    pub y2: u8,
+}
+
+impl Default for BezierCurve {
+    fn default() -> Self {
+        Self::LINEAR
+    }
+}
+
+impl BezierCurve {
+    /// MMD's default straight-line curve
+    pub const LINEAR: Self = Self {
+        x1: 20,
+        y1: 20,
+        x2: 107,
+        y2: 107,
+    };
+
+    pub const PRESETS: [&'static str; 5] = ["linear", "ease_in", "ease_out", "ease_in_out", "step"];
+
+    /// Build a curve from control points in the 0..1 range, like CSS `cubic-bezier`
+    pub fn new(x1: f32, y1: f32, x2: f32, y2: f32) -> Result<Self, String> {
+        let to_byte = |value: f32| -> Result<u8, String> {
+            if (0.0..=1.0).contains(&value) {
+                Ok((value * 127.0).round() as u8)
+            } else {
+                Err(format!("Control point {} must be between 0 and 1", value))
+            }
+        };
+
+        Ok(Self {
+            x1: to_byte(x1)?,
+            y1: to_byte(y1)?,
+            x2: to_byte(x2)?,
+            y2: to_byte(y2)?,
+        })
+    }
+
+    /// Named easing. `step` holds the previous value for as long as a Bezier curve can
+    /// and then snaps to the new one.
+    pub fn preset(name: &str) -> Option<Self> {
+        let (x1, y1, x2, y2) = match name {
+            "linear" => return Some(Self::LINEAR),
+            "ease_in" => (0.42, 0.0, 1.0, 1.0),
+            "ease_out" => (0.0, 0.0, 0.58, 1.0),
+            "ease_in_out" => (0.42, 0.0, 0.58, 1.0),
+            "step" => (1.0, 0.0, 1.0, 0.0),
+            _ => return None,
+        };
+        Self::new(x1, y1, x2, y2).ok()
+    }
+
+    /// Eased progress at `progress` (0..1) through a transition, the way MMD samples
+    /// the curve: solve the curve's x for `progress` and return its y
+    pub fn evaluate(&self, progress: f32) -> f32 {
+        let point = |s: f32, p1: u8, p2: u8| {
+            let (p1, p2) = (p1 as f32 / 127.0, p2 as f32 / 127.0);
+            let t = 1.0 - s;
+            3.0 * t * t * s * p1 + 3.0 * t * s * s * p2 + s * s * s
+        };
+
+        let progress = progress.clamp(0.0, 1.0);
+        let (mut low, mut high) = (0.0, 1.0);
+        for _ in 0..24 {
+            let mid = (low + high) / 2.0;
+            if point(mid, self.x1, self.x2) < progress {
+                low = mid;
+            } else {
+                high = mid;
+            }
+        }
+        point((low + high) / 2.0, self.y1, self.y2)
+    }
+}
+
+/// Per-channel curves of a bone keyframe, for the transition into that keyframe
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+pub struct BoneInterpolation {
+    pub x: BezierCurve,
+    pub y: BezierCurve,
+    pub z: BezierCurve,
+    pub rotation: BezierCurve,
+    /// Bytes read from a file whose redundant rows differ from the encoded curves.
+    /// They are written back only while the curves still decode from them.
+    #[serde(skip)]
+    raw: Option<[u8; 64]>,
+}
+
+/// Two interpolations are equal when their curves are; the file bytes they came from
+/// don't matter
+impl PartialEq for BoneInterpolation {
+    fn eq(&self, other: &Self) -> bool {
+        self.curves() == other.curves()
+    }
+}
+
+impl Eq for BoneInterpolation {}
+
+impl BoneInterpolation {
+    /// The same curve on every channel
+    pub fn uniform(curve: BezierCurve) -> Self {
+        Self {
+            x: curve,
+            y: curve,
+            z: curve,
+            rotation: curve,
+            raw: None,
+        }
+    }
+
+    fn curves(&self) -> [BezierCurve; 4] {
+        [self.x, self.y, self.z, self.rotation]
+    }
+
+    /// The curves of the first 16-byte row
+    fn decode(bytes: &[u8; 64]) -> Self {
+        let curve = |i: usize| BezierCurve {
+            x1: bytes[i],
+            y1: bytes[4 + i],
+            x2: bytes[8 + i],
+            y2: bytes[12 + i],
+        };
+        Self {
+            x: curve(0),
+            y: curve(1),
+            z: curve(2),
+            rotation: curve(3),
+            raw: None,
+        }
+    }
+
+    /// Decode the 64-byte VMD layout written by `to_bytes`
+    pub fn from_bytes(bytes: &[u8; 64]) -> Self {
+        let mut interpolation = Self::decode(bytes);
+        if interpolation.encode() != *bytes {
+            interpolation.raw = Some(*bytes);
+        }
+        interpolation
+    }
+
+    /// The bytes this was read from while its curves are unchanged, so files round-trip
+    /// exactly; the encoded curves once any of them is edited
+    pub fn to_bytes(&self) -> [u8; 64] {
+        match self.raw {
+            Some(raw) if Self::decode(&raw) == *self => raw,
+            _ => self.encode(),
+        }
+    }
+
+    /// Encode the 64-byte VMD layout: one 16-byte row of
+    /// `X/Y/Z/R x1, X/Y/Z/R y1, X/Y/Z/R x2, X/Y/Z/R y2`,
+    /// followed by three copies each shifted left by one more byte.
+    fn encode(&self) -> [u8; 64] {
+        let channels = [self.x, self.y, self.z, self.rotation];
+        let mut row = [0u8; 16];
+        for (i, curve) in channels.iter().enumerate() {
+            row[i] = curve.x1;
+            row[4 + i] = curve.y1;
+            row[8 + i] = curve.x2;
+            row[12 + i] = curve.y2;
+        }
+
+        let mut bytes = [0u8; 64];
+        for shift in 0..4 {
+            let len = 16 - shift;
+            bytes[shift * 16..shift * 16 + len].copy_from_slice(&row[shift..]);
+        }
+        bytes
+    }
+}
+
+/// Per-channel curves of a camera keyframe
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CameraInterpolation {
+    pub x: BezierCurve,
+    pub y: BezierCurve,
+    pub z: BezierCurve,
+    pub rotation: BezierCurve,
+    pub distance: BezierCurve,
+    pub fov: BezierCurve,
+}
+
+impl CameraInterpolation {
+    /// The same curve on every channel
+    pub fn uniform(curve: BezierCurve) -> Self {
+        Self {
+            x: curve,
+            y: curve,
+            z: curve,
+            rotation: curve,
+            distance: curve,
+            fov: curve,
+        }
+    }
+
+    /// Encode the 24-byte VMD layout: `x1, x2, y1, y2` for X, Y, Z, rotation, distance, fov
+    pub fn to_bytes(&self) -> [u8; 24] {
+        let channels = [
+            self.x,
+            self.y,
+            self.z,
+            self.rotation,
+            self.distance,
+            self.fov,
+        ];
+        let mut bytes = [0u8; 24];
+        for (i, curve) in channels.iter().enumerate() {
+            bytes[i * 4..i * 4 + 4].copy_from_slice(&[curve.x1, curve.x2, curve.y1, curve.y2]);
+        }
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8; 24]) -> Self {
+        let curve = |i: usize| BezierCurve {
+            x1: bytes[i * 4],
+            x2: bytes[i * 4 + 1],
+            y1: bytes[i * 4 + 2],
+            y2: bytes[i * 4 + 3],
+        };
+        Self {
+            x: curve(0),
+            y: curve(1),
+            z: curve(2),
+            rotation: curve(3),
+            distance: curve(4),
+            fov: curve(5),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bone_interpolation_round_trips_file_bytes() {
+        let curve = BezierCurve::preset("ease_in_out").unwrap();
+        let mut bytes = BoneInterpolation::uniform(curve).to_bytes();
+        // MMD files often carry other values in the redundant rows
+        bytes[63] = 1;
+        bytes[20] = 99;
+
+        let read = BoneInterpolation::from_bytes(&bytes);
+        assert_eq!(read.to_bytes(), bytes);
+        assert_eq!(read, BoneInterpolation::uniform(curve));
+    }
+
+    #[test]
+    fn edited_bone_interpolation_is_encoded() {
+        let mut bytes = BoneInterpolation::default().to_bytes();
+        bytes[63] = 1;
+
+        let mut read = BoneInterpolation::from_bytes(&bytes);
+        read.rotation = BezierCurve::preset("ease_out").unwrap();
+        let written = BoneInterpolation::from_bytes(&read.to_bytes());
+        assert_eq!(written.rotation, read.rotation);
+        assert_eq!(written.to_bytes(), read.to_bytes());
+    }
+}
@@ -0,0 +1,231 @@
+use std::collections::BTreeMap;
+
+use crate::{
+    interpolation::{BezierCurve, BoneInterpolation},
+    mpl::{MPLBoneFrame, MPLKeyFrame, MPLMorphFrame},
+    utils::{Quaternion, Vector3},
+};
+
+/// Compose an additive layer onto a base clip. A bone or morph keyed by both gets a
+/// keyframe at every time either one keys it, with the layer's rotation applied on top
+/// of the base rotation, positions added and weights added and kept within 0..=1.
+/// Everything else passes through untouched. Keyframes come back unsorted, one per bone or morph key.
+pub(crate) fn add_layer(base: &[MPLKeyFrame], layer: &[MPLKeyFrame]) -> Vec<MPLKeyFrame> {
+    let mut key_frames = vec![];
+
+    let base_bones = tracks(base, |k| &k.bone_frames, |f| f.name_en());
+    let mut layer_bones = tracks(layer, |k| &k.bone_frames, |f| f.name_en());
+    for (name, base_track) in base_bones {
+        let Some(layer_track) = layer_bones.remove(&name) else {
+            key_frames.extend(bone_keys(&base_track));
+            continue;
+        };
+
+        for time in key_times(&base_track, &layer_track) {
+            let (base_position, base_rotation) = sample_bone(&base_track, time);
+            let (layer_position, layer_rotation) = sample_bone(&layer_track, time);
+            let interpolation = key_at(&base_track, time)
+                .or(key_at(&layer_track, time))
+                .map(|frame| frame.interpolation())
+                .unwrap_or(BoneInterpolation::uniform(BezierCurve::LINEAR));
+
+            let frame = MPLBoneFrame::new(
+                name.clone(),
+                base_track[0].1.name_jp(),
+                Vector3::new(
+                    base_position.x + layer_position.x,
+                    base_position.y + layer_position.y,
+                    base_position.z + layer_position.z,
+                ),
+                base_rotation.multiply(&layer_rotation),
+            )
+            .with_interpolation(interpolation);
+            key_frames.push(MPLKeyFrame::new(time, vec![frame], vec![]));
+        }
+    }
+    for track in layer_bones.values() {
+        key_frames.extend(bone_keys(track));
+    }
+
+    let base_morphs = tracks(base, |k| &k.morph_frames, |f| f.name_en.clone());
+    let mut layer_morphs = tracks(layer, |k| &k.morph_frames, |f| f.name_en.clone());
+    for (name, base_track) in base_morphs {
+        let Some(layer_track) = layer_morphs.remove(&name) else {
+            key_frames.extend(morph_keys(&base_track));
+            continue;
+        };
+
+        for time in key_times(&base_track, &layer_track) {
+            let weight = sample_morph(&base_track, time) + sample_morph(&layer_track, time);
+            let frame = MPLMorphFrame {
+                weight: weight.clamp(0.0, 1.0),
+                ..base_track[0].1.clone()
+            };
+            key_frames.push(MPLKeyFrame::new(time, vec![], vec![frame]));
+        }
+    }
+    for track in layer_morphs.values() {
+        key_frames.extend(morph_keys(track));
+    }
+
+    key_frames
+}
+
+/// Keys of one bone or morph in time order, one per time
+type Track<'a, T> = Vec<(f32, &'a T)>;
+
+fn tracks<'a, T>(
+    key_frames: &'a [MPLKeyFrame],
+    frames: impl Fn(&'a MPLKeyFrame) -> &'a Vec<T>,
+    name: impl Fn(&T) -> String,
+) -> BTreeMap<String, Track<'a, T>> {
+    let mut tracks: BTreeMap<String, Track<'a, T>> = BTreeMap::new();
+    for key_frame in key_frames {
+        for frame in frames(key_frame) {
+            tracks
+                .entry(name(frame))
+                .or_default()
+                .push((key_frame.time, frame));
+        }
+    }
+    for track in tracks.values_mut() {
+        // Of keys on the same time, as parallel entries can give, the later one wins
+        track.sort_by(|a, b| a.0.total_cmp(&b.0));
+        track.dedup_by(|later, earlier| {
+            let same = (later.0 - earlier.0).abs() < f32::EPSILON;
+            if same {
+                *earlier = *later;
+            }
+            same
+        });
+    }
+    tracks
+}
+
+/// Every time either track keys, without duplicates
+fn key_times<A, B>(a: &Track<A>, b: &Track<B>) -> Vec<f32> {
+    let mut times: Vec<f32> = a.iter().map(|k| k.0).chain(b.iter().map(|k| k.0)).collect();
+    times.sort_by(f32::total_cmp);
+    times.dedup_by(|a, b| (*a - *b).abs() < f32::EPSILON);
+    times
+}
+
+fn key_at<'a, T>(track: &Track<'a, T>, time: f32) -> Option<&'a T> {
+    track
+        .iter()
+        .find(|(t, _)| (t - time).abs() < f32::EPSILON)
+        .map(|(_, frame)| *frame)
+}
+
+/// The two keys around `time` and the linear progress between them. Before the first
+/// key and after the last the track holds its value.
+fn segment<'a, T>(track: &Track<'a, T>, time: f32) -> (&'a T, &'a T, f32) {
+    match track.iter().position(|(t, _)| *t >= time - f32::EPSILON) {
+        None => {
+            let last = track[track.len() - 1].1;
+            (last, last, 1.0)
+        }
+        Some(0) => (track[0].1, track[0].1, 1.0),
+        Some(i) => {
+            let (start, from) = track[i - 1];
+            let (end, to) = track[i];
+            let progress = if end > start {
+                (time - start) / (end - start)
+            } else {
+                1.0
+            };
+            (from, to, progress)
+        }
+    }
+}
+
+/// Position and rotation of a bone at `time`, eased by the curves of the next key
+fn sample_bone(track: &Track<MPLBoneFrame>, time: f32) -> (Vector3, Quaternion) {
+    let (from, to, progress) = segment(track, time);
+    let curves = to.interpolation();
+    let axis = |a: f32, b: f32, curve: BezierCurve| a + (b - a) * curve.evaluate(progress);
+
+    let (a, b) = (from.position(), to.position());
+    let position = Vector3::new(
+        axis(a.x, b.x, curves.x),
+        axis(a.y, b.y, curves.y),
+        axis(a.z, b.z, curves.z),
+    );
+    let rotation = from
+        .rotation()
+        .slerp(&to.rotation(), curves.rotation.evaluate(progress));
+    (position, rotation)
+}
+
+/// Morph weights change linearly between keys
+fn sample_morph(track: &Track<MPLMorphFrame>, time: f32) -> f32 {
+    let (from, to, progress) = segment(track, time);
+    from.weight + (to.weight - from.weight) * progress
+}
+
+fn bone_keys(track: &Track<MPLBoneFrame>) -> Vec<MPLKeyFrame> {
+    track
+        .iter()
+        .map(|(time, frame)| MPLKeyFrame::new(*time, vec![(*frame).clone()], vec![]))
+        .collect()
+}
+
+fn morph_keys(track: &Track<MPLMorphFrame>) -> Vec<MPLKeyFrame> {
+    track
+        .iter()
+        .map(|(time, frame)| MPLKeyFrame::new(*time, vec![], vec![(*frame).clone()]))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn head(time: f32, degrees: f32) -> MPLKeyFrame {
+        let rotation = Quaternion::from_axis_angle(Vector3::new(0.0, 1.0, 0.0), degrees);
+        let frame = MPLBoneFrame::new(
+            "head".to_string(),
+            "頭".to_string(),
+            Vector3::new(0.0, 0.0, 0.0),
+            rotation,
+        );
+        MPLKeyFrame::new(time, vec![frame], vec![])
+    }
+
+    fn smile(time: f32, weight: f32) -> MPLKeyFrame {
+        let frame = MPLMorphFrame {
+            name_en: "smile".to_string(),
+            name_jp: "笑い".to_string(),
+            weight,
+        };
+        MPLKeyFrame::new(time, vec![], vec![frame])
+    }
+
+    #[test]
+    fn layered_morph_weights_stay_between_zero_and_one() {
+        let base = [smile(0.0, 0.8), smile(1.0, 0.2)];
+        let layer = [smile(0.0, 0.5), smile(1.0, -0.5)];
+        let mut composed = add_layer(&base, &layer);
+        composed.sort_by(|a, b| a.time.total_cmp(&b.time));
+
+        let weights: Vec<f32> = composed.iter().map(|k| k.morph_frames[0].weight).collect();
+        assert_eq!(weights, [1.0, 0.0]);
+    }
+
+    #[test]
+    fn keys_on_the_same_time_compose_without_nan() {
+        let base = [head(0.0, 0.0), head(1.0, 10.0), head(1.0, 20.0)];
+        let layer = [head(0.5, 5.0), head(2.0, 5.0)];
+        let composed = add_layer(&base, &layer);
+
+        let times: Vec<f32> = composed.iter().map(|k| k.time).collect();
+        assert_eq!(times, [0.0, 0.5, 1.0, 2.0]);
+        for key_frame in &composed {
+            let q = key_frame.bone_frames[0].rotation();
+            assert!([q.x, q.y, q.z, q.w].iter().all(|v| v.is_finite()));
+        }
+        // The later of the two keys at 1s wins, the layer holding 5 degrees
+        let expected = Quaternion::from_axis_angle(Vector3::new(0.0, 1.0, 0.0), 25.0);
+        assert!(composed[2].bone_frames[0].rotation().dot(&expected) > 0.9999);
+    }
+}
@@ -0,0 +1,277 @@
+use serde::{Deserialize, Serialize};
+use std::iter::Peekable;
+use std::str::CharIndices;
+
+use crate::diagnostic::{codes, Diagnostic};
+
+/// A location in the source text. Lines and columns are 1-based, offsets are byte offsets.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Position {
+    pub line: u32,
+    pub column: u32,
+    pub offset: u32,
+}
+
+impl Default for Position {
+    fn default() -> Self {
+        Self {
+            line: 1,
+            column: 1,
+            offset: 0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Span {
+    pub start: Position,
+    pub end: Position,
+}
+
+impl Span {
+    pub fn new(start: Position, end: Position) -> Self {
+        Self { start, end }
+    }
+
+    /// Span covering from the start of `self` to the end of `other`
+    pub fn to(&self, other: &Span) -> Self {
+        Self::new(self.start, other.end)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum TokenKind {
+    Ident(String),
+    Number(f32),
+    At,
+    LBrace,
+    RBrace,
+    Colon,
+    Semicolon,
+    Ampersand,
+    LParen,
+    RParen,
+    Comma,
+    Dollar,
+    Equals,
+    Star,
+    Plus,
+    Eof,
+}
+
+impl TokenKind {
+    pub fn describe(&self) -> String {
+        match self {
+            TokenKind::Ident(name) => format!("'{}'", name),
+            TokenKind::Number(value) => format!("'{}'", value),
+            TokenKind::At => "'@'".to_string(),
+            TokenKind::LBrace => "'{'".to_string(),
+            TokenKind::RBrace => "'}'".to_string(),
+            TokenKind::Colon => "':'".to_string(),
+            TokenKind::Semicolon => "';'".to_string(),
+            TokenKind::Ampersand => "'&'".to_string(),
+            TokenKind::LParen => "'('".to_string(),
+            TokenKind::RParen => "')'".to_string(),
+            TokenKind::Comma => "','".to_string(),
+            TokenKind::Dollar => "'$'".to_string(),
+            TokenKind::Equals => "'='".to_string(),
+            TokenKind::Star => "'*'".to_string(),
+            TokenKind::Plus => "'+'".to_string(),
+            TokenKind::Eof => "end of file".to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Token {
+    pub kind: TokenKind,
+    pub span: Span,
+}
+
+pub struct Lexer<'a> {
+    source: &'a str,
+    chars: Peekable<CharIndices<'a>>,
+    line: u32,
+    column: u32,
+}
+
+impl<'a> Lexer<'a> {
+    pub fn new(source: &'a str) -> Self {
+        Self {
+            source,
+            chars: source.char_indices().peekable(),
+            line: 1,
+            column: 1,
+        }
+    }
+
+    /// Tokenize the whole source. Invalid characters are reported and skipped so that
+    /// parsing can continue.
+    pub fn tokenize(mut self) -> (Vec<Token>, Vec<Diagnostic>) {
+        let mut tokens = Vec::new();
+        let mut diagnostics = Vec::new();
+
+        loop {
+            if let Err(diagnostic) = self.skip_trivia() {
+                diagnostics.push(diagnostic);
+            }
+            let start = self.position();
+
+            let c = match self.peek() {
+                Some(c) => c,
+                None => {
+                    tokens.push(Token {
+                        kind: TokenKind::Eof,
+                        span: Span::new(start, start),
+                    });
+                    return (tokens, diagnostics);
+                }
+            };
+
+            let kind = match c {
+                '@' => self.single(TokenKind::At),
+                '{' => self.single(TokenKind::LBrace),
+                '}' => self.single(TokenKind::RBrace),
+                ':' => self.single(TokenKind::Colon),
+                ';' => self.single(TokenKind::Semicolon),
+                '&' => self.single(TokenKind::Ampersand),
+                '(' => self.single(TokenKind::LParen),
+                ')' => self.single(TokenKind::RParen),
+                ',' => self.single(TokenKind::Comma),
+                '$' => self.single(TokenKind::Dollar),
+                '=' => self.single(TokenKind::Equals),
+                '*' => self.single(TokenKind::Star),
+                '+' => self.single(TokenKind::Plus),
+                c if c.is_ascii_digit() || c == '.' || c == '-' => match self.number(start) {
+                    Ok(kind) => kind,
+                    Err(diagnostic) => {
+                        diagnostics.push(diagnostic);
+                        TokenKind::Number(0.0)
+                    }
+                },
+                c if c.is_alphabetic() || c == '_' => self.ident(),
+                c => {
+                    self.bump();
+                    diagnostics.push(Diagnostic::error(
+                        codes::UNEXPECTED_CHARACTER,
+                        format!("Unexpected character '{}'", c),
+                        Span::new(start, self.position()),
+                    ));
+                    continue;
+                }
+            };
+
+            tokens.push(Token {
+                kind,
+                span: Span::new(start, self.position()),
+            });
+        }
+    }
+
+    fn position(&mut self) -> Position {
+        let offset = self
+            .chars
+            .peek()
+            .map(|&(i, _)| i)
+            .unwrap_or(self.source.len());
+        Position {
+            line: self.line,
+            column: self.column,
+            offset: offset as u32,
+        }
+    }
+
+    fn peek(&mut self) -> Option<char> {
+        self.chars.peek().map(|&(_, c)| c)
+    }
+
+    fn bump(&mut self) -> Option<char> {
+        let (_, c) = self.chars.next()?;
+        if c == '\n' {
+            self.line += 1;
+            self.column = 1;
+        } else {
+            self.column += 1;
+        }
+        Some(c)
+    }
+
+    fn single(&mut self, kind: TokenKind) -> TokenKind {
+        self.bump();
+        kind
+    }
+
+    fn rest(&mut self) -> &'a str {
+        let offset = self.position().offset as usize;
+        &self.source[offset..]
+    }
+
+    /// Skip whitespace, `// line` comments and `/* block */` comments
+    fn skip_trivia(&mut self) -> Result<(), Diagnostic> {
+        loop {
+            while matches!(self.peek(), Some(c) if c.is_whitespace()) {
+                self.bump();
+            }
+
+            if self.rest().starts_with("//") {
+                while matches!(self.peek(), Some(c) if c != '\n') {
+                    self.bump();
+                }
+            } else if self.rest().starts_with("/*") {
+                let start = self.position();
+                self.bump();
+                self.bump();
+                while !self.rest().starts_with("*/") {
+                    if self.bump().is_none() {
+                        return Err(Diagnostic::error(
+                            codes::UNTERMINATED_COMMENT,
+                            "Unterminated block comment",
+                            Span::new(start, self.position()),
+                        )
+                        .with_suggestion("add '*/' to close the comment"));
+                    }
+                }
+                self.bump();
+                self.bump();
+            } else {
+                return Ok(());
+            }
+        }
+    }
+
+    fn number(&mut self, start: Position) -> Result<TokenKind, Diagnostic> {
+        let mut text = String::new();
+        if self.peek() == Some('-') {
+            text.push('-');
+            self.bump();
+        }
+        while let Some(c) = self.peek() {
+            if c.is_ascii_digit() || c == '.' {
+                text.push(c);
+                self.bump();
+            } else {
+                break;
+            }
+        }
+        text.parse::<f32>().map(TokenKind::Number).map_err(|_| {
+            Diagnostic::error(
+                codes::INVALID_NUMBER,
+                format!("Invalid number '{}'", text),
+                Span::new(start, self.position()),
+            )
+        })
+    }
+
+    fn ident(&mut self) -> TokenKind {
+        let mut text = String::new();
+        while let Some(c) = self.peek() {
+            if c.is_alphanumeric() || c == '_' {
+                text.push(c);
+                self.bump();
+            } else {
+                break;
+            }
+        }
+        TokenKind::Ident(text)
+    }
+}
@@ -1,16 +1,50 @@
 mod animation;
+pub mod ast;
 mod bone;
+mod camera;
 mod compiler;
+mod decompiler;
+mod diagnostic;
+mod interpolation;
+mod layer;
+mod lexer;
+mod lighting;
+mod look;
 mod mpl;
+mod parser;
+mod pmx;
 mod pose;
+mod reach;
+mod rig;
+mod skeleton;
 mod utils;
 mod vmd;
+mod vpd;
 
 pub use bone::*;
-pub use compiler::MPLCompiler;
-use mpl::MPLBoneFrame;
-use pose::MPLPose;
-pub use vmd::VMDWriter;
+pub use camera::{MPLCamera, MPLCameraStatement, CAMERA_MODEL_NAME};
+pub use compiler::{MPLCompilation, MPLCompiler, MPLProgram};
+pub use decompiler::MPLDecompiler;
+pub use diagnostic::{codes, Diagnostic, Severity};
+pub use interpolation::{BezierCurve, BoneInterpolation, CameraInterpolation};
+pub use lexer::{Position, Span};
+pub use lighting::{MPLLight, MPLLightStatement, MPLShadow, MPLShadowStatement};
+pub use look::LOOK_WEIGHTS;
+pub use mpl::{
+    MPLBoneFrame, MPLCameraFrame, MPLIkFrame, MPLIkState, MPLKeyFrame, MPLLightFrame,
+    MPLMorphFrame, MPLShadowFrame,
+};
+pub use pmx::{PMXBone, PMXIk, PMXIkLink, PMXModel, PMXRig, PMXTail};
+pub use pose::{
+    MPLIkStatement, MPLLookStatement, MPLMorphStatement, MPLPose, MPLPoseStatement,
+    MPLReachStatement, MPLReachTarget,
+};
+pub use reach::{limbs, REACH_TOLERANCE};
+pub use rig::{RigJoint, RigProfile, RigRule};
+pub use skeleton::{JointTransform, Skeleton, SkeletonJoint};
+pub use utils::{Quaternion, Vector3};
+pub use vmd::{FrameConflict, FrameConflictError, VMDMotion, VMDReader, VMDWriter, DEFAULT_FPS};
+pub use vpd::VPDPose;
 
 use wasm_bindgen::prelude::*;
 
@@ -19,6 +53,12 @@ pub struct WasmMPLCompiler {
     compiler: MPLCompiler,
 }
 
+impl Default for WasmMPLCompiler {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 #[wasm_bindgen]
 impl WasmMPLCompiler {
     #[wasm_bindgen(constructor)]
@@ -28,34 +68,167 @@ impl WasmMPLCompiler {
         }
     }
 
+    /// Compile against a rig profile (TOML or JSON text, see `RigProfile`) instead of
+    /// the built-in bone rules, until `reset_rig_profile` is called
+    #[wasm_bindgen]
+    pub fn load_rig_profile(&mut self, profile: &str) -> Result<(), JsValue> {
+        let db = RigProfile::parse(profile)
+            .and_then(|profile| profile.to_database())
+            .map_err(|e| JsValue::from_str(&e))?;
+        self.compiler = MPLCompiler::new().with_bone_database(db);
+        Ok(())
+    }
+
+    /// Compile against a rig profile generated from a PMX model. Returns the `PMXRig`
+    /// (`{ profile, missing_bones, missing_iks }`) so the caller can show what is missing.
+    #[wasm_bindgen]
+    pub fn load_pmx(&mut self, pmx: &[u8]) -> Result<JsValue, JsValue> {
+        let rig = PMXModel::parse(pmx)
+            .map_err(|e| JsValue::from_str(&e.to_string()))?
+            .to_rig();
+        let db = rig
+            .profile
+            .to_database()
+            .map_err(|e| JsValue::from_str(&e))?;
+        self.compiler = MPLCompiler::new().with_bone_database(db);
+        serde_wasm_bindgen::to_value(&rig).map_err(|e| JsValue::from_str(&e.to_string()))
+    }
+
     #[wasm_bindgen]
-    pub fn compile(&self, script: &str) -> Result<Vec<u8>, String> {
-        let key_frames = self.compiler.compile(script)?;
-        let vmd = VMDWriter::new(key_frames);
-        match vmd.create_vmd() {
-            Ok(vmd) => Ok(vmd),
-            Err(e) => Err(e.to_string()),
+    pub fn reset_rig_profile(&mut self) {
+        self.compiler = MPLCompiler::new();
+    }
+
+    /// Compile a script to VMD bytes at `fps` (30 when omitted), which must be positive.
+    /// `fk_legs` keeps the leg IK off so bent legs show up. Errors are thrown as an array
+    /// of `Diagnostic` objects (`{ code, severity, message, span: { start, end },
+    /// suggestions }`) rather than the message string 0.2 threw.
+    #[wasm_bindgen]
+    pub fn compile(
+        &self,
+        script: &str,
+        fps: Option<f32>,
+        fk_legs: Option<bool>,
+    ) -> Result<Vec<u8>, JsValue> {
+        let motion = self
+            .compiler
+            .clone()
+            .with_fps(fps.unwrap_or(DEFAULT_FPS))
+            .compile_motion(script)
+            .map_err(|d| to_js(&d))?;
+        let mut vmd = VMDWriter::from(motion);
+        if fk_legs.unwrap_or(false) {
+            vmd = self.compiler.scope(|| vmd.with_fk_legs());
         }
+        vmd.create_vmd().map_err(|e| to_js(&export_diagnostics(e)))
+    }
+
+    /// Compile only the camera work of a script, as the separate camera VMD MMD loads
+    /// without a model. Errors are thrown like `compile`'s.
+    #[wasm_bindgen]
+    pub fn compile_camera(&self, script: &str, fps: Option<f32>) -> Result<Vec<u8>, JsValue> {
+        let motion = self
+            .compiler
+            .clone()
+            .with_fps(fps.unwrap_or(DEFAULT_FPS))
+            .compile_motion(script)
+            .map_err(|d| to_js(&d))?;
+        let (_, camera) = VMDWriter::from(motion).split_camera();
+        camera
+            .create_vmd()
+            .map_err(|e| to_js(&export_diagnostics(e)))
+    }
+
+    /// Every error and warning in a script, for editor squiggles
+    #[wasm_bindgen]
+    pub fn check(&self, script: &str) -> JsValue {
+        to_js(&self.compiler.check(script).diagnostics)
     }
 
     #[wasm_bindgen]
     pub fn reverse_compile(&self, name: &str, frames: Vec<MPLBoneFrame>) -> String {
-        MPLPose::from_bone_frames(name, frames).to_string()
+        self.compiler
+            .scope(|| MPLPose::from_bone_frames(name, frames).to_string())
+    }
+
+    /// Turn a whole VMD motion into an MPL script with shared poses and an animation
+    #[wasm_bindgen]
+    pub fn decompile(&self, vmd: &[u8], name: &str, fps: Option<f32>) -> Result<String, JsValue> {
+        self.compiler.scope(|| {
+            MPLDecompiler::new()
+                .with_name(name)
+                .decompile_vmd(vmd, fps.unwrap_or(DEFAULT_FPS))
+                .map_err(|e| JsValue::from_str(&e.to_string()))
+        })
+    }
+
+    /// VPD bytes of one pose of a script. Errors are thrown like `compile`'s.
+    #[wasm_bindgen]
+    pub fn export_vpd(
+        &self,
+        script: &str,
+        pose: &str,
+        model_name: &str,
+    ) -> Result<Vec<u8>, JsValue> {
+        let compilation = self.compiler.check(script);
+        if compilation.has_errors() {
+            return Err(to_js(&compilation.diagnostics));
+        }
+        match compilation.program.poses.get(pose) {
+            Some(pose) => Ok(self.compiler.scope(|| pose.to_vpd(model_name))),
+            None => Err(to_js(&[Diagnostic::error(
+                codes::UNKNOWN_REFERENCE,
+                format!("Unknown pose '{}'", pose),
+                Span::default(),
+            )])),
+        }
+    }
+
+    /// Model-space joint positions and rotations of one pose of a script, as an object
+    /// of `{ position, rotation }` by bone. Errors are thrown like `compile`'s.
+    #[wasm_bindgen]
+    pub fn evaluate_pose(&self, script: &str, pose: &str) -> Result<JsValue, JsValue> {
+        let compilation = self.compiler.check(script);
+        if compilation.has_errors() {
+            return Err(to_js(&compilation.diagnostics));
+        }
+        match compilation.program.poses.get(pose) {
+            Some(pose) => {
+                let transforms = self.bone_db(|db| db.skeleton().evaluate_pose(pose));
+                serde_wasm_bindgen::to_value(&transforms)
+                    .map_err(|e| JsValue::from_str(&e.to_string()))
+            }
+            None => Err(to_js(&[Diagnostic::error(
+                codes::UNKNOWN_REFERENCE,
+                format!("Unknown pose '{}'", pose),
+                Span::default(),
+            )])),
+        }
+    }
+
+    /// Turn a VPD file into an MPL pose, like `reverse_compile`
+    #[wasm_bindgen]
+    pub fn import_vpd(&self, name: &str, vpd: &[u8]) -> Result<String, JsValue> {
+        self.compiler.scope(|| {
+            MPLPose::from_vpd(name, vpd)
+                .map(|pose| pose.to_string())
+                .map_err(|e| JsValue::from_str(&e))
+        })
     }
 
     #[wasm_bindgen]
     pub fn get_all_bones(&self) -> Vec<String> {
-        with_bone_db(|db| db.bones().to_vec())
+        self.bone_db(|db| db.bones().to_vec())
     }
 
     #[wasm_bindgen]
     pub fn get_bone_actions(&self, bone: &str) -> Option<Vec<String>> {
-        with_bone_db(|db| db.actions(bone).map(|actions| actions.to_vec()))
+        self.bone_db(|db| db.actions(bone).map(|actions| actions.to_vec()))
     }
 
     #[wasm_bindgen]
     pub fn get_bone_directions(&self, bone: &str, action: &str) -> Option<Vec<String>> {
-        with_bone_db(|db| {
+        self.bone_db(|db| {
             db.directions(bone, action)
                 .map(|directions| directions.to_vec())
         })
@@ -63,16 +236,85 @@ impl WasmMPLCompiler {
 
     #[wasm_bindgen]
     pub fn get_bone_degree_limit(&self, bone: &str, action: &str, direction: &str) -> Option<f32> {
-        with_bone_db(|db| db.get_rule(bone, action, direction).map(|rule| rule.limit))
+        self.bone_db(|db| db.get_rule(bone, action, direction).map(|rule| rule.limit))
     }
 
     #[wasm_bindgen]
     pub fn get_bone_japanese_name(&self, bone: &str) -> Option<String> {
-        with_bone_db(|db| db.japanese_name(bone).map(|name| name.to_string()))
+        self.bone_db(|db| db.japanese_name(bone).map(|name| name.to_string()))
     }
 
     #[wasm_bindgen]
     pub fn get_bone_english_name(&self, bone: &str) -> Option<String> {
-        with_bone_db(|db| db.english_name(bone).map(|name| name.to_string()))
+        self.bone_db(|db| db.english_name(bone).map(|name| name.to_string()))
+    }
+
+    #[wasm_bindgen]
+    pub fn get_all_morphs(&self) -> Vec<String> {
+        self.bone_db(|db| db.morphs().to_vec())
+    }
+
+    #[wasm_bindgen]
+    pub fn get_morph_japanese_name(&self, morph: &str) -> Option<String> {
+        self.bone_db(|db| db.morph_japanese_name(morph).map(|name| name.to_string()))
+    }
+}
+
+impl WasmMPLCompiler {
+    /// `with_bone_db` against the loaded rig profile, if any
+    fn bone_db<T>(&self, f: impl FnOnce(&BoneActionDatabase) -> T) -> T {
+        self.compiler.scope(|| with_bone_db(f))
+    }
+}
+
+fn export_diagnostics(error: Box<dyn std::error::Error>) -> Vec<Diagnostic> {
+    match error.downcast_ref::<FrameConflictError>() {
+        Some(FrameConflictError(conflicts)) => conflicts
+            .iter()
+            .map(|conflict| {
+                Diagnostic::error(codes::FRAME_CONFLICT, conflict.to_string(), Span::default())
+                    .with_suggestion("space the keyframes at least one frame apart")
+            })
+            .collect(),
+        None => vec![Diagnostic::error(
+            codes::VMD_EXPORT,
+            error.to_string(),
+            Span::default(),
+        )],
+    }
+}
+
+fn to_js(diagnostics: &[Diagnostic]) -> JsValue {
+    serde_wasm_bindgen::to_value(diagnostics).unwrap_or_else(|e| JsValue::from_str(&e.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn export_errors_become_diagnostics() {
+        let head = |time: f32| {
+            let frame = MPLBoneFrame::new(
+                "head".to_string(),
+                "頭".to_string(),
+                Vector3::new(0.0, 0.0, 0.0),
+                Quaternion::identity(),
+            );
+            MPLKeyFrame::new(time, vec![frame], vec![])
+        };
+        let codes_of = |writer: VMDWriter| -> Vec<String> {
+            let error = writer.create_vmd().unwrap_err();
+            export_diagnostics(error)
+                .into_iter()
+                .map(|d| d.code)
+                .collect()
+        };
+
+        // Both keys land on frame 0 at 30 fps
+        let writer = VMDWriter::new(vec![head(0.0), head(0.01)]);
+        assert_eq!(codes_of(writer), [codes::FRAME_CONFLICT]);
+        let writer = VMDWriter::new(vec![head(0.0)]).with_fps(0.0);
+        assert_eq!(codes_of(writer), [codes::VMD_EXPORT]);
     }
 }
@@ -0,0 +1,106 @@
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    animation::track_duration,
+    mpl::{MPLLightFrame, MPLShadowFrame},
+    utils::Vector3,
+};
+
+/// One `@light` keyframe. Properties left out keep their previous value.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct MPLLightStatement {
+    pub time: f32,
+    /// RGB in the 0..255 range MMD's light panel shows
+    pub color: Option<Vector3>,
+    /// Direction the light shines in, each component within -1..1
+    pub direction: Option<Vector3>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MPLLight {
+    pub name: String,
+    pub statements: Vec<MPLLightStatement>,
+}
+
+impl MPLLight {
+    pub fn new(name: String, statements: Vec<MPLLightStatement>) -> Self {
+        Self { name, statements }
+    }
+
+    /// Same rule as `MPLAnimation::duration`
+    pub fn duration(&self) -> f32 {
+        track_duration(self.statements.iter().map(|s| s.time).collect())
+    }
+
+    /// Frames relative to the start of the block, starting from MMD's default light
+    pub fn to_light_frames(&self) -> Vec<MPLLightFrame> {
+        let mut statements: Vec<&MPLLightStatement> = self.statements.iter().collect();
+        statements.sort_by(|a, b| a.time.total_cmp(&b.time));
+
+        let mut frame = MPLLightFrame {
+            time: 0.0,
+            color: Vector3::new(154.0 / 255.0, 154.0 / 255.0, 154.0 / 255.0),
+            direction: Vector3::new(-0.5, -1.0, 0.5),
+        };
+
+        let mut frames = vec![];
+        for statement in statements {
+            frame.time = statement.time;
+            if let Some(color) = statement.color {
+                frame.color = Vector3::new(color.x / 255.0, color.y / 255.0, color.z / 255.0);
+            }
+            if let Some(direction) = statement.direction {
+                frame.direction = direction;
+            }
+            frames.push(frame.clone());
+        }
+        frames
+    }
+}
+
+/// One `@shadow` keyframe. Properties left out keep their previous value.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct MPLShadowStatement {
+    pub time: f32,
+    /// 0 = off, 1 = mode 1, 2 = mode 2
+    pub mode: Option<u8>,
+    /// Shadow range as MMD's self shadow panel shows it, 0..9999
+    pub distance: Option<f32>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MPLShadow {
+    pub name: String,
+    pub statements: Vec<MPLShadowStatement>,
+}
+
+impl MPLShadow {
+    pub fn new(name: String, statements: Vec<MPLShadowStatement>) -> Self {
+        Self { name, statements }
+    }
+
+    /// Same rule as `MPLAnimation::duration`
+    pub fn duration(&self) -> f32 {
+        track_duration(self.statements.iter().map(|s| s.time).collect())
+    }
+
+    /// Frames relative to the start of the block, starting from MMD's default self shadow
+    pub fn to_shadow_frames(&self) -> Vec<MPLShadowFrame> {
+        let mut statements: Vec<&MPLShadowStatement> = self.statements.iter().collect();
+        statements.sort_by(|a, b| a.time.total_cmp(&b.time));
+
+        let mut mode = 1;
+        let mut distance = 8875.0;
+        let mut frames = vec![];
+        for statement in statements {
+            mode = statement.mode.unwrap_or(mode);
+            distance = statement.distance.unwrap_or(distance);
+            frames.push(MPLShadowFrame {
+                time: statement.time,
+                mode,
+                distance: 0.1 - distance * 0.00001,
+            });
+        }
+        frames
+    }
+}
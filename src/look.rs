@@ -0,0 +1,200 @@
+use crate::{
+    bone::BoneActionDatabase,
+    mpl::MPLBoneFrame,
+    pose::MPLLookStatement,
+    reach::set_rotation,
+    utils::{Quaternion, Vector3},
+};
+
+/// Bones that can share a look, from the body up, with the share each takes by default
+pub const LOOK_WEIGHTS: &[(&str, f32)] = &[
+    ("upper_body", 0.2),
+    ("upper_body2", 0.0),
+    ("neck", 0.3),
+    ("head", 0.5),
+];
+
+/// The eyes take whatever turn the bones above leave
+const EYES: &[&str] = &["eye_l", "eye_r"];
+
+/// The rotation of a `turn` or `bend` by `degrees`, held within the limit of the rule
+/// for its direction. Positive turns left and bends backward (up).
+fn limited(db: &BoneActionDatabase, bone: &str, action: &str, degrees: f32) -> Quaternion {
+    let direction = match (action, degrees >= 0.0) {
+        ("turn", true) => "left",
+        ("turn", false) => "right",
+        (_, true) => "backward",
+        (_, false) => "forward",
+    };
+    match db.get_rule(bone, action, direction) {
+        Some(rule) => Quaternion::from_axis_angle(rule.axis, degrees.abs().min(rule.limit)),
+        None => Quaternion::identity(),
+    }
+}
+
+/// Yaw and pitch in degrees that turn a joint's forward (-z) toward the target, seen
+/// from between the eyes, or from the head without eyes
+fn toward(
+    db: &BoneActionDatabase,
+    frames: &[MPLBoneFrame],
+    eyes: &[&str],
+    bone: &str,
+    target: Vector3,
+) -> Result<(f32, f32), String> {
+    let posed = db.skeleton().evaluate(frames);
+    let position = |joint: &str| {
+        posed
+            .get(joint)
+            .map(|transform| transform.position)
+            .ok_or(format!("Skeleton has no joint '{}'", joint))
+    };
+    let origin = match eyes {
+        [] => position("head")?,
+        eyes => eyes
+            .iter()
+            .try_fold(Vector3::new(0.0, 0.0, 0.0), |sum, eye| {
+                Ok::<_, String>(sum.add(&position(eye)?))
+            })?
+            .scale(1.0 / eyes.len() as f32),
+    };
+
+    // In the frame the bone turns in, its parent's with its own rotation reset
+    let direction = posed[bone].rotation.conjugate().rotate(target.sub(&origin));
+    // Model space faces -z, so left is +x
+    let yaw = direction.x.atan2(-direction.z).to_degrees();
+    let pitch = direction
+        .y
+        .atan2(direction.x.hypot(direction.z))
+        .to_degrees();
+    Ok((yaw, pitch))
+}
+
+/// Turn the weighted bones toward the target from the body up, each by its share of
+/// what is left to turn, then each eye by the rest. Every turn stays within the
+/// bone's rules, and every turned bone gets a frame so keyed looks interpolate.
+pub(crate) fn solve(
+    db: &BoneActionDatabase,
+    mut frames: Vec<MPLBoneFrame>,
+    look: &MPLLookStatement,
+) -> Result<Vec<MPLBoneFrame>, String> {
+    let skeleton = db.skeleton();
+    let bones: Vec<(&str, f32)> = LOOK_WEIGHTS
+        .iter()
+        .map(|(bone, _)| (*bone, look.weight(bone)))
+        .filter(|(bone, weight)| *weight > 0.0 && skeleton.joint(bone).is_some())
+        .collect();
+    let eyes: Vec<&str> = EYES
+        .iter()
+        .copied()
+        .filter(|eye| skeleton.joint(eye).is_some())
+        .collect();
+    if bones.is_empty() && eyes.is_empty() {
+        return Err("Skeleton has no joint to turn toward the target".to_string());
+    }
+
+    for bone in bones
+        .iter()
+        .map(|(bone, _)| *bone)
+        .chain(eyes.iter().copied())
+    {
+        set_rotation(db, &mut frames, bone, Quaternion::identity());
+    }
+
+    // Each bone takes its weight out of the share still left, measured from where the
+    // bones below it already turned the head
+    let mut left = 1.0;
+    for (bone, weight) in bones {
+        let share = (weight / left).min(1.0);
+        left -= weight;
+        let (yaw, pitch) = toward(db, &frames, &eyes, bone, look.target)?;
+        set_rotation(
+            db,
+            &mut frames,
+            bone,
+            turned(db, bone, yaw * share, pitch * share),
+        );
+    }
+    for eye in &eyes {
+        let (yaw, pitch) = toward(db, &frames, &[eye], eye, look.target)?;
+        set_rotation(db, &mut frames, eye, turned(db, eye, yaw, pitch));
+    }
+    Ok(frames)
+}
+
+/// Turn then bend, so the bend tilts the bone up or down along the direction it faces
+fn turned(db: &BoneActionDatabase, bone: &str, yaw: f32, pitch: f32) -> Quaternion {
+    limited(db, bone, "turn", yaw).multiply(&limited(db, bone, "bend", pitch))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{skeleton::JointTransform, with_bone_db};
+    use std::collections::BTreeMap;
+
+    fn look_at(target: Vector3, weights: &[(&str, f32)]) -> BTreeMap<String, JointTransform> {
+        let weights = weights.iter().map(|(b, w)| (b.to_string(), *w)).collect();
+        let look = MPLLookStatement::new(target, weights).unwrap();
+        with_bone_db(|db| {
+            let frames = solve(db, vec![], &look).unwrap();
+            db.skeleton().evaluate(&frames)
+        })
+    }
+
+    /// How closely `bone` faces the target as seen from `from`, 1 when straight at it
+    fn facing(
+        posed: &BTreeMap<String, JointTransform>,
+        bone: &str,
+        from: Vector3,
+        target: Vector3,
+    ) -> f32 {
+        let forward = posed[bone].rotation.rotate(Vector3::new(0.0, 0.0, -1.0));
+        forward.dot(&target.sub(&from).normalize())
+    }
+
+    fn turn(posed: &BTreeMap<String, JointTransform>, bone: &str, parent: &str) -> f32 {
+        posed[parent].rotation.angle_to(&posed[bone].rotation)
+    }
+
+    #[test]
+    fn default_weights_face_head_and_eyes_toward_the_target() {
+        for target in [
+            Vector3::new(0.0, 18.0, -20.0),
+            Vector3::new(10.0, 18.0, -10.0),
+        ] {
+            let posed = look_at(target, &[]);
+            let between_eyes = posed["eye_l"]
+                .position
+                .add(&posed["eye_r"].position)
+                .scale(0.5);
+            // The eyes swing with the head, so it ends up within a degree or two
+            assert!(facing(&posed, "head", between_eyes, target) > 0.999);
+            for eye in ["eye_l", "eye_r"] {
+                assert!(facing(&posed, eye, posed[eye].position, target) > 0.9999);
+            }
+        }
+
+        // Shares of 0.2, 0.3 and 0.5 of a 45 degree turn, each of what is left
+        let posed = look_at(Vector3::new(10.0, 18.0, -10.0), &[]);
+        let upper_body = posed["upper_body"]
+            .rotation
+            .angle_to(&Quaternion::identity());
+        let neck = turn(&posed, "neck", "upper_body");
+        assert!(upper_body > 7.0 && upper_body < neck && neck < turn(&posed, "head", "neck"));
+    }
+
+    #[test]
+    fn target_past_the_limits_is_clamped() {
+        // About 170 degrees to the left, turned by the neck and head alone
+        let target = Vector3::new(3.5, 18.0, 19.5);
+        let posed = look_at(target, &[("upper_body", 0.0), ("neck", 0.5), ("head", 0.5)]);
+
+        let neck = posed["neck"].rotation.angle_to(&Quaternion::identity());
+        let head = turn(&posed, "head", "neck");
+        assert!((neck - 75.0).abs() < 0.5, "neck turned {}", neck);
+        assert!((head - 90.0).abs() < 0.5, "head turned {}", head);
+        for eye in ["eye_l", "eye_r"] {
+            assert!(facing(&posed, eye, posed[eye].position, target) > 0.999);
+        }
+    }
+}
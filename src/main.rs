@@ -10,14 +10,9 @@ fn main() {
                waist bend forward 0;
 }
 
-        @pose look_left {
-               head turn left 20;
-               head sway left 20;
-}
-
-       @pose look_right {
-               head turn right 20;
-               head sway right 20;
+        @pose look(dir, amount) {
+               head turn $dir $amount;
+               head sway $dir $amount;
 }
 
        @pose bend_over {
@@ -30,13 +25,13 @@ fn main() {
 
        @animation look_around {
       
-  0.8: look_right;
-  0.9: look_left;
-  1.2: look_right;
+  0.8: look(right, 20);
+  0.9: look(left, 20);
+  1.2: look(right, 20);
 }
 
 @animation bow {
-    1.4: stand & look_left;
+    1.4: stand & look(left, 20);
   2.0: bend_over;
   2.3: stand;
     }
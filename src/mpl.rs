@@ -1,7 +1,10 @@
 use serde::{Deserialize, Serialize};
 use wasm_bindgen::prelude::*;
 
-use crate::utils::{Quaternion, Vector3};
+use crate::{
+    interpolation::{BoneInterpolation, CameraInterpolation},
+    utils::{Quaternion, Vector3},
+};
 
 #[wasm_bindgen]
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -10,6 +13,8 @@ pub struct MPLBoneFrame {
     name_jp: String,
     position: Vector3,
     rotation: Quaternion,
+    #[serde(default)]
+    interpolation: BoneInterpolation,
 }
 
 #[wasm_bindgen]
@@ -21,6 +26,7 @@ impl MPLBoneFrame {
             name_jp,
             position,
             rotation,
+            interpolation: BoneInterpolation::default(),
         }
     }
     #[wasm_bindgen(getter)]
@@ -33,11 +39,22 @@ impl MPLBoneFrame {
     }
     #[wasm_bindgen(getter)]
     pub fn position(&self) -> Vector3 {
-        self.position.clone()
+        self.position
     }
     #[wasm_bindgen(getter)]
     pub fn rotation(&self) -> Quaternion {
-        self.rotation.clone()
+        self.rotation
+    }
+}
+
+impl MPLBoneFrame {
+    pub fn with_interpolation(mut self, interpolation: BoneInterpolation) -> Self {
+        self.interpolation = interpolation;
+        self
+    }
+
+    pub fn interpolation(&self) -> BoneInterpolation {
+        self.interpolation
     }
 }
 
@@ -68,3 +85,48 @@ impl MPLKeyFrame {
         }
     }
 }
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MPLCameraFrame {
+    pub time: f32,
+    pub distance: f32,
+    pub target: Vector3,
+    /// Euler angles in radians
+    pub rotation: Vector3,
+    pub interpolation: CameraInterpolation,
+    /// Field of view in degrees
+    pub fov: u32,
+    pub perspective: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MPLLightFrame {
+    pub time: f32,
+    /// RGB in the 0..1 range
+    pub color: Vector3,
+    pub direction: Vector3,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MPLShadowFrame {
+    pub time: f32,
+    /// 0 = off, 1 = mode 1, 2 = mode 2
+    pub mode: u8,
+    /// Raw VMD value, `0.1 - distance * 0.00001` for the distance shown in MMD
+    pub distance: f32,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MPLIkState {
+    pub name_en: String,
+    pub name_jp: String,
+    pub enabled: bool,
+}
+
+/// Model visibility and IK switches from one frame on
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MPLIkFrame {
+    pub time: f32,
+    pub show: bool,
+    pub ik_states: Vec<MPLIkState>,
+}
@@ -0,0 +1,836 @@
+use crate::{
+    ast::{
+        AnimationBlock, AnimationStatement, Arg, Block, BoneStatement, Easing, Ident, IkStatement,
+        KeyStatement, LookStatement, MPLScript, MainBlock, MainStatement, MirrorBlock,
+        MorphStatement, Number, PoseBlock, PoseCall, PoseStatement, Property, PropertyValue,
+        ReachStatement, ReachTarget, RepeatStatement, Repetition, ShowStatement, Slot, TrackBlock,
+        TrackStatement,
+    },
+    diagnostic::{codes, Diagnostic},
+    lexer::{Span, Token, TokenKind},
+};
+
+/// Recursive-descent parser turning a token stream into an `MPLScript`.
+///
+/// Syntax errors are collected rather than returned: a bad statement is skipped up to
+/// the next `;` and a bad block up to the next block header, so one pass reports every
+/// problem and still yields the blocks that did parse.
+pub struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl Parser {
+    pub fn new(tokens: Vec<Token>) -> Self {
+        Self {
+            tokens,
+            pos: 0,
+            diagnostics: vec![],
+        }
+    }
+
+    pub fn parse_script(&mut self) -> MPLScript {
+        let start = self.peek().span;
+        let mut blocks = Vec::new();
+
+        while self.peek().kind != TokenKind::Eof {
+            match self.parse_block() {
+                Ok(block) => blocks.push(block),
+                Err(diagnostic) => {
+                    self.diagnostics.push(diagnostic);
+                    self.synchronize_block();
+                }
+            }
+        }
+
+        let end = self.peek().span;
+        MPLScript {
+            blocks,
+            span: start.to(&end),
+        }
+    }
+
+    pub fn into_diagnostics(self) -> Vec<Diagnostic> {
+        self.diagnostics
+    }
+
+    fn parse_block(&mut self) -> Result<Block, Diagnostic> {
+        let token = self.peek().clone();
+        match &token.kind {
+            TokenKind::At => {
+                self.advance();
+                let keyword = self.expect_ident("block type")?;
+                match keyword.name.as_str() {
+                    "pose" if self.peek_nth(1).kind == TokenKind::Equals => {
+                        self.parse_mirror(token.span).map(Block::Mirror)
+                    }
+                    "pose" => self.parse_pose(token.span).map(Block::Pose),
+                    "animation" => self.parse_animation(token.span).map(Block::Animation),
+                    "camera" => self
+                        .parse_track("camera name", token.span)
+                        .map(Block::Camera),
+                    "light" => self.parse_track("light name", token.span).map(Block::Light),
+                    "shadow" => self
+                        .parse_track("shadow name", token.span)
+                        .map(Block::Shadow),
+                    _ => Err(Diagnostic::error(
+                        codes::INVALID_BLOCK,
+                        format!("Invalid block type '@{}'", keyword.name),
+                        token.span.to(&keyword.span),
+                    )
+                    .with_suggestion(
+                        "use '@pose', '@animation', '@camera', '@light' or '@shadow'",
+                    )),
+                }
+            }
+            TokenKind::Ident(name) if name == "main" => {
+                self.advance();
+                self.parse_main(token.span).map(Block::Main)
+            }
+            _ => Err(Diagnostic::error(
+                codes::INVALID_BLOCK,
+                "Invalid text outside of block",
+                token.span,
+            )
+            .with_suggestion("statements must be inside a '@pose', '@animation' or 'main' block")),
+        }
+    }
+
+    fn parse_pose(&mut self, start: Span) -> Result<PoseBlock, Diagnostic> {
+        let name = self.expect_ident("pose name")?;
+
+        // `@pose look(dir, amount)` declares parameters used as `$dir` and `$amount`
+        let mut params = vec![];
+        if self.check(&TokenKind::LParen) {
+            self.advance();
+            params.push(self.expect_ident("parameter name")?);
+            while self.check(&TokenKind::Comma) {
+                self.advance();
+                params.push(self.expect_ident("parameter name")?);
+            }
+            self.expect(TokenKind::RParen)?;
+        }
+        self.expect(TokenKind::LBrace)?;
+
+        let mut statements = Vec::new();
+        while !self.at_block_end() {
+            match self.parse_pose_statement() {
+                Ok(statement) => statements.push(statement),
+                Err(diagnostic) => self.recover_statement(diagnostic),
+            }
+        }
+        let end = self.expect_block_end();
+
+        Ok(PoseBlock {
+            name,
+            params,
+            statements,
+            span: start.to(&end),
+        })
+    }
+
+    fn parse_mirror(&mut self, start: Span) -> Result<MirrorBlock, Diagnostic> {
+        let name = self.expect_ident("pose name")?;
+        self.expect(TokenKind::Equals)?;
+        if !self.check_keyword("mirror") {
+            let token = self.peek();
+            return Err(Diagnostic::error(
+                codes::UNEXPECTED_TOKEN,
+                format!("Expected 'mirror' but found {}", token.kind.describe()),
+                token.span,
+            )
+            .with_suggestion(format!("write '@pose {} = mirror other_pose;'", name.name)));
+        }
+        self.advance();
+        let source = self.expect_ident("pose name")?;
+        let end = self.expect(TokenKind::Semicolon)?;
+
+        Ok(MirrorBlock {
+            name,
+            source,
+            span: start.to(&end),
+        })
+    }
+
+    fn parse_pose_statement(&mut self) -> Result<PoseStatement, Diagnostic> {
+        // `morph name weight;` (or `face ...`) sets a facial expression
+        if let (TokenKind::Ident(keyword), TokenKind::Ident(_) | TokenKind::Dollar) =
+            (&self.peek().kind, &self.peek_nth(1).kind)
+        {
+            if keyword == "morph" || keyword == "face" {
+                let start = self.advance().span;
+                let morph = self.expect_ident_slot("morph name")?;
+                let weight = self.expect_number_slot("weight")?;
+                let end = self.expect(TokenKind::Semicolon)?;
+                return Ok(PoseStatement::Morph(MorphStatement {
+                    morph,
+                    weight,
+                    span: start.to(&end),
+                }));
+            }
+        }
+
+        // `ik name on|off;` and `show on|off;` switch IK chains and model visibility
+        if let (TokenKind::Ident(keyword), TokenKind::Ident(_)) =
+            (&self.peek().kind, &self.peek_nth(1).kind)
+        {
+            if keyword == "ik" {
+                let start = self.advance().span;
+                let ik = self.expect_ident("IK name")?;
+                let state = self.expect_ident("'on' or 'off'")?;
+                let end = self.expect(TokenKind::Semicolon)?;
+                return Ok(PoseStatement::Ik(IkStatement {
+                    ik,
+                    state,
+                    span: start.to(&end),
+                }));
+            }
+            if keyword == "show" {
+                let start = self.advance().span;
+                let state = self.expect_ident("'on' or 'off'")?;
+                let end = self.expect(TokenKind::Semicolon)?;
+                return Ok(PoseStatement::Show(ShowStatement {
+                    state,
+                    span: start.to(&end),
+                }));
+            }
+        }
+
+        // `look_at (x, y, z);` turns the upper body, neck, head and eyes toward a point
+        if self.check_keyword("look_at") {
+            return Ok(PoseStatement::Look(self.parse_look()?));
+        }
+
+        // `limb reach (x, y, z);` and `limb plant ground;` solve a limb onto a target
+        if let (TokenKind::Ident(_), TokenKind::Ident(verb)) =
+            (&self.peek().kind, &self.peek_nth(1).kind)
+        {
+            if verb == "reach" || verb == "plant" {
+                return Ok(PoseStatement::Reach(self.parse_reach()?));
+            }
+        }
+
+        let bone = self.expect_ident_slot("bone name")?;
+        let action = self.expect_ident_slot("action")?;
+        let direction = self.expect_ident_slot("direction")?;
+        let degrees = self.expect_number_slot("degrees")?;
+        let unit = match self.peek().kind {
+            TokenKind::Ident(_) => Some(self.expect_ident("unit")?),
+            _ => None,
+        };
+        let end = self.expect(TokenKind::Semicolon)?;
+
+        Ok(PoseStatement::Bone(BoneStatement {
+            span: bone.span().to(&end),
+            bone,
+            action,
+            direction,
+            degrees,
+            unit,
+        }))
+    }
+
+    fn parse_look(&mut self) -> Result<LookStatement, Diagnostic> {
+        let start = self.advance().span;
+        self.expect(TokenKind::LParen)?;
+        let mut values = vec![self.expect_number_slot("coordinate")?];
+        while self.check(&TokenKind::Comma) {
+            self.advance();
+            values.push(self.expect_number_slot("coordinate")?);
+        }
+        self.expect(TokenKind::RParen)?;
+        let unit = match &self.peek().kind {
+            TokenKind::Ident(name) if name != "with" => Some(self.expect_ident("unit")?),
+            _ => None,
+        };
+
+        let mut weights = vec![];
+        if self.check_keyword("with") {
+            self.advance();
+            loop {
+                let bone = self.expect_ident("bone name")?;
+                let weight = self.expect_number("weight")?;
+                weights.push((bone, weight));
+                if !self.check(&TokenKind::Comma) {
+                    break;
+                }
+                self.advance();
+            }
+        }
+        let end = self.expect(TokenKind::Semicolon)?;
+
+        Ok(LookStatement {
+            values,
+            unit,
+            weights,
+            span: start.to(&end),
+        })
+    }
+
+    fn parse_reach(&mut self) -> Result<ReachStatement, Diagnostic> {
+        let limb = self.expect_ident("limb")?;
+        let verb = self.expect_ident("'reach' or 'plant'")?;
+        let target = if self.check(&TokenKind::LParen) {
+            let start = self.advance().span;
+            let mut values = vec![self.expect_number("coordinate")?];
+            while self.check(&TokenKind::Comma) {
+                self.advance();
+                values.push(self.expect_number("coordinate")?);
+            }
+            let end = self.expect(TokenKind::RParen)?;
+            let unit = match self.peek().kind {
+                TokenKind::Ident(_) => Some(self.expect_ident("unit")?),
+                _ => None,
+            };
+            ReachTarget::Point {
+                values,
+                unit,
+                span: start.to(&end),
+            }
+        } else {
+            ReachTarget::Named(self.expect_ident("target point or 'ground'")?)
+        };
+        let end = self.expect(TokenKind::Semicolon)?;
+
+        Ok(ReachStatement {
+            span: limb.span.to(&end),
+            limb,
+            verb,
+            target,
+        })
+    }
+
+    fn parse_animation(&mut self, start: Span) -> Result<AnimationBlock, Diagnostic> {
+        let name = self.expect_ident("animation name")?;
+        self.expect(TokenKind::LBrace)?;
+
+        let statements = self.parse_animation_body();
+        let end = self.expect_block_end();
+
+        Ok(AnimationBlock {
+            name,
+            statements,
+            span: start.to(&end),
+        })
+    }
+
+    /// Statements up to the closing brace, which is left for the caller
+    fn parse_animation_body(&mut self) -> Vec<AnimationStatement> {
+        let mut statements = Vec::new();
+        while !self.at_block_end() {
+            let statement = if self.check_keyword("repeat") || self.check_keyword("loop") {
+                self.parse_repeat().map(AnimationStatement::Repeat)
+            } else {
+                self.parse_animation_statement()
+                    .map(AnimationStatement::Key)
+            };
+            match statement {
+                Ok(statement) => statements.push(statement),
+                Err(diagnostic) => self.recover_statement(diagnostic),
+            }
+        }
+        statements
+    }
+
+    /// `repeat 4 { ... }` or `loop until 12.0 { ... }`
+    fn parse_repeat(&mut self) -> Result<RepeatStatement, Diagnostic> {
+        let start = self.peek().span;
+        let repetition = self.parse_repetition()?;
+        self.expect(TokenKind::LBrace)?;
+        let statements = self.parse_animation_body();
+        let end = self.expect_block_end();
+
+        Ok(RepeatStatement {
+            repetition,
+            statements,
+            span: start.to(&end),
+        })
+    }
+
+    /// `repeat 4`, `x 4` or `loop until 12.0`
+    fn parse_repetition(&mut self) -> Result<Repetition, Diagnostic> {
+        if self.check_keyword("loop") {
+            self.advance();
+            if !self.check_keyword("until") {
+                let token = self.peek();
+                return Err(Diagnostic::error(
+                    codes::UNEXPECTED_TOKEN,
+                    format!("Expected 'until' but found {}", token.kind.describe()),
+                    token.span,
+                )
+                .with_suggestion("write 'loop until <time>'"));
+            }
+            self.advance();
+            let time = self.expect_number("end time")?;
+            return Ok(Repetition::Until { time });
+        }
+
+        self.advance();
+        let count = self.expect_number("repeat count")?;
+        Ok(Repetition::Times { count })
+    }
+
+    fn parse_animation_statement(&mut self) -> Result<KeyStatement, Diagnostic> {
+        let time = self.expect_number("keyframe time")?;
+        self.expect(TokenKind::Colon)?;
+
+        let mut poses = vec![self.parse_pose_call()?];
+        while self.check(&TokenKind::Ampersand) {
+            self.advance();
+            poses.push(self.parse_pose_call()?);
+        }
+
+        let easing = if self.check_keyword("with") {
+            self.advance();
+            Some(self.parse_easing()?)
+        } else {
+            None
+        };
+        let end = self.expect(TokenKind::Semicolon)?;
+
+        Ok(KeyStatement {
+            span: time.span.to(&end),
+            time,
+            poses,
+            easing,
+        })
+    }
+
+    /// `name` or `name(arg, ...)` with words and numbers as arguments, then an
+    /// optional `*weight`
+    fn parse_pose_call(&mut self) -> Result<PoseCall, Diagnostic> {
+        let pose = self.expect_ident("pose name")?;
+        let mut span = pose.span;
+        let mut args = vec![];
+        if self.check(&TokenKind::LParen) {
+            self.advance();
+            loop {
+                args.push(match self.peek().kind {
+                    TokenKind::Number(_) => Arg::Number(self.expect_number("argument")?),
+                    _ => Arg::Ident(self.expect_ident("argument")?),
+                });
+                if !self.check(&TokenKind::Comma) {
+                    break;
+                }
+                self.advance();
+            }
+            span = span.to(&self.expect(TokenKind::RParen)?);
+        }
+
+        let mut weight = None;
+        if self.check(&TokenKind::Star) {
+            self.advance();
+            let number = self.expect_number("blend weight")?;
+            span = span.to(&number.span);
+            weight = Some(number);
+        }
+
+        Ok(PoseCall {
+            pose,
+            args,
+            weight,
+            span,
+        })
+    }
+
+    fn parse_easing(&mut self) -> Result<Easing, Diagnostic> {
+        let name = self.expect_ident("easing")?;
+        if name.name != "bezier" {
+            return Ok(Easing::Preset(name));
+        }
+
+        self.expect(TokenKind::LParen)?;
+        let mut points = vec![self.expect_number("control point")?];
+        while self.check(&TokenKind::Comma) {
+            self.advance();
+            points.push(self.expect_number("control point")?);
+        }
+        let end = self.expect(TokenKind::RParen)?;
+
+        Ok(Easing::Bezier {
+            points,
+            span: name.span.to(&end),
+        })
+    }
+
+    fn parse_track(&mut self, what: &str, start: Span) -> Result<TrackBlock, Diagnostic> {
+        let name = self.expect_ident(what)?;
+        self.expect(TokenKind::LBrace)?;
+
+        let mut statements = Vec::new();
+        while !self.at_block_end() {
+            match self.parse_track_statement() {
+                Ok(statement) => statements.push(statement),
+                Err(diagnostic) => self.recover_statement(diagnostic),
+            }
+        }
+        let end = self.expect_block_end();
+
+        Ok(TrackBlock {
+            name,
+            statements,
+            span: start.to(&end),
+        })
+    }
+
+    fn parse_track_statement(&mut self) -> Result<TrackStatement, Diagnostic> {
+        let time = self.expect_number("keyframe time")?;
+        self.expect(TokenKind::Colon)?;
+
+        let mut properties = vec![self.parse_property()?];
+        while self.check(&TokenKind::Comma) {
+            self.advance();
+            properties.push(self.parse_property()?);
+        }
+
+        let easing = if self.check_keyword("with") {
+            self.advance();
+            Some(self.parse_easing()?)
+        } else {
+            None
+        };
+        let end = self.expect(TokenKind::Semicolon)?;
+
+        Ok(TrackStatement {
+            span: time.span.to(&end),
+            time,
+            properties,
+            easing,
+        })
+    }
+
+    fn parse_property(&mut self) -> Result<Property, Diagnostic> {
+        let name = self.expect_ident("property name")?;
+        let token = self.peek().clone();
+        let value = match token.kind {
+            TokenKind::Number(value) => {
+                self.advance();
+                PropertyValue::Number(Number {
+                    value,
+                    span: token.span,
+                })
+            }
+            TokenKind::Ident(_) => PropertyValue::Ident(self.expect_ident("property value")?),
+            TokenKind::LParen => {
+                self.advance();
+                let mut values = vec![self.expect_number("vector component")?];
+                while self.check(&TokenKind::Comma) {
+                    self.advance();
+                    values.push(self.expect_number("vector component")?);
+                }
+                let end = self.expect(TokenKind::RParen)?;
+                PropertyValue::Vector {
+                    values,
+                    span: token.span.to(&end),
+                }
+            }
+            kind => {
+                return Err(Diagnostic::error(
+                    codes::UNEXPECTED_TOKEN,
+                    format!(
+                        "Expected a value for '{}' but found {}",
+                        name.name,
+                        kind.describe()
+                    ),
+                    token.span,
+                ))
+            }
+        };
+
+        Ok(Property {
+            span: name.span.to(&value.span()),
+            name,
+            value,
+        })
+    }
+
+    fn parse_main(&mut self, start: Span) -> Result<MainBlock, Diagnostic> {
+        self.expect(TokenKind::LBrace)?;
+
+        let mut statements = Vec::new();
+        while !self.at_block_end() {
+            match self.parse_main_statement() {
+                Ok(statement) => statements.push(statement),
+                Err(diagnostic) => self.recover_statement(diagnostic),
+            }
+        }
+        let end = self.expect_block_end();
+
+        Ok(MainBlock {
+            statements,
+            span: start.to(&end),
+        })
+    }
+
+    fn parse_main_statement(&mut self) -> Result<MainStatement, Diagnostic> {
+        let first = self.peek().span;
+
+        // `at 2.5: name;` places the entry at an absolute time
+        let start = match (&self.peek().kind, &self.peek_nth(1).kind) {
+            (TokenKind::Ident(keyword), TokenKind::Number(_)) if keyword == "at" => {
+                self.advance();
+                let time = self.expect_number("start time")?;
+                self.expect(TokenKind::Colon)?;
+                Some(time)
+            }
+            _ => None,
+        };
+
+        // `a & b;` plays entries in parallel
+        let mut entries = vec![self.expect_ident("animation or pose name")?];
+        while self.check(&TokenKind::Ampersand) {
+            self.advance();
+            entries.push(self.expect_ident("animation or pose name")?);
+        }
+
+        // `walk + breathe;` layers `breathe` on top of `walk`
+        let mut layers = vec![];
+        while self.check(&TokenKind::Plus) {
+            self.advance();
+            layers.push(self.expect_ident("animation or pose name")?);
+        }
+
+        // `walk x 4;` or `walk loop until 12.0;` plays the statement back to back
+        let repetition = match (&self.peek().kind, &self.peek_nth(1).kind) {
+            (TokenKind::Ident(keyword), TokenKind::Number(_)) if keyword == "x" => {
+                Some(self.parse_repetition()?)
+            }
+            (TokenKind::Ident(keyword), _) if keyword == "loop" => Some(self.parse_repetition()?),
+            _ => None,
+        };
+        let end = self.expect(TokenKind::Semicolon)?;
+
+        Ok(MainStatement {
+            start,
+            entries,
+            layers,
+            repetition,
+            span: first.to(&end),
+        })
+    }
+
+    fn peek(&self) -> &Token {
+        self.peek_nth(0)
+    }
+
+    fn peek_nth(&self, n: usize) -> &Token {
+        &self.tokens[(self.pos + n).min(self.tokens.len() - 1)]
+    }
+
+    fn advance(&mut self) -> Token {
+        let token = self.peek().clone();
+        if token.kind != TokenKind::Eof {
+            self.pos += 1;
+        }
+        token
+    }
+
+    fn check(&self, kind: &TokenKind) -> bool {
+        &self.peek().kind == kind
+    }
+
+    fn check_keyword(&self, keyword: &str) -> bool {
+        matches!(&self.peek().kind, TokenKind::Ident(name) if name == keyword)
+    }
+
+    /// The closing brace, or anything that cannot continue the current block body
+    fn at_block_end(&self) -> bool {
+        matches!(
+            self.peek().kind,
+            TokenKind::RBrace | TokenKind::At | TokenKind::Eof
+        )
+    }
+
+    /// Consume the closing brace of a block. A missing brace is reported but the block
+    /// is still kept, ending at the last token of its body.
+    fn expect_block_end(&mut self) -> Span {
+        if self.check(&TokenKind::RBrace) {
+            return self.advance().span;
+        }
+
+        let token = self.peek().clone();
+        let message = format!("Expected '}}' but found {}", token.kind.describe());
+        self.diagnostics.push(
+            Diagnostic::error(codes::UNCLOSED_BLOCK, message, token.span)
+                .with_suggestion("add '}' to close the block"),
+        );
+        self.tokens[self.pos.saturating_sub(1)].span
+    }
+
+    fn recover_statement(&mut self, diagnostic: Diagnostic) {
+        self.diagnostics.push(diagnostic);
+
+        // Skip to the end of the broken statement, stopping early at the end of the block
+        while !self.at_block_end() {
+            if self.advance().kind == TokenKind::Semicolon {
+                break;
+            }
+        }
+    }
+
+    fn synchronize_block(&mut self) {
+        // Always make progress, then skip to the next block header
+        self.advance();
+        loop {
+            match &self.peek().kind {
+                TokenKind::At | TokenKind::Eof => break,
+                TokenKind::Ident(name) if name == "main" => break,
+                _ => {
+                    self.advance();
+                }
+            }
+        }
+    }
+
+    fn expect(&mut self, kind: TokenKind) -> Result<Span, Diagnostic> {
+        let token = self.peek();
+        if token.kind == kind {
+            return Ok(self.advance().span);
+        }
+
+        let message = format!(
+            "Expected {} but found {}",
+            kind.describe(),
+            token.kind.describe()
+        );
+        Err(
+            Diagnostic::error(codes::UNEXPECTED_TOKEN, message, token.span)
+                .with_suggestion(format!("insert {}", kind.describe())),
+        )
+    }
+
+    fn expect_ident(&mut self, what: &str) -> Result<Ident, Diagnostic> {
+        let token = self.peek().clone();
+        match token.kind {
+            TokenKind::Ident(name) => {
+                self.advance();
+                Ok(Ident {
+                    name,
+                    span: token.span,
+                })
+            }
+            kind => Err(Diagnostic::error(
+                codes::UNEXPECTED_TOKEN,
+                format!("Expected {} but found {}", what, kind.describe()),
+                token.span,
+            )),
+        }
+    }
+
+    /// `what`, or a `$param` reference
+    fn expect_ident_slot(&mut self, what: &str) -> Result<Slot<Ident>, Diagnostic> {
+        if self.check(&TokenKind::Dollar) {
+            self.expect_param().map(Slot::Param)
+        } else {
+            self.expect_ident(what).map(Slot::Value)
+        }
+    }
+
+    /// `what`, or a `$param` reference
+    fn expect_number_slot(&mut self, what: &str) -> Result<Slot<Number>, Diagnostic> {
+        if self.check(&TokenKind::Dollar) {
+            self.expect_param().map(Slot::Param)
+        } else {
+            self.expect_number(what).map(Slot::Value)
+        }
+    }
+
+    /// `$name`, spanning the dollar sign
+    fn expect_param(&mut self) -> Result<Ident, Diagnostic> {
+        let dollar = self.expect(TokenKind::Dollar)?;
+        let param = self.expect_ident("parameter name")?;
+        Ok(Ident {
+            name: param.name,
+            span: dollar.to(&param.span),
+        })
+    }
+
+    fn expect_number(&mut self, what: &str) -> Result<Number, Diagnostic> {
+        let token = self.peek().clone();
+        match token.kind {
+            TokenKind::Number(value) => {
+                self.advance();
+                Ok(Number {
+                    value,
+                    span: token.span,
+                })
+            }
+            kind => Err(Diagnostic::error(
+                codes::UNEXPECTED_TOKEN,
+                format!("Expected {} but found {}", what, kind.describe()),
+                token.span,
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer::Lexer;
+
+    /// Parsed blocks with the code of each syntax error and the text it points at
+    fn parse(text: &str) -> (MPLScript, Vec<(String, String)>) {
+        let (tokens, _) = Lexer::new(text).tokenize();
+        let mut parser = Parser::new(tokens);
+        let script = parser.parse_script();
+        let diagnostics = parser
+            .into_diagnostics()
+            .into_iter()
+            .map(|d| {
+                let span = &text[d.span.start.offset as usize..d.span.end.offset as usize];
+                (d.code, span.to_string())
+            })
+            .collect();
+        (script, diagnostics)
+    }
+
+    fn error(code: &str, text: &str) -> (String, String) {
+        (code.to_string(), text.to_string())
+    }
+
+    #[test]
+    fn broken_statement_is_skipped_to_its_semicolon() {
+        let (script, diagnostics) = parse("@pose a { head turn; neck bend forward 10; }");
+        assert_eq!(diagnostics, [error(codes::UNEXPECTED_TOKEN, ";")]);
+        let Block::Pose(pose) = &script.blocks[0] else {
+            panic!("expected a pose");
+        };
+        assert_eq!(pose.statements.len(), 1);
+
+        let (script, diagnostics) = parse("main { a 3 b; a; }");
+        assert_eq!(diagnostics, [error(codes::UNEXPECTED_TOKEN, "3")]);
+        let Block::Main(main) = &script.blocks[0] else {
+            panic!("expected main");
+        };
+        assert_eq!(main.statements[0].entries[0].name, "a");
+    }
+
+    #[test]
+    fn broken_block_is_skipped_to_the_next_header() {
+        let (script, diagnostics) =
+            parse("@bogus x { head; } @pose a { head turn left 10; } main { a; }");
+        assert_eq!(diagnostics, [error(codes::INVALID_BLOCK, "@bogus")]);
+        assert!(matches!(
+            script.blocks[..],
+            [Block::Pose(_), Block::Main(_)]
+        ));
+    }
+
+    #[test]
+    fn unclosed_block_keeps_its_statements() {
+        let (script, diagnostics) =
+            parse("@pose a { head turn left 10; @pose b { neck bend forward 5; }");
+        assert_eq!(diagnostics, [error(codes::UNCLOSED_BLOCK, "@")]);
+        let names: Vec<&str> = script
+            .blocks
+            .iter()
+            .filter_map(|block| match block {
+                Block::Pose(pose) if pose.statements.len() == 1 => Some(pose.name.name.as_str()),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(names, ["a", "b"]);
+    }
+}
@@ -0,0 +1,657 @@
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::io::{Cursor, Read};
+
+use crate::{
+    bone::BoneActionDatabase,
+    rig::{RigJoint, RigProfile, RigRule},
+    utils::{Quaternion, Vector3},
+    MOVE_ACTION,
+};
+
+const MAGIC: &[u8; 4] = b"PMX ";
+
+// Bone flags
+const TAIL_IS_BONE: u16 = 0x0001;
+const ROTATABLE: u16 = 0x0002;
+const TRANSLATABLE: u16 = 0x0004;
+const IK: u16 = 0x0020;
+const INHERIT_ROTATION: u16 = 0x0100;
+const INHERIT_TRANSLATION: u16 = 0x0200;
+const FIXED_AXIS: u16 = 0x0400;
+const LOCAL_AXIS: u16 = 0x0800;
+const EXTERNAL_PARENT: u16 = 0x2000;
+
+/// Names some models use instead of the standard one
+const ALTERNATE_NAMES: &[(&str, &str)] = &[("toe_l", "左つま先"), ("toe_r", "右つま先")];
+
+/// Bones whose rules bend relative to the bone itself rather than the body. The
+/// built-in axes assume arms hanging 45 degrees below horizontal, as in most models.
+const ARM_CHAIN: &[&str] = &[
+    "arm_twist_",
+    "elbow_",
+    "wrist_",
+    "index_",
+    "middle_",
+    "ring_",
+    "pinky_",
+];
+
+/// The bone list of a PMX 2.0/2.1 model; vertices, materials and physics are skipped
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PMXModel {
+    pub name: String,
+    pub name_en: String,
+    pub bones: Vec<PMXBone>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PMXBone {
+    pub name: String,
+    pub name_en: String,
+    pub position: Vector3,
+    pub parent: Option<usize>,
+    pub tail: PMXTail,
+    pub rotatable: bool,
+    pub translatable: bool,
+    /// Twist bones only turn about this axis
+    pub fixed_axis: Option<Vector3>,
+    /// Local X and Z axes, X usually pointing along the bone
+    pub local_axes: Option<(Vector3, Vector3)>,
+    pub ik: Option<PMXIk>,
+}
+
+/// Where a bone points: at another bone, or along an offset from its own position
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum PMXTail {
+    Bone { bone: usize },
+    Offset { offset: Vector3 },
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PMXIk {
+    pub target: Option<usize>,
+    pub loops: i32,
+    /// Radians per iteration
+    pub limit_angle: f32,
+    pub links: Vec<PMXIkLink>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PMXIkLink {
+    pub bone: Option<usize>,
+    /// Lower and upper rotation limits in radians
+    pub limits: Option<(Vector3, Vector3)>,
+}
+
+/// A rig profile generated for one model, with the MPL bones and IK chains it lacks
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PMXRig {
+    pub profile: RigProfile,
+    /// Bones the model doesn't have, or has locked against every action
+    pub missing_bones: Vec<String>,
+    pub missing_iks: Vec<String>,
+}
+
+impl PMXModel {
+    pub fn parse(data: &[u8]) -> Result<Self, Box<dyn std::error::Error>> {
+        PMXReader::new(data).read()
+    }
+
+    /// Index of the bone named `name`, comparing full-width and half-width digits and
+    /// letters as equal
+    pub fn find_bone(&self, name: &str) -> Option<usize> {
+        let name = normalize_name(name);
+        self.bones
+            .iter()
+            .position(|bone| normalize_name(&bone.name) == name)
+    }
+
+    /// Direction the bone points in, from its fixed axis, local X axis or tail
+    pub fn bone_direction(&self, index: usize) -> Option<Vector3> {
+        let bone = &self.bones[index];
+        let direction = match (
+            bone.fixed_axis.or(bone.local_axes.map(|(x, _)| x)),
+            &bone.tail,
+        ) {
+            (Some(axis), _) => axis,
+            (None, PMXTail::Bone { bone: tail }) => {
+                let tail = self.bones.get(*tail)?.position;
+                Vector3::new(
+                    tail.x - bone.position.x,
+                    tail.y - bone.position.y,
+                    tail.z - bone.position.z,
+                )
+            }
+            (None, PMXTail::Offset { offset }) => *offset,
+        };
+        (direction.length() > 0.0001).then(|| direction.normalize())
+    }
+
+    /// Built-in rules for every standard bone the model has, turned to match its axes.
+    /// Twist bones take the model's fixed axis, arm and finger bones are turned from
+    /// the built-in arm angle to the direction the model's bone points in, and actions
+    /// a bone's flags forbid are left out. Every standard bone found also becomes a
+    /// skeleton joint at its rest position, hanging from its nearest standard ancestor;
+    /// the skeleton holds only those joints, so bones in `missing_bones` have none.
+    pub fn to_rig(&self) -> PMXRig {
+        let translations = BoneActionDatabase::build_translations();
+        let mut profile = RigProfile {
+            replace: true,
+            replace_skeleton: true,
+            ..RigProfile::default()
+        };
+        let mut missing_bones = vec![];
+        let mut found: BTreeMap<usize, String> = BTreeMap::new();
+
+        let mut rules: Vec<_> = BoneActionDatabase::build_rules().into_iter().collect();
+        rules.sort_by(|a, b| a.0.cmp(&b.0));
+        for (bone, actions) in rules {
+            let standard = translations
+                .get(&bone)
+                .map_or(bone.as_str(), |jp| jp.as_str());
+            let alternates = ALTERNATE_NAMES
+                .iter()
+                .filter(|(en, _)| *en == bone)
+                .map(|(_, jp)| *jp);
+            let Some(index) = std::iter::once(standard)
+                .chain(alternates)
+                .find_map(|name| self.find_bone(name))
+            else {
+                missing_bones.push(bone);
+                continue;
+            };
+            let pmx_bone = &self.bones[index];
+            found.insert(index, bone.clone());
+
+            let turn = ARM_CHAIN
+                .iter()
+                .any(|prefix| bone.starts_with(prefix))
+                .then(|| {
+                    let side = if bone.ends_with("_l") { 1.0 } else { -1.0 };
+                    let reference = Vector3::new(side, -1.0, 0.0);
+                    Some(Quaternion::between(reference, self.bone_direction(index)?))
+                })
+                .flatten();
+
+            let mut bone_rules = BTreeMap::new();
+            for (action, directions) in actions {
+                let allowed = if action == MOVE_ACTION {
+                    pmx_bone.translatable
+                } else {
+                    pmx_bone.rotatable
+                };
+                if !allowed {
+                    continue;
+                }
+
+                let directions = directions
+                    .into_iter()
+                    .map(|(direction, rule)| {
+                        let turned = match turn {
+                            Some(turn) if action != MOVE_ACTION => turn.rotate(rule.axis),
+                            _ => rule.axis,
+                        };
+                        // Twist the way the turned built-in axis leans
+                        let axis = match pmx_bone.fixed_axis {
+                            Some(fixed) if action != MOVE_ACTION => {
+                                let fixed = fixed.normalize();
+                                let sign = if fixed.dot(&turned) < 0.0 { -1.0 } else { 1.0 };
+                                Vector3::new(fixed.x * sign, fixed.y * sign, fixed.z * sign)
+                            }
+                            _ => turned,
+                        };
+                        let rule = RigRule {
+                            axis: [axis.x, axis.y, axis.z],
+                            limit: rule.limit,
+                        };
+                        (direction, rule)
+                    })
+                    .collect();
+                bone_rules.insert(action, directions);
+            }
+
+            if bone_rules.is_empty() {
+                missing_bones.push(bone);
+                continue;
+            }
+            if pmx_bone.name != standard {
+                profile.names.insert(bone.clone(), pmx_bone.name.clone());
+            }
+            profile.bones.insert(bone, bone_rules);
+        }
+
+        for (index, bone) in &found {
+            let mut parent = self.bones[*index].parent;
+            // Walk up past bones MPL doesn't know, stopping at cycles in broken models
+            for _ in 0..self.bones.len() {
+                match parent {
+                    Some(p) if !found.contains_key(&p) => {
+                        parent = self.bones.get(p).and_then(|bone| bone.parent)
+                    }
+                    _ => break,
+                }
+            }
+            let position = self.bones[*index].position;
+            profile.skeleton.insert(
+                bone.clone(),
+                RigJoint {
+                    parent: parent.and_then(|p| found.get(&p).cloned()),
+                    position: [position.x, position.y, position.z],
+                },
+            );
+        }
+
+        let mut iks: Vec<_> = BoneActionDatabase::build_ik_translations()
+            .into_iter()
+            .collect();
+        iks.sort();
+        let missing_iks = iks
+            .into_iter()
+            .filter(|(_, jp)| {
+                self.find_bone(jp)
+                    .is_none_or(|index| self.bones[index].ik.is_none())
+            })
+            .map(|(ik, _)| ik)
+            .collect();
+
+        PMXRig {
+            profile,
+            missing_bones,
+            missing_iks,
+        }
+    }
+}
+
+/// Full-width digits and letters as their ASCII counterparts (`上半身２` as `上半身2`)
+fn normalize_name(name: &str) -> String {
+    name.chars()
+        .map(|c| match c {
+            '\u{FF01}'..='\u{FF5E}' => char::from_u32(c as u32 - 0xFEE0).unwrap_or(c),
+            c => c,
+        })
+        .collect()
+}
+
+struct PMXReader<'a> {
+    cursor: Cursor<&'a [u8]>,
+    utf8: bool,
+    additional_uvs: usize,
+    vertex_index_size: usize,
+    texture_index_size: usize,
+    bone_index_size: usize,
+}
+
+impl<'a> PMXReader<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Self {
+            cursor: Cursor::new(data),
+            utf8: false,
+            additional_uvs: 0,
+            vertex_index_size: 4,
+            texture_index_size: 4,
+            bone_index_size: 4,
+        }
+    }
+
+    fn read(mut self) -> Result<PMXModel, Box<dyn std::error::Error>> {
+        let magic: [u8; 4] = self.read_bytes("header")?;
+        if &magic != MAGIC {
+            return Err("Not a PMX file (missing 'PMX ' header)".into());
+        }
+        let version = self.read_f32("version")?;
+        if !(2.0..2.2).contains(&version) {
+            return Err(format!("Unsupported PMX version {}", version).into());
+        }
+
+        let [count] = self.read_bytes("globals count")?;
+        let mut globals = vec![0u8; count as usize];
+        self.fill(&mut globals, "globals")?;
+        let [encoding, additional_uvs, vertex_index, texture_index, _, bone_index, ..] =
+            globals[..]
+        else {
+            return Err(format!("PMX header has {} globals, expected 8", count).into());
+        };
+        self.utf8 = encoding == 1;
+        self.additional_uvs = additional_uvs as usize;
+        self.vertex_index_size = vertex_index as usize;
+        self.texture_index_size = texture_index as usize;
+        self.bone_index_size = bone_index as usize;
+
+        let name = self.read_text("model name")?;
+        let name_en = self.read_text("English model name")?;
+        self.read_text("comment")?;
+        self.read_text("English comment")?;
+
+        self.skip_vertices()?;
+        let indices = self.read_count("face count")?;
+        let faces = indices
+            .checked_mul(self.vertex_index_size)
+            .ok_or(format!("Face count {} is too large", indices))?;
+        self.skip(faces, "faces")?;
+        for _ in 0..self.read_count("texture count")? {
+            self.read_text("texture path")?;
+        }
+        self.skip_materials()?;
+
+        let mut bones = vec![];
+        for _ in 0..self.read_count("bone count")? {
+            bones.push(self.read_bone()?);
+        }
+
+        Ok(PMXModel {
+            name,
+            name_en,
+            bones,
+        })
+    }
+
+    fn skip_vertices(&mut self) -> Result<(), Box<dyn std::error::Error>> {
+        let bone = self.bone_index_size;
+        for _ in 0..self.read_count("vertex count")? {
+            // Position, normal, UV and additional UVs
+            self.skip(32 + 16 * self.additional_uvs, "vertex")?;
+            let [deform] = self.read_bytes("vertex deform type")?;
+            let weights = match deform {
+                0 => bone,                  // BDEF1
+                1 => 2 * bone + 4,          // BDEF2
+                2 | 4 => 4 * bone + 16,     // BDEF4, QDEF
+                3 => 2 * bone + 4 + 3 * 12, // SDEF
+                _ => return Err(format!("Unknown vertex deform type {}", deform).into()),
+            };
+            self.skip(weights + 4, "vertex weights")?;
+        }
+        Ok(())
+    }
+
+    fn skip_materials(&mut self) -> Result<(), Box<dyn std::error::Error>> {
+        for _ in 0..self.read_count("material count")? {
+            self.read_text("material name")?;
+            self.read_text("English material name")?;
+            // Colors, flags and edge
+            self.skip(16 + 12 + 4 + 12 + 1 + 16 + 4, "material")?;
+            self.skip(2 * self.texture_index_size + 1, "material textures")?;
+            let [shared_toon] = self.read_bytes("material toon flag")?;
+            let toon = if shared_toon == 0 {
+                self.texture_index_size
+            } else {
+                1
+            };
+            self.skip(toon, "material toon")?;
+            self.read_text("material memo")?;
+            self.skip(4, "material face count")?;
+        }
+        Ok(())
+    }
+
+    fn read_bone(&mut self) -> Result<PMXBone, Box<dyn std::error::Error>> {
+        let name = self.read_text("bone name")?;
+        let name_en = self.read_text("English bone name")?;
+        let position = self.read_vector("bone position")?;
+        let parent = self.read_bone_index("bone parent")?;
+        self.skip(4, "bone layer")?;
+        let flags = u16::from_le_bytes(self.read_bytes("bone flags")?);
+
+        let tail = if flags & TAIL_IS_BONE != 0 {
+            match self.read_bone_index("bone tail")? {
+                Some(bone) => PMXTail::Bone { bone },
+                None => PMXTail::Offset {
+                    offset: Vector3::new(0.0, 0.0, 0.0),
+                },
+            }
+        } else {
+            PMXTail::Offset {
+                offset: self.read_vector("bone tail")?,
+            }
+        };
+        if flags & (INHERIT_ROTATION | INHERIT_TRANSLATION) != 0 {
+            self.skip(self.bone_index_size + 4, "bone inheritance")?;
+        }
+        let fixed_axis = if flags & FIXED_AXIS != 0 {
+            Some(self.read_vector("bone fixed axis")?)
+        } else {
+            None
+        };
+        let local_axes = if flags & LOCAL_AXIS != 0 {
+            Some((
+                self.read_vector("bone local X axis")?,
+                self.read_vector("bone local Z axis")?,
+            ))
+        } else {
+            None
+        };
+        if flags & EXTERNAL_PARENT != 0 {
+            self.skip(4, "bone external parent")?;
+        }
+        let ik = if flags & IK != 0 {
+            Some(self.read_ik()?)
+        } else {
+            None
+        };
+
+        Ok(PMXBone {
+            name,
+            name_en,
+            position,
+            parent,
+            tail,
+            rotatable: flags & ROTATABLE != 0,
+            translatable: flags & TRANSLATABLE != 0,
+            fixed_axis,
+            local_axes,
+            ik,
+        })
+    }
+
+    fn read_ik(&mut self) -> Result<PMXIk, Box<dyn std::error::Error>> {
+        let target = self.read_bone_index("IK target")?;
+        let loops = i32::from_le_bytes(self.read_bytes("IK loop count")?);
+        let limit_angle = self.read_f32("IK limit angle")?;
+        let mut links = vec![];
+        for _ in 0..self.read_count("IK link count")? {
+            let bone = self.read_bone_index("IK link")?;
+            let [limited] = self.read_bytes("IK link limit flag")?;
+            let limits = if limited != 0 {
+                Some((
+                    self.read_vector("IK link lower limit")?,
+                    self.read_vector("IK link upper limit")?,
+                ))
+            } else {
+                None
+            };
+            links.push(PMXIkLink { bone, limits });
+        }
+        Ok(PMXIk {
+            target,
+            loops,
+            limit_angle,
+            links,
+        })
+    }
+
+    fn read_bytes<const N: usize>(
+        &mut self,
+        what: &str,
+    ) -> Result<[u8; N], Box<dyn std::error::Error>> {
+        let mut buffer = [0u8; N];
+        self.fill(&mut buffer, what)?;
+        Ok(buffer)
+    }
+
+    fn fill(&mut self, buffer: &mut [u8], what: &str) -> Result<(), Box<dyn std::error::Error>> {
+        let position = self.cursor.position();
+        self.cursor.read_exact(buffer).map_err(|_| {
+            format!(
+                "Unexpected end of PMX data while reading {} at byte {}",
+                what, position
+            )
+        })?;
+        Ok(())
+    }
+
+    fn skip(&mut self, size: usize, what: &str) -> Result<(), Box<dyn std::error::Error>> {
+        self.ensure(size, what)?;
+        let position = self.cursor.position() + size as u64;
+        self.cursor.set_position(position);
+        Ok(())
+    }
+
+    /// Fail before reading `size` bytes the data doesn't have, so a corrupt length
+    /// never turns into a huge allocation
+    fn ensure(&self, size: usize, what: &str) -> Result<(), Box<dyn std::error::Error>> {
+        let length = self.cursor.get_ref().len() as u64;
+        let remaining = length.saturating_sub(self.cursor.position());
+        if size as u64 > remaining {
+            return Err(format!(
+                "Unexpected end of PMX data while reading {} at byte {}",
+                what,
+                self.cursor.position()
+            )
+            .into());
+        }
+        Ok(())
+    }
+
+    fn read_f32(&mut self, what: &str) -> Result<f32, Box<dyn std::error::Error>> {
+        Ok(f32::from_le_bytes(self.read_bytes(what)?))
+    }
+
+    fn read_vector(&mut self, what: &str) -> Result<Vector3, Box<dyn std::error::Error>> {
+        Ok(Vector3::new(
+            self.read_f32(what)?,
+            self.read_f32(what)?,
+            self.read_f32(what)?,
+        ))
+    }
+
+    fn read_count(&mut self, what: &str) -> Result<usize, Box<dyn std::error::Error>> {
+        let count = i32::from_le_bytes(self.read_bytes(what)?);
+        usize::try_from(count).map_err(|_| format!("Negative {} {}", what, count).into())
+    }
+
+    /// A signed 1, 2 or 4 byte bone index, `None` for -1
+    fn read_bone_index(&mut self, what: &str) -> Result<Option<usize>, Box<dyn std::error::Error>> {
+        let index = match self.bone_index_size {
+            1 => i8::from_le_bytes(self.read_bytes(what)?) as i32,
+            2 => i16::from_le_bytes(self.read_bytes(what)?) as i32,
+            4 => i32::from_le_bytes(self.read_bytes(what)?),
+            size => return Err(format!("Invalid bone index size {}", size).into()),
+        };
+        Ok(usize::try_from(index).ok())
+    }
+
+    /// Length-prefixed UTF-16LE or UTF-8 text, as the header's encoding says
+    fn read_text(&mut self, what: &str) -> Result<String, Box<dyn std::error::Error>> {
+        let length = self.read_count(what)?;
+        self.ensure(length, what)?;
+        let mut buffer = vec![0u8; length];
+        self.fill(&mut buffer, what)?;
+        if self.utf8 {
+            return Ok(String::from_utf8_lossy(&buffer).into_owned());
+        }
+        let units: Vec<u16> = buffer
+            .chunks_exact(2)
+            .map(|pair| u16::from_le_bytes([pair[0], pair[1]]))
+            .collect();
+        Ok(String::from_utf16_lossy(&units))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A PMX 2.0 header with UTF-16 text and 4-byte indices, followed by `rest`
+    fn pmx(rest: &[u8]) -> Vec<u8> {
+        let mut data = MAGIC.to_vec();
+        data.extend(2.0f32.to_le_bytes());
+        data.extend([8, 0, 0, 4, 4, 4, 4, 4, 4]);
+        data.extend(rest);
+        data
+    }
+
+    #[test]
+    fn oversized_text_length_is_an_error() {
+        let error = PMXModel::parse(&pmx(&i32::MAX.to_le_bytes())).unwrap_err();
+        assert!(
+            error.to_string().starts_with("Unexpected end of PMX data"),
+            "{}",
+            error
+        );
+    }
+
+    #[test]
+    fn empty_model_parses() {
+        let mut rest = vec![];
+        // Names, comments and the vertex, face, texture, material and bone counts
+        for _ in 0..9 {
+            rest.extend(0i32.to_le_bytes());
+        }
+        let model = PMXModel::parse(&pmx(&rest)).unwrap();
+        assert!(model.bones.is_empty());
+    }
+
+    fn bone(name: &str, position: Vector3, parent: Option<usize>, offset: Vector3) -> PMXBone {
+        PMXBone {
+            name: name.to_string(),
+            name_en: String::new(),
+            position,
+            parent,
+            tail: PMXTail::Offset { offset },
+            rotatable: true,
+            translatable: false,
+            fixed_axis: None,
+            local_axes: None,
+            ik: None,
+        }
+    }
+
+    #[test]
+    fn rig_keeps_only_the_bones_the_model_has() {
+        let up = Vector3::new(0.0, 1.0, 0.0);
+        let model = PMXModel {
+            name: String::new(),
+            name_en: String::new(),
+            bones: vec![
+                bone("上半身", Vector3::new(0.0, 12.0, 0.0), None, up),
+                bone("胸", Vector3::new(0.0, 14.0, 0.0), Some(0), up),
+                bone("首", Vector3::new(0.0, 16.0, 0.0), Some(1), up),
+                // Pointing straight out instead of 45 degrees down
+                bone(
+                    "左ひじ",
+                    Vector3::new(3.0, 15.0, 0.0),
+                    Some(1),
+                    Vector3::new(1.0, 0.0, 0.0),
+                ),
+            ],
+        };
+        let rig = model.to_rig();
+        let profile = &rig.profile;
+
+        assert!(rig.missing_bones.contains(&"upper_body2".to_string()));
+        assert!(!profile.skeleton.contains_key("upper_body2"));
+        assert_eq!(profile.skeleton["upper_body"].parent, None);
+        // 胸 is not a standard bone, so the neck hangs from the upper body
+        assert_eq!(
+            profile.skeleton["neck"].parent.as_deref(),
+            Some("upper_body")
+        );
+        assert_eq!(
+            profile.skeleton["elbow_l"].parent.as_deref(),
+            Some("upper_body")
+        );
+
+        let builtin = &BoneActionDatabase::build_rules()["elbow_l"]["bend"]["forward"];
+        let turn = Quaternion::between(Vector3::new(1.0, -1.0, 0.0), Vector3::new(1.0, 0.0, 0.0));
+        let expected = turn.rotate(builtin.axis);
+        let [x, y, z] = profile.bones["elbow_l"]["bend"]["forward"].axis;
+        assert!(expected.sub(&Vector3::new(x, y, z)).length() < 0.001);
+
+        let database = profile.to_database().unwrap();
+        let skeleton = database.skeleton();
+        assert!(skeleton.joint("upper_body2").is_none());
+        assert!(skeleton.joint("center").is_none());
+        assert_eq!(skeleton.joint("neck").unwrap().position.y, 16.0);
+    }
+}
@@ -1,54 +1,48 @@
 use std::collections::HashMap;
+use std::fmt;
 
 use serde::{Deserialize, Serialize};
 
 use crate::{
-    mpl::MPLBoneFrame,
+    look::{self, LOOK_WEIGHTS},
+    mpl::{MPLBoneFrame, MPLMorphFrame},
+    reach,
     utils::{Quaternion, Vector3},
-    with_bone_db, ActionRule,
+    vpd::VPDPose,
+    with_bone_db, ActionKind, ActionRule, MOVE_ACTION,
 };
 
+/// A bone rotated or moved, `head turn left 30;`. Rules come from `with_bone_db`:
+/// the built-in rig unless called inside `MPLCompiler::scope` or `using_bone_db`.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct MPLPoseStatement {
     pub bone: String,
     pub action: String,
     pub direction: String,
+    /// Degrees, or MMD units for `move`
     pub degrees: f32,
 }
 
 impl MPLPoseStatement {
-    pub fn from_str(text: &str) -> Result<Self, String> {
-        if text.is_empty() {
-            return Err("Empty statement".to_string());
-        }
-        let parts = text.split_whitespace().collect::<Vec<&str>>();
-        if parts.len() != 4 {
-            return Err("Invalid statement".to_string());
-        }
-
-        let bone = parts[0].to_string();
-        let action = parts[1].to_string();
-        let direction = parts[2].to_string();
-        let degrees: f32 = parts[3]
-            .trim()
-            .parse()
-            .map_err(|_| "Invalid degrees number".to_string())?;
-
-        with_bone_db(|db| db.validate(&bone, &action, &direction, degrees))?;
+    pub fn new(bone: &str, action: &str, direction: &str, degrees: f32) -> Result<Self, String> {
+        with_bone_db(|db| db.validate(bone, action, direction, degrees))?;
 
         Ok(Self {
-            bone,
-            action,
-            direction,
+            bone: bone.to_string(),
+            action: action.to_string(),
+            direction: direction.to_string(),
             degrees,
         })
     }
 
-    pub fn to_string(&self) -> String {
-        format!(
-            "{} {} {} {:.0};",
-            self.bone, self.action, self.direction, self.degrees
-        )
+    /// Left/right mirror image: `_l` and `_r` bones swap, `bend` keeps its direction and
+    /// `turn`, `sway` and `move` swap left and right, so with the built-in rules the
+    /// rotation is the original's with y and z negated. The result is validated like a
+    /// written statement.
+    pub fn mirrored(&self) -> Result<Self, String> {
+        let (bone, action, direction) =
+            with_bone_db(|db| db.mirror_rule(&self.bone, &self.action, &self.direction))?;
+        Self::new(&bone, &action, &direction, self.degrees)
     }
 
     pub fn to_quaternion(&self) -> Quaternion {
@@ -58,8 +52,8 @@ impl MPLPoseStatement {
         });
 
         let rule = match rule {
-            Some(r) => r,
-            None => return Quaternion::identity(),
+            Some(r) if r.kind == ActionKind::Rotation => r,
+            _ => return Quaternion::identity(),
         };
 
         let normalized_axis = rule.axis.normalize();
@@ -77,6 +71,53 @@ impl MPLPoseStatement {
         )
     }
 
+    /// Offset of a `move` statement in MMD units, zero for rotations
+    pub fn to_translation(&self) -> Vector3 {
+        match with_bone_db(|db| {
+            db.get_rule(&self.bone, &self.action, &self.direction)
+                .cloned()
+        }) {
+            Some(rule) if rule.kind == ActionKind::Translation => {
+                let axis = rule.axis.normalize();
+                Vector3::new(
+                    axis.x * self.degrees,
+                    axis.y * self.degrees,
+                    axis.z * self.degrees,
+                )
+            }
+            _ => Vector3::new(0.0, 0.0, 0.0),
+        }
+    }
+
+    /// `move` statements reproducing a bone offset, clamped to the bone's limits
+    pub fn from_translation(bone: &str, position: Vector3) -> Vec<Self> {
+        let directions = with_bone_db(|db| {
+            let mut directions: Vec<(String, ActionRule)> = db
+                .directions(bone, MOVE_ACTION)
+                .unwrap_or_default()
+                .iter()
+                .filter_map(|d| Some((d.clone(), db.get_rule(bone, MOVE_ACTION, d)?.clone())))
+                .collect();
+            directions.sort_by(|a, b| a.0.cmp(&b.0));
+            directions
+        });
+
+        directions
+            .into_iter()
+            .filter_map(|(direction, rule)| {
+                let axis = rule.axis.normalize();
+                let distance = position.x * axis.x + position.y * axis.y + position.z * axis.z;
+                let distance = (distance.min(rule.limit) * 100.0).round() / 100.0;
+                (distance > 0.0).then(|| Self {
+                    bone: bone.to_string(),
+                    action: MOVE_ACTION.to_string(),
+                    direction,
+                    degrees: distance,
+                })
+            })
+            .collect()
+    }
+
     pub fn from_quaternion(bone: &str, target_quat: Quaternion) -> Vec<Self> {
         let bone = bone.to_string();
 
@@ -87,7 +128,10 @@ impl MPLPoseStatement {
                 for action in actions {
                     if let Some(directions) = db.directions(&bone, action) {
                         for direction in directions {
-                            if let Some(rule) = db.get_rule(&bone, action, direction) {
+                            if let Some(rule) = db
+                                .get_rule(&bone, action, direction)
+                                .filter(|rule| rule.kind == ActionKind::Rotation)
+                            {
                                 vec.push((action.to_string(), direction.to_string(), rule.clone()));
                             }
                         }
@@ -166,13 +210,13 @@ impl MPLPoseStatement {
 
                 // Calculate centroid (excluding worst point)
                 let mut centroid = vec![0.0f32; n];
-                for i in 0..n {
-                    for j in 0..n {
-                        centroid[j] += simplex[i].0[j];
+                for (point, _) in simplex.iter().take(n) {
+                    for (c, p) in centroid.iter_mut().zip(point) {
+                        *c += p;
                     }
                 }
-                for j in 0..n {
-                    centroid[j] /= n as f32;
+                for c in centroid.iter_mut() {
+                    *c /= n as f32;
                 }
 
                 // Reflection step
@@ -220,11 +264,11 @@ impl MPLPoseStatement {
 
                 // Shrinkage step
                 let best_point = simplex[0].0.clone();
-                for i in 1..=n {
-                    for j in 0..n {
-                        simplex[i].0[j] = best_point[j] + sigma * (simplex[i].0[j] - best_point[j]);
+                for vertex in simplex.iter_mut().skip(1) {
+                    for (v, b) in vertex.0.iter_mut().zip(&best_point) {
+                        *v = b + sigma * (*v - b);
                     }
-                    simplex[i].1 = evaluate_combination(&simplex[i].0);
+                    vertex.1 = evaluate_combination(&vertex.0);
                 }
             }
 
@@ -332,58 +376,423 @@ impl MPLPoseStatement {
             }
         }
 
-        // Format statements to match TypeScript output format
+        // Drop statements that would print as zero degrees
         statements
             .into_iter()
-            .filter(|stmt| !format!("{:.0}", stmt.degrees).ends_with("0"))
+            .filter(|stmt| format!("{:.0}", stmt.degrees) != "0")
             .collect()
     }
 }
 
+impl fmt::Display for MPLPoseStatement {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.action == MOVE_ACTION {
+            // Distances keep two decimals, trailing zeros dropped
+            let distance = format!("{:.2}", self.degrees);
+            let distance = distance.trim_end_matches('0').trim_end_matches('.');
+            write!(
+                f,
+                "{} {} {} {};",
+                self.bone, self.action, self.direction, distance
+            )
+        } else {
+            write!(
+                f,
+                "{} {} {} {:.0};",
+                self.bone, self.action, self.direction, self.degrees
+            )
+        }
+    }
+}
+
+/// A facial expression weight, `morph smile 0.8;`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MPLMorphStatement {
+    pub morph: String,
+    pub weight: f32,
+}
+
+impl MPLMorphStatement {
+    pub fn new(morph: &str, weight: f32) -> Result<Self, String> {
+        if with_bone_db(|db| db.morph_japanese_name(morph).is_none()) {
+            return Err(format!("Unknown morph '{}'", morph));
+        }
+        if !(0.0..=1.0).contains(&weight) {
+            return Err(format!("Morph weight {} must be between 0 and 1", weight));
+        }
+
+        Ok(Self {
+            morph: morph.to_string(),
+            weight,
+        })
+    }
+
+    /// The same weight on the mirror image morph, e.g. `wink_r` for `wink`
+    pub fn mirrored(&self) -> Result<Self, String> {
+        let morph = with_bone_db(|db| db.mirror_morph(&self.morph).map(str::to_string))
+            .ok_or(format!("Morph '{}' has no mirror image", self.morph))?;
+        Self::new(&morph, self.weight)
+    }
+
+    pub fn to_morph_frame(&self) -> MPLMorphFrame {
+        let name_jp = with_bone_db(|db| {
+            db.morph_japanese_name(&self.morph)
+                .unwrap_or(&self.morph)
+                .to_string()
+        });
+        MPLMorphFrame {
+            name_en: self.morph.clone(),
+            name_jp,
+            weight: self.weight,
+        }
+    }
+}
+
+impl fmt::Display for MPLMorphStatement {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "morph {} {};", self.morph, self.weight)
+    }
+}
+
+/// An IK chain switched on or off, `ik leg_l off;`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MPLIkStatement {
+    pub ik: String,
+    pub enabled: bool,
+}
+
+impl MPLIkStatement {
+    pub fn new(ik: &str, enabled: bool) -> Result<Self, String> {
+        if with_bone_db(|db| db.ik_japanese_name(ik).is_none()) {
+            return Err(format!("Unknown IK '{}'", ik));
+        }
+
+        Ok(Self {
+            ik: ik.to_string(),
+            enabled,
+        })
+    }
+
+    /// The same switch on the other side's IK chain
+    pub fn mirrored(&self) -> Result<Self, String> {
+        let ik = with_bone_db(|db| db.mirror_ik(&self.ik).map(str::to_string))
+            .ok_or(format!("IK '{}' has no mirror image", self.ik))?;
+        Self::new(&ik, self.enabled)
+    }
+}
+
+impl fmt::Display for MPLIkStatement {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let state = if self.enabled { "on" } else { "off" };
+        write!(f, "ik {} {};", self.ik, state)
+    }
+}
+
+/// A hand or foot placed on a point by turning its limb, `hand_l reach (4, 14, -3);`,
+/// or set down with the hand or foot kept level, `foot_r plant ground;`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MPLReachStatement {
+    pub limb: String,
+    pub target: MPLReachTarget,
+    /// Keep the hand or foot at the orientation it has with the limb straight
+    pub plant: bool,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum MPLReachTarget {
+    /// Model space, in MMD units
+    Point { position: Vector3 },
+    /// Straight below where the hand or foot is with the limb straight, on the floor
+    Ground,
+}
+
+impl MPLReachStatement {
+    pub fn new(limb: &str, target: MPLReachTarget, plant: bool) -> Result<Self, String> {
+        if reach::limb_bones(limb).is_none() {
+            return Err(format!("Unknown limb '{}'", limb));
+        }
+
+        Ok(Self {
+            limb: limb.to_string(),
+            target,
+            plant,
+        })
+    }
+
+    /// The other side's limb reaching for the target reflected across the model's
+    /// center line
+    pub fn mirrored(&self) -> Result<Self, String> {
+        let limb = reach::mirror_limb(&self.limb)
+            .ok_or(format!("Limb '{}' has no mirror image", self.limb))?;
+        let target = match self.target {
+            MPLReachTarget::Point { position } => MPLReachTarget::Point {
+                position: Vector3::new(-position.x, position.y, position.z),
+            },
+            MPLReachTarget::Ground => MPLReachTarget::Ground,
+        };
+        Self::new(limb, target, self.plant)
+    }
+}
+
+impl fmt::Display for MPLReachStatement {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let verb = if self.plant { "plant" } else { "reach" };
+        match self.target {
+            MPLReachTarget::Point { position } => {
+                write!(
+                    f,
+                    "{} {} ({}, {}, {});",
+                    self.limb,
+                    verb,
+                    coordinate(position.x),
+                    coordinate(position.y),
+                    coordinate(position.z)
+                )
+            }
+            MPLReachTarget::Ground => write!(f, "{} {} ground;", self.limb, verb),
+        }
+    }
+}
+
+/// Coordinates keep two decimals, trailing zeros dropped
+fn coordinate(value: f32) -> String {
+    let value = format!("{:.2}", value);
+    value
+        .trim_end_matches('0')
+        .trim_end_matches('.')
+        .to_string()
+}
+
+/// The upper body, neck and head turned toward a point, `look_at (0, 16, -20);`, each
+/// by its share of the turn. The eyes take what the limits of the others leave.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MPLLookStatement {
+    /// Model space, in MMD units
+    pub target: Vector3,
+    /// Shares of the turn by bone; bones left out keep their default share
+    #[serde(default)]
+    pub weights: Vec<(String, f32)>,
+}
+
+impl MPLLookStatement {
+    pub fn new(target: Vector3, weights: Vec<(String, f32)>) -> Result<Self, String> {
+        for (bone, weight) in &weights {
+            if !LOOK_WEIGHTS.iter().any(|(known, _)| known == bone) {
+                let known: Vec<&str> = LOOK_WEIGHTS.iter().map(|(bone, _)| *bone).collect();
+                return Err(format!(
+                    "Bone '{}' cannot share a look, only {} can",
+                    bone,
+                    known.join(", ")
+                ));
+            }
+            if !(0.0..=1.0).contains(weight) {
+                return Err(format!(
+                    "Look weight of {} must be between 0 and 1, got {}",
+                    bone, weight
+                ));
+            }
+        }
+
+        let look = Self { target, weights };
+        let total: f32 = LOOK_WEIGHTS.iter().map(|(bone, _)| look.weight(bone)).sum();
+        if total > 1.0 + 1e-4 {
+            return Err(format!("Look weights add up to {:.2}, more than 1", total));
+        }
+        Ok(look)
+    }
+
+    /// The share of the turn `bone` takes
+    pub fn weight(&self, bone: &str) -> f32 {
+        match self
+            .weights
+            .iter()
+            .rev()
+            .find(|(weighted, _)| weighted == bone)
+        {
+            Some((_, weight)) => *weight,
+            None => LOOK_WEIGHTS
+                .iter()
+                .find(|(weighted, _)| *weighted == bone)
+                .map_or(0.0, |(_, weight)| *weight),
+        }
+    }
+
+    /// Looking at the target reflected across the model's center line
+    pub fn mirrored(&self) -> Self {
+        Self {
+            target: Vector3::new(-self.target.x, self.target.y, self.target.z),
+            weights: self.weights.clone(),
+        }
+    }
+}
+
+impl fmt::Display for MPLLookStatement {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "look_at ({}, {}, {})",
+            coordinate(self.target.x),
+            coordinate(self.target.y),
+            coordinate(self.target.z)
+        )?;
+        if !self.weights.is_empty() {
+            let weights: Vec<String> = self
+                .weights
+                .iter()
+                .map(|(bone, weight)| format!("{} {}", bone, weight))
+                .collect();
+            write!(f, " with {}", weights.join(", "))?;
+        }
+        write!(f, ";")
+    }
+}
+
+/// A named set of statements. Its frames are built against `with_bone_db`, like
+/// `MPLPoseStatement`.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct MPLPose {
     pub name: String,
     pub statements: Vec<MPLPoseStatement>,
+    #[serde(default)]
+    pub morphs: Vec<MPLMorphStatement>,
+    /// IK switches, applied from the keyframe that plays this pose on
+    #[serde(default)]
+    pub ik: Vec<MPLIkStatement>,
+    /// Upper body, neck, head and eyes turned toward a point after the statements
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub look: Option<MPLLookStatement>,
+    /// Limbs solved onto their targets after the statements and look, in order
+    #[serde(default)]
+    pub reaches: Vec<MPLReachStatement>,
+    /// Model visibility from the keyframe that plays this pose on
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub show: Option<bool>,
+    /// Printed as a `//` comment above the pose, e.g. the error of a reverse compile
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub comment: Option<String>,
 }
 
 impl MPLPose {
     pub fn new(name: String, statements: Vec<MPLPoseStatement>) -> Self {
-        Self { name, statements }
+        Self {
+            name,
+            statements,
+            morphs: vec![],
+            ik: vec![],
+            look: None,
+            reaches: vec![],
+            show: None,
+            comment: None,
+        }
     }
 
-    pub fn to_string(&self) -> String {
-        format!(
-            "@pose {} {{\n{}\n}}\n\nmain {{\n    {};\n}}",
-            self.name,
-            self.statements
-                .iter()
-                .map(|s| format!("    {}", s.to_string()))
-                .collect::<Vec<String>>()
-                .join("\n"),
-            self.name
-        )
+    pub fn with_look(mut self, look: Option<MPLLookStatement>) -> Self {
+        self.look = look;
+        self
+    }
+
+    pub fn with_reaches(mut self, reaches: Vec<MPLReachStatement>) -> Self {
+        self.reaches = reaches;
+        self
+    }
+
+    pub fn with_morphs(mut self, morphs: Vec<MPLMorphStatement>) -> Self {
+        self.morphs = morphs;
+        self
+    }
+
+    pub fn with_ik(mut self, ik: Vec<MPLIkStatement>, show: Option<bool>) -> Self {
+        self.ik = ik;
+        self.show = show;
+        self
+    }
+
+    pub fn with_comment(mut self, comment: impl Into<String>) -> Self {
+        self.comment = Some(comment.into());
+        self
     }
 
+    /// One frame per morph; a morph set twice keeps its last weight
+    pub fn to_morph_frames(&self) -> Vec<MPLMorphFrame> {
+        let mut frames: Vec<MPLMorphFrame> = vec![];
+        for morph in &self.morphs {
+            frames.retain(|frame| frame.name_en != morph.morph);
+            frames.push(morph.to_morph_frame());
+        }
+        frames
+    }
+
+    /// Frames of the statements, turned toward the look target and with the limbs of
+    /// reach statements turned onto theirs
     pub fn to_bone_frames(&self) -> Vec<MPLBoneFrame> {
+        self.solve_reaches().0
+    }
+
+    /// How far each reach statement's hand or foot ends up from its target in MMD
+    /// units, or why it couldn't be solved
+    pub fn reach_misses(&self) -> Vec<Result<f32, String>> {
+        self.solve_reaches().1
+    }
+
+    /// Why the look statement couldn't turn the pose toward its target, if it couldn't
+    pub fn look_error(&self) -> Option<String> {
+        let look = self.look.as_ref()?;
+        with_bone_db(|db| look::solve(db, self.statement_frames(), look)).err()
+    }
+
+    fn solve_reaches(&self) -> (Vec<MPLBoneFrame>, Vec<Result<f32, String>>) {
+        let mut frames = self.statement_frames();
+        if let Some(look) = &self.look {
+            // A skeleton without the bones to look with leaves the statements as they are
+            if let Ok(looked) = with_bone_db(|db| look::solve(db, frames.clone(), look)) {
+                frames = looked;
+            }
+        }
+        let mut misses = vec![];
+        for statement in &self.reaches {
+            match with_bone_db(|db| reach::solve(db, frames.clone(), statement)) {
+                Ok((solved, miss)) => {
+                    frames = solved;
+                    misses.push(Ok(miss));
+                }
+                Err(e) => misses.push(Err(e)),
+            }
+        }
+        (frames, misses)
+    }
+
+    fn statement_frames(&self) -> Vec<MPLBoneFrame> {
         let mut frames = vec![];
 
-        let mut bone_groups: HashMap<String, Vec<&MPLPoseStatement>> = HashMap::new();
+        // Bones in the order the script first names them, so output is reproducible
+        let mut bone_groups: Vec<(String, Vec<&MPLPoseStatement>)> = vec![];
         for statement in &self.statements {
-            bone_groups
-                .entry(statement.bone.clone())
-                .or_insert_with(Vec::new)
-                .push(statement);
+            match bone_groups
+                .iter_mut()
+                .find(|(bone, _)| *bone == statement.bone)
+            {
+                Some((_, group)) => group.push(statement),
+                None => bone_groups.push((statement.bone.clone(), vec![statement])),
+            }
         }
 
         for (bone, bone_statements) in bone_groups {
             let mut combined_quaternion = Quaternion::identity();
+            let mut position = Vector3::new(0.0, 0.0, 0.0);
 
             for statement in bone_statements {
                 let quaternion = statement.to_quaternion();
                 combined_quaternion = combined_quaternion.multiply(&quaternion);
+
+                let offset = statement.to_translation();
+                position = Vector3::new(
+                    position.x + offset.x,
+                    position.y + offset.y,
+                    position.z + offset.z,
+                );
             }
 
-            let position = Vector3::new(0.0, 0.0, 0.0);
             let bone_name_jp =
                 with_bone_db(|db| db.japanese_name(&bone).unwrap_or(&bone).to_string());
 
@@ -398,14 +807,221 @@ impl MPLPose {
         frames
     }
 
+    /// Reverse a set of bone rotations into statements. The pose carries a comment
+    /// recording how far the printed statements are from the original rotations.
     pub fn from_bone_frames(name: &str, frames: Vec<MPLBoneFrame>) -> Self {
         let mut statements = vec![];
+        let mut errors: Vec<(String, f32)> = vec![];
+        let mut unsupported = vec![];
+
         for frame in frames.iter() {
-            statements.extend(MPLPoseStatement::from_quaternion(
-                &frame.name_en(),
-                frame.rotation(),
-            ));
+            let bone = frame.name_en();
+            if with_bone_db(|db| db.actions(&bone).is_none()) {
+                unsupported.push(bone);
+                continue;
+            }
+            let bone_statements = MPLPoseStatement::from_quaternion(&bone, frame.rotation());
+
+            // Measure against the rotation the printed (rounded) statements produce
+            let reconstructed = bone_statements
+                .iter()
+                .map(|s| MPLPoseStatement {
+                    degrees: s.degrees.round(),
+                    ..s.clone()
+                })
+                .fold(Quaternion::identity(), |q, s| {
+                    q.multiply(&s.to_quaternion())
+                });
+            errors.push((bone.clone(), reconstructed.angle_to(&frame.rotation())));
+
+            statements.extend(bone_statements);
+            statements.extend(MPLPoseStatement::from_translation(&bone, frame.position()));
+        }
+
+        let mut comment = match errors
+            .iter()
+            .max_by(|a, b| a.1.partial_cmp(&b.1).unwrap_or(std::cmp::Ordering::Equal))
+        {
+            Some((bone, max)) => {
+                let mean = errors.iter().map(|(_, e)| e).sum::<f32>() / errors.len() as f32;
+                format!(
+                    "reconstruction error: max {:.1} deg ({}), mean {:.1} deg",
+                    max, bone, mean
+                )
+            }
+            None => "reconstruction error: no supported bones".to_string(),
+        };
+        if !unsupported.is_empty() {
+            comment.push_str(&format!("\nskipped bones: {}", unsupported.join(", ")));
+        }
+
+        Self::new(name.to_string(), statements).with_comment(comment)
+    }
+
+    /// Reverse the bone rotations and known morphs of a VPD file into a pose
+    pub fn from_vpd(name: &str, data: &[u8]) -> Result<Self, String> {
+        let vpd = VPDPose::parse(data)?;
+        let morphs = vpd
+            .morph_frames
+            .iter()
+            .filter(|frame| frame.weight > 0.0)
+            .filter_map(|frame| {
+                let morph =
+                    with_bone_db(|db| db.morph_english_name(&frame.name_jp).map(str::to_string))?;
+                MPLMorphStatement::new(&morph, frame.weight.min(1.0)).ok()
+            })
+            .collect();
+        Ok(Self::from_bone_frames(name, vpd.bone_frames).with_morphs(morphs))
+    }
+
+    /// Shift-JIS VPD file of this pose for the given parent model file
+    pub fn to_vpd(&self, model_name: &str) -> Vec<u8> {
+        let mut vpd = VPDPose::new(model_name, self.to_bone_frames());
+        vpd.morph_frames = self.to_morph_frames();
+        vpd.to_bytes()
+    }
+
+    /// The `@pose` block alone, preceded by its comment
+    pub fn to_block(&self) -> String {
+        let mut block = String::new();
+        if let Some(comment) = &self.comment {
+            for line in comment.lines() {
+                block.push_str(&format!("// {}\n", line));
+            }
+        }
+        block.push_str(&format!(
+            "@pose {} {{\n{}\n}}",
+            self.name,
+            self.statements
+                .iter()
+                .map(|s| format!("    {}", s))
+                .chain(self.morphs.iter().map(|m| format!("    {}", m)))
+                .chain(self.ik.iter().map(|ik| format!("    {}", ik)))
+                .chain(self.look.iter().map(|look| format!("    {}", look)))
+                .chain(self.reaches.iter().map(|reach| format!("    {}", reach)))
+                .chain(
+                    self.show
+                        .map(|show| { format!("    show {};", if show { "on" } else { "off" }) })
+                )
+                .collect::<Vec<String>>()
+                .join("\n")
+        ));
+        block
+    }
+}
+
+impl fmt::Display for MPLPose {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}\n\nmain {{\n    {};\n}}", self.to_block(), self.name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bone_frames_follow_the_script_order() {
+        let compiler = crate::MPLCompiler::new();
+        let text = "@pose a { neck bend forward 5; head turn left 10; arm_l sway left 20; \
+                    center move up 1; neck turn right 5; } main { a; }";
+        let pose = &compiler.check(text).program.poses["a"];
+        let names: Vec<String> = pose.to_bone_frames().iter().map(|f| f.name_en()).collect();
+        assert_eq!(names, ["neck", "head", "arm_l", "center"]);
+
+        let vmd = || {
+            let motion = compiler.compile_motion(text).unwrap();
+            crate::VMDWriter::from(motion).create_vmd().unwrap()
+        };
+        assert_eq!(vmd(), vmd());
+    }
+
+    fn rules() -> Vec<(String, String, String, f32)> {
+        with_bone_db(|db| {
+            let mut rules = vec![];
+            for bone in db.bones() {
+                for action in db.actions(bone).unwrap_or_default() {
+                    for direction in db.directions(bone, action).unwrap_or_default() {
+                        let limit = db.get_rule(bone, action, direction).unwrap().limit;
+                        rules.push((bone.clone(), action.clone(), direction.clone(), limit));
+                    }
+                }
+            }
+            rules
+        })
+    }
+
+    #[test]
+    fn mirrored_rules_reflect_across_the_center_plane() {
+        for (bone, action, direction, limit) in rules() {
+            let statement =
+                MPLPoseStatement::new(&bone, &action, &direction, limit.min(10.0)).unwrap();
+            let mirrored = statement.mirrored().unwrap();
+            let flipped = match (action.as_str(), direction.as_str()) {
+                ("bend", _) => direction.as_str(),
+                (_, "left") => "right",
+                (_, "right") => "left",
+                (_, other) => other,
+            };
+            assert_eq!(
+                (mirrored.action.as_str(), mirrored.direction.as_str()),
+                (action.as_str(), flipped),
+                "{} mirrors to {}",
+                statement,
+                mirrored
+            );
+
+            // Reflecting across x = 0 negates the y and z of a rotation and the x of a move
+            let (q, m) = (statement.to_quaternion(), mirrored.to_quaternion());
+            let expected = Quaternion::new(q.x, -q.y, -q.z, q.w);
+            assert!(
+                m.dot(&expected).abs() > 0.9999,
+                "{} mirrors to {}",
+                statement,
+                mirrored
+            );
+            let (t, n) = (statement.to_translation(), mirrored.to_translation());
+            assert!(
+                n.sub(&Vector3::new(-t.x, t.y, t.z)).length() < 1e-4,
+                "{} mirrors to {}",
+                statement,
+                mirrored
+            );
+        }
+    }
+
+    #[test]
+    fn wrist_and_finger_mirrors_round_trip() {
+        for (bone, action, direction, mirror) in [
+            ("wrist_l", "bend", "backward", "wrist_r bend backward 10;"),
+            ("wrist_r", "sway", "left", "wrist_l sway right 10;"),
+            ("index_0_l", "sway", "left", "index_0_r sway right 10;"),
+            ("pinky_0_r", "sway", "right", "pinky_0_l sway left 10;"),
+            ("thumb_0_r", "sway", "left", "thumb_0_l sway right 10;"),
+        ] {
+            let statement = MPLPoseStatement::new(bone, action, direction, 10.0).unwrap();
+            let mirrored = statement.mirrored().unwrap();
+            assert_eq!(mirrored.to_string(), mirror);
+            assert_eq!(
+                mirrored.mirrored().unwrap().to_string(),
+                statement.to_string()
+            );
+        }
+    }
+
+    #[test]
+    fn from_quaternion_keeps_multiples_of_ten_degrees() {
+        for degrees in [10.0, 20.0, 30.0] {
+            let rotation = MPLPoseStatement::new("head", "turn", "left", degrees)
+                .unwrap()
+                .to_quaternion();
+            let statements = MPLPoseStatement::from_quaternion("head", rotation);
+            let turn = statements
+                .iter()
+                .find(|s| s.action == "turn" && s.direction == "left")
+                .expect("turn statement was dropped");
+            assert!((turn.degrees - degrees).abs() < 2.0, "{}", turn);
         }
-        Self::new(name.to_string(), statements.into_iter().collect())
+        assert!(MPLPoseStatement::from_quaternion("head", Quaternion::identity()).is_empty());
     }
 }
@@ -0,0 +1,401 @@
+use std::collections::BTreeMap;
+
+use crate::{
+    bone::{ActionKind, BoneActionDatabase},
+    mpl::MPLBoneFrame,
+    pose::{MPLPoseStatement, MPLReachStatement, MPLReachTarget},
+    skeleton::JointTransform,
+    utils::{Quaternion, Vector3},
+};
+
+/// How far in MMD units a solved hand or foot may end up from its target before the
+/// target counts as out of reach
+pub const REACH_TOLERANCE: f32 = 0.05;
+
+/// Limb -> upper bone, lower bone and the bone placed on the target
+const LIMBS: &[(&str, [&str; 3])] = &[
+    ("hand_l", ["arm_l", "elbow_l", "wrist_l"]),
+    ("hand_r", ["arm_r", "elbow_r", "wrist_r"]),
+    ("foot_l", ["leg_l", "knee_l", "ankle_l"]),
+    ("foot_r", ["leg_r", "knee_r", "ankle_r"]),
+];
+
+pub fn limbs() -> Vec<&'static str> {
+    LIMBS.iter().map(|(limb, _)| *limb).collect()
+}
+
+pub(crate) fn limb_bones(limb: &str) -> Option<[&'static str; 3]> {
+    LIMBS
+        .iter()
+        .find(|(name, _)| *name == limb)
+        .map(|(_, bones)| *bones)
+}
+
+pub(crate) fn mirror_limb(limb: &str) -> Option<&'static str> {
+    let mirrored = match limb.strip_suffix("_l") {
+        Some(stem) => format!("{}_r", stem),
+        None => format!("{}_l", limb.strip_suffix("_r")?),
+    };
+    limbs().into_iter().find(|limb| *limb == mirrored)
+}
+
+/// One rotation action of a bone as a signed angle about a single axis
+struct Hinge {
+    /// Unit axis in the bone's rest frame
+    axis: Vector3,
+    /// Degrees, negative turning against `axis`
+    min: f32,
+    max: f32,
+}
+
+impl Hinge {
+    fn clamp(&self, degrees: f32) -> f32 {
+        degrees.clamp(self.min, self.max)
+    }
+
+    fn rotation(&self, degrees: f32) -> Quaternion {
+        Quaternion::from_axis_angle(self.axis, degrees)
+    }
+}
+
+/// The rotation actions of a bone, the ones most perpendicular to `along` first so
+/// twists come last
+fn hinges(db: &BoneActionDatabase, bone: &str, along: Vector3) -> Vec<Hinge> {
+    let mut actions = db.actions(bone).unwrap_or_default().to_vec();
+    actions.sort();
+
+    let mut hinges = vec![];
+    for action in actions {
+        let mut directions = db.directions(bone, &action).unwrap_or_default().to_vec();
+        directions.sort();
+        let rules: Vec<_> = directions
+            .iter()
+            .filter_map(|direction| db.get_rule(bone, &action, direction))
+            .filter(|rule| rule.kind == ActionKind::Rotation)
+            .collect();
+        let Some(first) = rules.first() else {
+            continue;
+        };
+
+        let axis = first.axis.normalize();
+        let mut hinge = Hinge {
+            axis,
+            min: 0.0,
+            max: 0.0,
+        };
+        for rule in &rules {
+            if rule.axis.dot(&axis) > 0.0 {
+                hinge.max = hinge.max.max(rule.limit);
+            } else {
+                hinge.min = hinge.min.min(-rule.limit);
+            }
+        }
+        hinges.push(hinge);
+    }
+
+    let along = along.normalize();
+    hinges.sort_by(|a, b| {
+        a.axis
+            .dot(&along)
+            .abs()
+            .total_cmp(&b.axis.dot(&along).abs())
+    });
+    hinges
+}
+
+/// Angles in degrees that turn `v` about the unit `axis` so its dot product with `u`
+/// is `target`, or the one angle that comes closest when none does
+fn angles_for_dot(axis: Vector3, v: Vector3, u: Vector3, target: f32) -> Vec<f32> {
+    let parallel = axis.scale(v.dot(&axis));
+    let perpendicular = v.sub(&parallel);
+    let a = u.dot(&perpendicular);
+    let b = u.dot(&axis.cross(&perpendicular));
+    let c = target - u.dot(&parallel);
+
+    let amplitude = (a * a + b * b).sqrt();
+    let phase = b.atan2(a);
+    if amplitude < 1e-6 {
+        return vec![0.0];
+    }
+    let ratio = c / amplitude;
+    if ratio >= 1.0 {
+        vec![phase.to_degrees()]
+    } else if ratio <= -1.0 {
+        vec![(phase + std::f32::consts::PI).to_degrees()]
+    } else {
+        let spread = ratio.acos();
+        vec![(phase + spread).to_degrees(), (phase - spread).to_degrees()]
+    }
+}
+
+/// The same angle within -180..180
+fn wrap(degrees: f32) -> f32 {
+    (degrees + 180.0).rem_euclid(360.0) - 180.0
+}
+
+/// Rotation about at most two hinges that turns the unit vector `w` closest to `t`.
+/// The hinge applied second can only keep the component along its axis, so the first
+/// one lines that up and the second turns the rest; both orders are tried.
+fn aim(hinges: &[Hinge], w: Vector3, t: Vector3) -> Quaternion {
+    let orders: Vec<(&Hinge, Option<&Hinge>)> = match hinges {
+        [only] => vec![(only, None)],
+        [first, second, ..] => vec![(first, Some(second)), (second, Some(first))],
+        [] => return Quaternion::identity(),
+    };
+
+    let mut best: Option<(f32, f32, Quaternion)> = None;
+    for (outer, inner) in orders {
+        let inner_angles = match inner {
+            Some(inner) => angles_for_dot(inner.axis, w, outer.axis, outer.axis.dot(&t)),
+            None => vec![0.0],
+        };
+        for inner_angle in inner_angles {
+            let inner_angle = inner.map_or(0.0, |inner| inner.clamp(wrap(inner_angle)));
+            let inner_rotation =
+                inner.map_or(Quaternion::identity(), |inner| inner.rotation(inner_angle));
+            let turned = inner_rotation.rotate(w);
+
+            let flat = |v: Vector3| v.sub(&outer.axis.scale(v.dot(&outer.axis)));
+            let (from, to) = (flat(turned), flat(t));
+            let outer_angle = outer.clamp(
+                outer
+                    .axis
+                    .dot(&from.cross(&to))
+                    .atan2(from.dot(&to))
+                    .to_degrees(),
+            );
+            let rotation = outer.rotation(outer_angle).multiply(&inner_rotation);
+
+            let miss = rotation.rotate(w).sub(&t).length();
+            let effort = outer_angle.abs() + inner_angle.abs();
+            if best.is_none_or(|(best_miss, best_effort, _)| {
+                // Ignore float noise between exact solutions so effort decides
+                miss < best_miss - 1e-4 || (miss < best_miss + 1e-4 && effort < best_effort)
+            }) {
+                best = Some((miss, effort, rotation));
+            }
+        }
+    }
+    best.map_or(Quaternion::identity(), |(_, _, rotation)| rotation)
+}
+
+/// Replace the rotation of `bone`, keeping any translation its frame has
+pub(crate) fn set_rotation(
+    db: &BoneActionDatabase,
+    frames: &mut Vec<MPLBoneFrame>,
+    bone: &str,
+    rotation: Quaternion,
+) {
+    let position = match frames.iter().position(|frame| frame.name_en() == bone) {
+        Some(index) => frames.remove(index).position(),
+        None => Vector3::new(0.0, 0.0, 0.0),
+    };
+    let name_jp = db.japanese_name(bone).unwrap_or(bone).to_string();
+    frames.push(MPLBoneFrame::new(
+        bone.to_string(),
+        name_jp,
+        position,
+        rotation,
+    ));
+}
+
+fn joint<'a>(
+    transforms: &'a BTreeMap<String, JointTransform>,
+    bone: &str,
+) -> Result<&'a JointTransform, String> {
+    transforms
+        .get(bone)
+        .ok_or(format!("Skeleton has no joint '{}'", bone))
+}
+
+/// Turn the upper and lower bone of a limb so its end bone lands on the target, and
+/// with `plant` counter-rotate the end bone so it keeps the orientation it has with
+/// the limb straight. Each rotation stays within the bone's rules. Returns the new
+/// frames and how far the end bone ended up from the target.
+pub(crate) fn solve(
+    db: &BoneActionDatabase,
+    mut frames: Vec<MPLBoneFrame>,
+    reach: &MPLReachStatement,
+) -> Result<(Vec<MPLBoneFrame>, f32), String> {
+    let [upper, lower, end] =
+        limb_bones(&reach.limb).ok_or(format!("Unknown limb '{}'", reach.limb))?;
+    let skeleton = db.skeleton();
+    let rest_position = |bone: &str| {
+        skeleton
+            .joint(bone)
+            .map(|joint| joint.position)
+            .ok_or(format!("Skeleton has no joint '{}'", bone))
+    };
+
+    set_rotation(db, &mut frames, upper, Quaternion::identity());
+    set_rotation(db, &mut frames, lower, Quaternion::identity());
+    let straight = skeleton.evaluate(&frames);
+    let (a, b, c) = (
+        joint(&straight, upper)?.position,
+        joint(&straight, lower)?.position,
+        joint(&straight, end)?.position,
+    );
+
+    let target = match reach.target {
+        MPLReachTarget::Point { position } => position,
+        MPLReachTarget::Ground => {
+            // The lowest joint below the end bone, a toe or fingertip, touches the floor
+            let height = rest_position(end)?.y;
+            let lowest = skeleton
+                .descendants(end)
+                .iter()
+                .map(|joint| joint.position.y)
+                .fold(height, f32::min);
+            Vector3::new(c.x, height - lowest, c.z)
+        }
+    };
+
+    // Bend the lower bone until the end bone is as far from the upper joint as the target
+    let lower_hinge = hinges(db, lower, rest_position(end)?.sub(&rest_position(lower)?))
+        .into_iter()
+        .next()
+        .ok_or(format!("Bone '{}' cannot bend", lower))?;
+    let lower_frame = joint(&straight, lower)?.rotation;
+    let axis = lower_frame.rotate(lower_hinge.axis);
+    let (u, v) = (b.sub(&a), c.sub(&b));
+    let distance = target.sub(&a).length();
+    let wanted = (distance * distance - u.dot(&u) - v.dot(&v)) / 2.0;
+    let bend = angles_for_dot(axis, v, u, wanted)
+        .into_iter()
+        .map(|degrees| lower_hinge.clamp(wrap(degrees)))
+        .min_by(|x, y| {
+            let miss = |degrees: f32| {
+                let reached = u.add(&Quaternion::from_axis_angle(axis, degrees).rotate(v));
+                (reached.length() - distance).abs()
+            };
+            miss(*x)
+                .total_cmp(&miss(*y))
+                .then(x.abs().total_cmp(&y.abs()))
+        })
+        .unwrap_or(0.0);
+    let bent = b.add(&Quaternion::from_axis_angle(axis, bend).rotate(v));
+
+    // Swing the upper bone so the bent limb points at the target
+    let mut upper_hinges = hinges(db, upper, rest_position(lower)?.sub(&rest_position(upper)?));
+    if upper_hinges.is_empty() {
+        return Err(format!("Bone '{}' cannot rotate", upper));
+    }
+    upper_hinges.truncate(2);
+    let upper_frame = joint(&straight, upper)?.rotation;
+    let local = |world: Vector3| upper_frame.conjugate().rotate(world).normalize();
+    let (w, t) = (local(bent.sub(&a)), local(target.sub(&a)));
+
+    set_rotation(db, &mut frames, upper, aim(&upper_hinges, w, t));
+    set_rotation(db, &mut frames, lower, lower_hinge.rotation(bend));
+
+    if reach.plant {
+        // Keep the end bone level with the straight limb, within its own limits
+        let posed = skeleton.evaluate(&frames);
+        let parent = skeleton
+            .joint(end)
+            .and_then(|joint| joint.parent.as_deref())
+            .ok_or(format!("Skeleton joint '{}' has no parent", end))?;
+        let wanted = joint(&posed, parent)?
+            .rotation
+            .conjugate()
+            .multiply(&joint(&straight, end)?.rotation);
+        let rotation = MPLPoseStatement::from_quaternion(end, wanted)
+            .iter()
+            .fold(Quaternion::identity(), |q, statement| {
+                q.multiply(&statement.to_quaternion())
+            });
+        set_rotation(db, &mut frames, end, rotation);
+    }
+
+    let reached = joint(&skeleton.evaluate(&frames), end)?.position;
+    Ok((frames, reached.sub(&target).length()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{pose::MPLPose, with_bone_db};
+
+    fn reach(limb: &str, target: MPLReachTarget, plant: bool) -> (Vec<MPLBoneFrame>, f32) {
+        let statement = MPLReachStatement::new(limb, target, plant).unwrap();
+        with_bone_db(|db| solve(db, vec![], &statement)).unwrap()
+    }
+
+    fn point(x: f32, y: f32, z: f32) -> MPLReachTarget {
+        MPLReachTarget::Point {
+            position: Vector3::new(x, y, z),
+        }
+    }
+
+    fn joints(frames: &[MPLBoneFrame]) -> BTreeMap<String, JointTransform> {
+        with_bone_db(|db| db.skeleton().evaluate(frames))
+    }
+
+    /// Where the ankle is with the leg bent by statements the rules allow
+    fn bent_ankle() -> Vector3 {
+        let pose = MPLPose::new(
+            "bent".to_string(),
+            vec![
+                MPLPoseStatement::new("leg_l", "bend", "forward", 30.0).unwrap(),
+                MPLPoseStatement::new("knee_l", "bend", "backward", 60.0).unwrap(),
+            ],
+        );
+        joints(&pose.to_bone_frames())["ankle_l"].position
+    }
+
+    #[test]
+    fn target_in_reach_is_hit() {
+        let ankle = bent_ankle();
+        let (_, miss) = reach("foot_l", point(ankle.x, ankle.y, ankle.z), false);
+        assert!(miss <= REACH_TOLERANCE, "missed by {}", miss);
+
+        let (_, miss) = reach("hand_l", point(4.0, 14.0, -3.0), false);
+        assert!(miss <= REACH_TOLERANCE, "missed by {}", miss);
+    }
+
+    #[test]
+    fn target_out_of_reach_gets_a_straight_limb() {
+        let target = Vector3::new(1.0, -30.0, -5.0);
+        let (frames, miss) = reach("foot_l", point(target.x, target.y, target.z), false);
+        assert!(miss > 1.0);
+
+        let knee = frames.iter().find(|f| f.name_en() == "knee_l").unwrap();
+        assert!(knee.rotation().angle_to(&Quaternion::identity()) < 0.1);
+
+        let posed = joints(&frames);
+        let (hip, ankle) = (posed["leg_l"].position, posed["ankle_l"].position);
+        let toward = target.sub(&hip).normalize();
+        assert!(ankle.sub(&hip).normalize().dot(&toward) > 0.999);
+    }
+
+    #[test]
+    fn planted_foot_keeps_its_straight_leg_orientation() {
+        let ankle = bent_ankle();
+        let (frames, miss) = reach("foot_l", point(ankle.x, ankle.y, ankle.z), true);
+        assert!(miss <= REACH_TOLERANCE, "missed by {}", miss);
+
+        // With the leg straight the ankle is not turned at all
+        let rotation = joints(&frames)["ankle_l"].rotation;
+        assert!(
+            rotation.angle_to(&Quaternion::identity()) < 2.0,
+            "{:?}",
+            rotation
+        );
+    }
+
+    #[test]
+    fn foot_reaching_the_ground_puts_the_toe_on_the_floor() {
+        let (frames, miss) = reach("foot_l", MPLReachTarget::Ground, false);
+        assert!(miss <= REACH_TOLERANCE, "missed by {}", miss);
+        assert!(joints(&frames)["toe_l"].position.y.abs() < REACH_TOLERANCE);
+
+        let compilation =
+            crate::MPLCompiler::new().check("@pose p { foot_l reach ground; } main { p; }");
+        assert!(
+            compilation.diagnostics.is_empty(),
+            "{:?}",
+            compilation.diagnostics
+        );
+        assert_eq!(compilation.program.poses["p"].reach_misses().len(), 1);
+    }
+}
@@ -0,0 +1,159 @@
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+
+use crate::{
+    bone::{ActionKind, ActionRule, BoneActionDatabase},
+    skeleton::{Skeleton, SkeletonJoint},
+    utils::Vector3,
+};
+
+/// Bone rules for a model whose local axes or limits differ from the built-in ones,
+/// written in TOML or JSON:
+///
+/// ```toml
+/// [names]
+/// tail = "尻尾"
+///
+/// [bones.tail.swing.left]
+/// axis = [0.0, 0.0, 1.0]
+/// limit = 40
+/// ```
+///
+/// Each rule overrides the built-in rule for the same bone, action and direction or
+/// adds a new one. Everything else keeps its built-in rule unless `replace` is set.
+/// Joints under `[skeleton.<bone>]` move or add joints of the standard skeleton, or
+/// make up the whole skeleton when `replace_skeleton` is set.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct RigProfile {
+    /// Start from no rules instead of the built-in ones
+    #[serde(default)]
+    pub replace: bool,
+    /// English -> Japanese names of bones the built-in database does not know
+    #[serde(default)]
+    pub names: BTreeMap<String, String>,
+    /// Bone -> action -> direction -> rule
+    #[serde(default)]
+    pub bones: BTreeMap<String, BTreeMap<String, BTreeMap<String, RigRule>>>,
+    /// Build the skeleton from `skeleton` alone instead of the standard one
+    #[serde(default)]
+    pub replace_skeleton: bool,
+    /// Bone -> rest joint
+    #[serde(default)]
+    pub skeleton: BTreeMap<String, RigJoint>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RigRule {
+    /// Rotation axis, or the direction of travel for `move`, in the parent bone's
+    /// local frame like the keyframes MMD stores
+    pub axis: [f32; 3],
+    /// Degrees, or MMD units for `move`
+    pub limit: f32,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RigJoint {
+    /// Kept from the standard skeleton when omitted, or a root with `replace_skeleton`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub parent: Option<String>,
+    /// Rest position in model space
+    pub position: [f32; 3],
+}
+
+impl RigProfile {
+    pub fn from_toml(text: &str) -> Result<Self, String> {
+        toml::from_str(text).map_err(|e| format!("Invalid rig profile: {}", e))
+    }
+
+    pub fn from_json(text: &str) -> Result<Self, String> {
+        serde_json::from_str(text).map_err(|e| format!("Invalid rig profile: {}", e))
+    }
+
+    pub fn to_toml(&self) -> Result<String, String> {
+        toml::to_string(self).map_err(|e| format!("Cannot write rig profile: {}", e))
+    }
+
+    /// JSON when the text is an object, TOML otherwise
+    pub fn parse(text: &str) -> Result<Self, String> {
+        if text.trim_start().starts_with('{') {
+            Self::from_json(text)
+        } else {
+            Self::from_toml(text)
+        }
+    }
+
+    /// The built-in rules with this profile applied
+    pub fn to_database(&self) -> Result<BoneActionDatabase, String> {
+        let mut rules = if self.replace {
+            HashMap::new()
+        } else {
+            BoneActionDatabase::build_rules()
+        };
+
+        for (bone, actions) in &self.bones {
+            for (action, directions) in actions {
+                for (direction, rule) in directions {
+                    let [x, y, z] = rule.axis;
+                    if x == 0.0 && y == 0.0 && z == 0.0 {
+                        return Err(format!(
+                            "Rule {} {} {} needs a non-zero axis",
+                            bone, action, direction
+                        ));
+                    }
+                    if rule.limit.is_nan() || rule.limit < 0.0 {
+                        return Err(format!(
+                            "Rule {} {} {} needs a non-negative limit",
+                            bone, action, direction
+                        ));
+                    }
+
+                    rules
+                        .entry(bone.clone())
+                        .or_default()
+                        .entry(action.clone())
+                        .or_default()
+                        .insert(
+                            direction.clone(),
+                            ActionRule {
+                                axis: Vector3::new(x, y, z),
+                                limit: rule.limit,
+                                kind: ActionKind::of(action),
+                            },
+                        );
+                }
+            }
+        }
+
+        if rules.is_empty() {
+            return Err("Rig profile replaces the built-in rules but defines no bones".to_string());
+        }
+        Ok(BoneActionDatabase::from_rules(rules)
+            .with_bone_names(self.names.clone().into_iter().collect())
+            .with_skeleton(self.to_skeleton()?))
+    }
+
+    /// The standard skeleton with this profile's joints applied, or only this
+    /// profile's joints with `replace_skeleton`
+    pub fn to_skeleton(&self) -> Result<Skeleton, String> {
+        let standard = Skeleton::standard();
+        let joints = self.skeleton.iter().map(|(bone, joint)| {
+            let [x, y, z] = joint.position;
+            let parent = match &joint.parent {
+                Some(parent) => Some(parent.clone()),
+                None if self.replace_skeleton => None,
+                None => standard.joint(bone).and_then(|joint| joint.parent.clone()),
+            };
+            SkeletonJoint {
+                name: bone.clone(),
+                parent,
+                position: Vector3::new(x, y, z),
+            }
+        });
+        let skeleton = if self.replace_skeleton {
+            Skeleton::from_joints(joints.collect())
+        } else {
+            standard.with_joints(joints)
+        };
+        skeleton.map_err(|e| format!("Invalid rig skeleton: {}", e))
+    }
+}
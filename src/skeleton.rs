@@ -0,0 +1,314 @@
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+
+use crate::{
+    mpl::{MPLBoneFrame, MPLKeyFrame},
+    pose::MPLPose,
+    utils::{Quaternion, Vector3},
+};
+
+/// Rest positions of a standard MMD model about 20 units tall, facing -Z with its
+/// arms hanging 45 degrees below horizontal. Right-side joints mirror the `_l` ones.
+const STANDARD_JOINTS: &[(&str, Option<&str>, [f32; 3])] = &[
+    ("base", None, [0.0, 0.0, 0.0]),
+    ("center", Some("base"), [0.0, 8.0, 0.0]),
+    ("groove", Some("center"), [0.0, 8.2, 0.0]),
+    ("waist", Some("groove"), [0.0, 11.2, 0.2]),
+    ("upper_body", Some("waist"), [0.0, 11.8, 0.0]),
+    ("upper_body2", Some("upper_body"), [0.0, 13.0, 0.0]),
+    ("neck", Some("upper_body2"), [0.0, 16.2, 0.3]),
+    ("head", Some("neck"), [0.0, 17.0, 0.1]),
+    ("eyes", Some("head"), [0.0, 18.0, -0.5]),
+    ("eye_l", Some("head"), [0.35, 18.0, -0.5]),
+    ("lower_body", Some("waist"), [0.0, 11.8, 0.0]),
+    ("shoulder_l", Some("upper_body2"), [0.4, 15.6, 0.3]),
+    ("arm_l", Some("shoulder_l"), [1.6, 15.4, 0.3]),
+    ("arm_twist_l", Some("arm_l"), [2.8, 14.2, 0.3]),
+    ("elbow_l", Some("arm_twist_l"), [4.0, 13.0, 0.3]),
+    ("wrist_twist_l", Some("elbow_l"), [5.05, 11.95, 0.3]),
+    ("wrist_l", Some("wrist_twist_l"), [6.1, 10.9, 0.3]),
+    ("thumb_0_l", Some("wrist_l"), [6.2, 10.6, -0.1]),
+    ("thumb_1_l", Some("thumb_0_l"), [6.5, 10.2, -0.3]),
+    ("thumb_2_l", Some("thumb_1_l"), [6.8, 9.9, -0.4]),
+    ("index_0_l", Some("wrist_l"), [7.0, 10.0, 0.05]),
+    ("index_1_l", Some("index_0_l"), [7.35, 9.65, 0.05]),
+    ("index_2_l", Some("index_1_l"), [7.6, 9.4, 0.05]),
+    ("middle_0_l", Some("wrist_l"), [7.05, 9.95, 0.3]),
+    ("middle_1_l", Some("middle_0_l"), [7.45, 9.55, 0.3]),
+    ("middle_2_l", Some("middle_1_l"), [7.7, 9.3, 0.3]),
+    ("ring_0_l", Some("wrist_l"), [7.0, 10.0, 0.5]),
+    ("ring_1_l", Some("ring_0_l"), [7.35, 9.65, 0.5]),
+    ("ring_2_l", Some("ring_1_l"), [7.6, 9.4, 0.5]),
+    ("pinky_0_l", Some("wrist_l"), [6.9, 10.1, 0.7]),
+    ("pinky_1_l", Some("pinky_0_l"), [7.2, 9.8, 0.7]),
+    ("pinky_2_l", Some("pinky_1_l"), [7.4, 9.6, 0.7]),
+    ("leg_l", Some("lower_body"), [0.9, 10.6, 0.0]),
+    ("knee_l", Some("leg_l"), [0.9, 5.9, -0.1]),
+    ("ankle_l", Some("knee_l"), [0.9, 1.2, 0.3]),
+    ("toe_l", Some("ankle_l"), [0.9, 0.0, -1.2]),
+    ("leg_ik_l", Some("base"), [0.9, 1.2, 0.3]),
+];
+
+/// A bone's rest position in model space and the bone it hangs from
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SkeletonJoint {
+    pub name: String,
+    pub parent: Option<String>,
+    pub position: Vector3,
+}
+
+/// Where a joint ends up in model space once a pose is applied
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct JointTransform {
+    pub position: Vector3,
+    pub rotation: Quaternion,
+}
+
+/// The bone tree of a model, for forward kinematics
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Skeleton {
+    /// Parents before their children
+    joints: Vec<SkeletonJoint>,
+}
+
+impl Skeleton {
+    /// The joints of a standard MMD model
+    pub fn standard() -> Self {
+        let mut joints = vec![];
+        for (name, parent, [x, y, z]) in STANDARD_JOINTS {
+            let joint = |name: &str, parent: Option<&str>, x: f32| SkeletonJoint {
+                name: name.to_string(),
+                parent: parent.map(str::to_string),
+                position: Vector3::new(x, *y, *z),
+            };
+            joints.push(joint(name, *parent, *x));
+            if let Some(stem) = name.strip_suffix("_l") {
+                let parent = parent.map(|p| {
+                    p.strip_suffix("_l")
+                        .map_or(p.to_string(), |p| format!("{}_r", p))
+                });
+                joints.push(joint(&format!("{}_r", stem), parent.as_deref(), -x));
+            }
+        }
+        Self::from_joints(joints).expect("the standard skeleton is a tree")
+    }
+
+    /// A skeleton over any joints, as long as every parent exists and the joints form
+    /// a tree
+    pub fn from_joints(joints: Vec<SkeletonJoint>) -> Result<Self, String> {
+        let mut pending: BTreeMap<String, SkeletonJoint> = BTreeMap::new();
+        for joint in joints {
+            if pending.contains_key(&joint.name) {
+                return Err(format!("Joint '{}' is defined twice", joint.name));
+            }
+            pending.insert(joint.name.clone(), joint);
+        }
+        for joint in pending.values() {
+            if let Some(parent) = &joint.parent {
+                if !pending.contains_key(parent) {
+                    return Err(format!(
+                        "Joint '{}' has unknown parent '{}'",
+                        joint.name, parent
+                    ));
+                }
+            }
+        }
+
+        let mut ordered: Vec<SkeletonJoint> = vec![];
+        while !pending.is_empty() {
+            let ready: Vec<String> = pending
+                .values()
+                .filter(|joint| {
+                    joint
+                        .parent
+                        .as_ref()
+                        .is_none_or(|parent| !pending.contains_key(parent))
+                })
+                .map(|joint| joint.name.clone())
+                .collect();
+            if ready.is_empty() {
+                let cycle: Vec<&str> = pending.keys().map(String::as_str).collect();
+                return Err(format!("Joints {} form a cycle", cycle.join(", ")));
+            }
+            ordered.extend(ready.iter().filter_map(|name| pending.remove(name)));
+        }
+        Ok(Self { joints: ordered })
+    }
+
+    /// This skeleton with `joints` added, or replacing the joints of the same name
+    pub fn with_joints(
+        &self,
+        joints: impl IntoIterator<Item = SkeletonJoint>,
+    ) -> Result<Self, String> {
+        let mut merged: BTreeMap<String, SkeletonJoint> = self
+            .joints
+            .iter()
+            .map(|joint| (joint.name.clone(), joint.clone()))
+            .collect();
+        for joint in joints {
+            merged.insert(joint.name.clone(), joint);
+        }
+        Self::from_joints(merged.into_values().collect())
+    }
+
+    pub fn joints(&self) -> &[SkeletonJoint] {
+        &self.joints
+    }
+
+    pub fn joint(&self, name: &str) -> Option<&SkeletonJoint> {
+        self.joints.iter().find(|joint| joint.name == name)
+    }
+
+    /// Every joint below `name`, children before grandchildren
+    pub fn descendants(&self, name: &str) -> Vec<&SkeletonJoint> {
+        let mut names = vec![name];
+        let mut descendants = vec![];
+        for joint in &self.joints {
+            if joint
+                .parent
+                .as_deref()
+                .is_some_and(|parent| names.contains(&parent))
+            {
+                names.push(&joint.name);
+                descendants.push(joint);
+            }
+        }
+        descendants
+    }
+
+    /// Model-space transform of every joint, each bone frame rotating and moving its
+    /// bone relative to its parent. Bones without a frame stay at rest and frames for
+    /// bones the skeleton lacks are ignored.
+    pub fn evaluate(&self, frames: &[MPLBoneFrame]) -> BTreeMap<String, JointTransform> {
+        let local: HashMap<String, &MPLBoneFrame> = frames
+            .iter()
+            .map(|frame| (frame.name_en(), frame))
+            .collect();
+
+        let mut transforms: BTreeMap<String, JointTransform> = BTreeMap::new();
+        for joint in &self.joints {
+            let (translation, rotation) = local.get(&joint.name).map_or(
+                (Vector3::new(0.0, 0.0, 0.0), Quaternion::identity()),
+                |frame| (frame.position(), frame.rotation()),
+            );
+            let parent = joint
+                .parent
+                .as_ref()
+                .and_then(|parent| Some((transforms.get(parent)?, self.joint(parent)?.position)));
+
+            let transform = match parent {
+                Some((parent, rest)) => JointTransform {
+                    position: parent.position.add(
+                        &parent
+                            .rotation
+                            .rotate(joint.position.sub(&rest).add(&translation)),
+                    ),
+                    rotation: parent.rotation.multiply(&rotation),
+                },
+                None => JointTransform {
+                    position: joint.position.add(&translation),
+                    rotation,
+                },
+            };
+            transforms.insert(joint.name.clone(), transform);
+        }
+        transforms
+    }
+
+    pub fn evaluate_pose(&self, pose: &MPLPose) -> BTreeMap<String, JointTransform> {
+        self.evaluate(&pose.to_bone_frames())
+    }
+
+    /// Only the bones the keyframe keys are posed, the rest stay at rest
+    pub fn evaluate_key_frame(&self, key_frame: &MPLKeyFrame) -> BTreeMap<String, JointTransform> {
+        self.evaluate(&key_frame.bone_frames)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn joint(name: &str, parent: Option<&str>, y: f32) -> SkeletonJoint {
+        SkeletonJoint {
+            name: name.to_string(),
+            parent: parent.map(str::to_string),
+            position: Vector3::new(0.0, y, 0.0),
+        }
+    }
+
+    fn frame(name: &str, position: Vector3, rotation: Quaternion) -> MPLBoneFrame {
+        MPLBoneFrame::new(name.to_string(), name.to_string(), position, rotation)
+    }
+
+    /// root at the origin, arm 2 units up and hand 2 more
+    fn chain() -> Skeleton {
+        Skeleton::from_joints(vec![
+            joint("hand", Some("arm"), 4.0),
+            joint("arm", Some("root"), 2.0),
+            joint("root", None, 0.0),
+        ])
+        .unwrap()
+    }
+
+    fn close(a: Vector3, b: Vector3) -> bool {
+        a.sub(&b).length() < 1e-4
+    }
+
+    #[test]
+    fn joints_are_ordered_parents_first() {
+        let skeleton = chain();
+        let names: Vec<&str> = skeleton.joints().iter().map(|j| j.name.as_str()).collect();
+        assert_eq!(names, ["root", "arm", "hand"]);
+    }
+
+    #[test]
+    fn joints_that_are_not_a_tree_are_rejected() {
+        let cycle = Skeleton::from_joints(vec![
+            joint("root", None, 0.0),
+            joint("a", Some("b"), 1.0),
+            joint("b", Some("a"), 2.0),
+        ]);
+        assert_eq!(cycle.unwrap_err(), "Joints a, b form a cycle");
+
+        let orphan = Skeleton::from_joints(vec![joint("a", Some("missing"), 1.0)]);
+        assert_eq!(
+            orphan.unwrap_err(),
+            "Joint 'a' has unknown parent 'missing'"
+        );
+    }
+
+    #[test]
+    fn frames_rotate_and_move_their_children() {
+        let skeleton = chain();
+        let quarter = Quaternion::from_axis_angle(Vector3::new(0.0, 0.0, 1.0), 90.0);
+        let swung = quarter.rotate(Vector3::new(0.0, 1.0, 0.0));
+
+        let posed = skeleton.evaluate(&[frame("arm", Vector3::new(0.0, 0.0, 0.0), quarter)]);
+        assert!(close(posed["arm"].position, Vector3::new(0.0, 2.0, 0.0)));
+        assert!(close(
+            posed["hand"].position,
+            Vector3::new(0.0, 2.0, 0.0).add(&swung.scale(2.0))
+        ));
+        assert!(posed["hand"].rotation.angle_to(&quarter) < 0.1);
+
+        // A translation is in the parent's frame and carries the children along
+        let key_frame = MPLKeyFrame::new(
+            0.0,
+            vec![
+                frame("root", Vector3::new(0.0, 0.0, 0.0), quarter),
+                frame("arm", Vector3::new(0.0, 1.0, 0.0), Quaternion::identity()),
+                frame(
+                    "unknown",
+                    Vector3::new(5.0, 0.0, 0.0),
+                    Quaternion::identity(),
+                ),
+            ],
+            vec![],
+        );
+        let posed = skeleton.evaluate_key_frame(&key_frame);
+        assert!(close(posed["root"].position, Vector3::new(0.0, 0.0, 0.0)));
+        assert!(close(posed["arm"].position, swung.scale(3.0)));
+        assert!(close(posed["hand"].position, swung.scale(5.0)));
+    }
+}
@@ -45,6 +45,11 @@ impl Quaternion {
         1.0 - self.similarity(other)
     }
 
+    /// Angle in degrees of the rotation between two orientations
+    pub fn angle_to(&self, other: &Self) -> f32 {
+        2.0 * self.similarity(other).min(1.0).acos().to_degrees()
+    }
+
     pub fn from_axis_angle(axis: Vector3, degrees: f32) -> Self {
         if degrees.abs() < 0.0001 {
             return Self::identity();
@@ -58,6 +63,100 @@ impl Quaternion {
     }
 }
 
+impl Quaternion {
+    /// Weighted, normalized average of rotations (nlerp). Each rotation is first moved
+    /// onto the identity's hemisphere so that `q` and `-q` don't cancel out.
+    pub fn blend(rotations: &[(Quaternion, f32)]) -> Self {
+        let mut sum = Self::new(0.0, 0.0, 0.0, 0.0);
+        for (q, weight) in rotations {
+            let weight = if q.w < 0.0 { -weight } else { *weight };
+            sum.x += q.x * weight;
+            sum.y += q.y * weight;
+            sum.z += q.z * weight;
+            sum.w += q.w * weight;
+        }
+
+        let length = sum.dot(&sum).sqrt();
+        if length < f32::EPSILON {
+            return Self::identity();
+        }
+        Self::new(
+            sum.x / length,
+            sum.y / length,
+            sum.z / length,
+            sum.w / length,
+        )
+    }
+
+    /// Spherical interpolation from `self` (t = 0) to `other` (t = 1) along the
+    /// shorter arc
+    pub fn slerp(&self, other: &Self, t: f32) -> Self {
+        let mut dot = self.dot(other);
+        let other = if dot < 0.0 {
+            dot = -dot;
+            Self::new(-other.x, -other.y, -other.z, -other.w)
+        } else {
+            *other
+        };
+
+        // Nearly equal rotations make the sine below vanish, interpolate linearly
+        let (a, b) = if dot > 0.9995 {
+            (1.0 - t, t)
+        } else {
+            let angle = dot.acos();
+            (
+                ((1.0 - t) * angle).sin() / angle.sin(),
+                (t * angle).sin() / angle.sin(),
+            )
+        };
+        let q = Self::new(
+            self.x * a + other.x * b,
+            self.y * a + other.y * b,
+            self.z * a + other.z * b,
+            self.w * a + other.w * b,
+        );
+        let length = q.dot(&q).sqrt();
+        Self::new(q.x / length, q.y / length, q.z / length, q.w / length)
+    }
+
+    /// The shortest rotation turning direction `from` onto direction `to`
+    pub fn between(from: Vector3, to: Vector3) -> Self {
+        let (from, to) = (from.normalize(), to.normalize());
+        let dot = from.dot(&to);
+        if dot < -0.9999 {
+            // Opposite directions: half a turn about any perpendicular axis
+            let axis = match from.cross(&Vector3::new(1.0, 0.0, 0.0)) {
+                axis if axis.length() > 0.001 => axis,
+                _ => from.cross(&Vector3::new(0.0, 1.0, 0.0)),
+            };
+            return Self::from_axis_angle(axis, 180.0);
+        }
+
+        let axis = from.cross(&to);
+        let q = Self::new(axis.x, axis.y, axis.z, 1.0 + dot);
+        let length = q.dot(&q).sqrt();
+        Self::new(q.x / length, q.y / length, q.z / length, q.w / length)
+    }
+
+    /// The inverse of a unit rotation
+    pub fn conjugate(&self) -> Self {
+        Self::new(-self.x, -self.y, -self.z, self.w)
+    }
+
+    /// `v` turned by this rotation
+    pub fn rotate(&self, v: Vector3) -> Vector3 {
+        let u = Vector3::new(self.x, self.y, self.z);
+        let t = u.cross(&v);
+        let t = Vector3::new(t.x * 2.0, t.y * 2.0, t.z * 2.0);
+        let ut = u.cross(&t);
+        Vector3::new(
+            v.x + self.w * t.x + ut.x,
+            v.y + self.w * t.y + ut.y,
+            v.z + self.w * t.z + ut.z,
+        )
+    }
+}
+
 #[wasm_bindgen]
 #[derive(Debug, Clone, Copy, Serialize, Deserialize)]
 pub struct Vector3 {
@@ -85,4 +184,28 @@ impl Vector3 {
     pub fn dot(&self, other: &Self) -> f32 {
         self.x * other.x + self.y * other.y + self.z * other.z
     }
+
+    pub fn cross(&self, other: &Self) -> Self {
+        Self {
+            x: self.y * other.z - self.z * other.y,
+            y: self.z * other.x - self.x * other.z,
+            z: self.x * other.y - self.y * other.x,
+        }
+    }
+
+    pub fn length(&self) -> f32 {
+        self.dot(self).sqrt()
+    }
+
+    pub fn add(&self, other: &Self) -> Self {
+        Self::new(self.x + other.x, self.y + other.y, self.z + other.z)
+    }
+
+    pub fn sub(&self, other: &Self) -> Self {
+        Self::new(self.x - other.x, self.y - other.y, self.z - other.z)
+    }
+
+    pub fn scale(&self, factor: f32) -> Self {
+        Self::new(self.x * factor, self.y * factor, self.z * factor)
+    }
 }
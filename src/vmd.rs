@@ -1,46 +1,307 @@
 use encoding_rs::SHIFT_JIS;
 
 use crate::{
-    mpl::MPLKeyFrame,
+    bone::{english_name, morph_english_name, with_bone_db},
+    camera::CAMERA_MODEL_NAME,
+    interpolation::{BoneInterpolation, CameraInterpolation},
+    mpl::{
+        MPLBoneFrame, MPLCameraFrame, MPLIkFrame, MPLIkState, MPLKeyFrame, MPLLightFrame,
+        MPLMorphFrame, MPLShadowFrame,
+    },
     utils::{Quaternion, Vector3},
 };
-use std::io::{Cursor, Write};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::io::{Cursor, Read, Write};
+
+/// MMD's native timeline rate
+pub const DEFAULT_FPS: f32 = 30.0;
+
+/// Frame rates must be positive numbers; others put every key on frame 0 or
+/// divide by zero
+pub(crate) fn check_fps(fps: f32) -> Result<(), String> {
+    if fps.is_finite() && fps > 0.0 {
+        Ok(())
+    } else {
+        Err(format!(
+            "Invalid frame rate {}: must be a positive number",
+            fps
+        ))
+    }
+}
+
+const HEADER_V1: &str = "Vocaloid Motion Data file";
+const HEADER_V2: &str = "Vocaloid Motion Data 0002";
+
+/// Two or more keyframes of the same bone or morph that land on one VMD frame
+#[derive(Debug, Clone, PartialEq)]
+pub struct FrameConflict {
+    pub name: String,
+    pub frame: u32,
+    pub times: Vec<f32>,
+}
+
+impl fmt::Display for FrameConflict {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let times = self
+            .times
+            .iter()
+            .map(|t| format!("{}s", t))
+            .collect::<Vec<String>>()
+            .join(", ");
+        write!(
+            f,
+            "'{}' has {} keyframes on frame {} (at {})",
+            self.name,
+            self.times.len(),
+            self.frame,
+            times
+        )
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct FrameConflictError(pub Vec<FrameConflict>);
+
+impl fmt::Display for FrameConflictError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let conflicts = self
+            .0
+            .iter()
+            .map(|c| c.to_string())
+            .collect::<Vec<String>>()
+            .join("; ");
+        write!(f, "Conflicting keyframes: {}", conflicts)
+    }
+}
+
+impl std::error::Error for FrameConflictError {}
+
+/// Every section of a VMD file, with frame numbers converted to seconds
+#[derive(Debug, Clone)]
+pub struct VMDMotion {
+    pub model_name: String,
+    pub fps: f32,
+    pub key_frames: Vec<MPLKeyFrame>,
+    pub camera_frames: Vec<MPLCameraFrame>,
+    pub light_frames: Vec<MPLLightFrame>,
+    pub shadow_frames: Vec<MPLShadowFrame>,
+    pub ik_frames: Vec<MPLIkFrame>,
+}
 
 #[derive(Debug, Clone)]
 pub struct VMDWriter {
     pub key_frames: Vec<MPLKeyFrame>,
+    pub camera_frames: Vec<MPLCameraFrame>,
+    pub light_frames: Vec<MPLLightFrame>,
+    pub shadow_frames: Vec<MPLShadowFrame>,
+    pub ik_frames: Vec<MPLIkFrame>,
+    pub model_name: String,
+    pub fps: f32,
+}
+
+impl From<VMDMotion> for VMDWriter {
+    fn from(motion: VMDMotion) -> Self {
+        Self {
+            key_frames: motion.key_frames,
+            camera_frames: motion.camera_frames,
+            light_frames: motion.light_frames,
+            shadow_frames: motion.shadow_frames,
+            ik_frames: motion.ik_frames,
+            model_name: motion.model_name,
+            fps: motion.fps,
+        }
+    }
 }
 
 impl VMDWriter {
     pub fn new(key_frames: Vec<MPLKeyFrame>) -> Self {
-        Self { key_frames }
+        Self {
+            key_frames,
+            camera_frames: vec![],
+            light_frames: vec![],
+            shadow_frames: vec![],
+            ik_frames: vec![],
+            model_name: String::new(),
+            fps: DEFAULT_FPS,
+        }
     }
 
-    /// Write a bone frame to the buffer
-    fn write_bone_frame(
+    /// Target frame rate used to convert keyframe times to frame numbers
+    pub fn with_fps(mut self, fps: f32) -> Self {
+        self.fps = fps;
+        self
+    }
+
+    /// Model the motion was made for, in Japanese as MMD shows it
+    pub fn with_model_name(mut self, model_name: &str) -> Self {
+        self.model_name = model_name.to_string();
+        self
+    }
+
+    /// Keep the leg and toe IK off for the whole motion so FK leg bends show up in MMD,
+    /// overriding `ik` statements of the script
+    pub fn with_fk_legs(mut self) -> Self {
+        if !self
+            .ik_frames
+            .iter()
+            .any(|frame| self.frame_number(frame.time) == 0)
+        {
+            self.ik_frames.insert(
+                0,
+                MPLIkFrame {
+                    time: 0.0,
+                    show: true,
+                    ik_states: vec![],
+                },
+            );
+        }
+
+        let legs: Vec<(String, String)> = with_bone_db(|db| {
+            db.iks()
+                .iter()
+                .filter_map(|ik| Some((ik.clone(), db.ik_japanese_name(ik)?.to_string())))
+                .collect()
+        });
+        for frame in &mut self.ik_frames {
+            for (name_en, name_jp) in &legs {
+                match frame.ik_states.iter_mut().find(|s| s.name_jp == *name_jp) {
+                    Some(state) => state.enabled = false,
+                    None => frame.ik_states.push(MPLIkState {
+                        name_en: name_en.clone(),
+                        name_jp: name_jp.clone(),
+                        enabled: false,
+                    }),
+                }
+            }
+        }
+        self
+    }
+
+    /// Split into the model motion and a camera motion holding the camera, light and
+    /// self shadow frames, named the way MMD names camera files
+    pub fn split_camera(self) -> (Self, Self) {
+        let camera = Self {
+            key_frames: vec![],
+            camera_frames: self.camera_frames,
+            light_frames: self.light_frames,
+            shadow_frames: self.shadow_frames,
+            ik_frames: vec![],
+            model_name: CAMERA_MODEL_NAME.to_string(),
+            fps: self.fps,
+        };
+        let model = Self {
+            key_frames: self.key_frames,
+            camera_frames: vec![],
+            light_frames: vec![],
+            shadow_frames: vec![],
+            ik_frames: self.ik_frames,
+            model_name: self.model_name,
+            fps: self.fps,
+        };
+        (model, camera)
+    }
+
+    /// Frame number of a time in seconds, rounded to the nearest frame
+    pub fn frame_number(&self, time: f32) -> u32 {
+        (time * self.fps).round().max(0.0) as u32
+    }
+
+    /// Bones, morphs and camera or lighting tracks with more than one keyframe on the
+    /// same frame
+    pub fn conflicts(&self) -> Vec<FrameConflict> {
+        let mut keys: BTreeMap<(String, u32), Vec<f32>> = BTreeMap::new();
+        for (name, frame, time) in self.keys() {
+            keys.entry((name, frame)).or_default().push(time);
+        }
+
+        keys.into_iter()
+            .filter(|(_, times)| times.len() > 1)
+            .map(|((name, frame), times)| FrameConflict { name, frame, times })
+            .collect()
+    }
+
+    /// Every keyframe of a bone, morph, or the camera, light or shadow track, with its
+    /// frame number and time
+    pub(crate) fn keys(&self) -> Vec<(String, u32, f32)> {
+        let mut keys = vec![];
+        for key_frame in &self.key_frames {
+            let frame = self.frame_number(key_frame.time);
+            let names = key_frame
+                .bone_frames
+                .iter()
+                .map(|b| b.name_en())
+                .chain(key_frame.morph_frames.iter().map(|m| m.name_en.clone()));
+            keys.extend(names.map(|name| (name, frame, key_frame.time)));
+        }
+        let tracks = [
+            (
+                "camera",
+                self.camera_frames
+                    .iter()
+                    .map(|f| f.time)
+                    .collect::<Vec<_>>(),
+            ),
+            ("light", self.light_frames.iter().map(|f| f.time).collect()),
+            (
+                "shadow",
+                self.shadow_frames.iter().map(|f| f.time).collect(),
+            ),
+        ];
+        for (name, times) in tracks {
+            keys.extend(
+                times
+                    .into_iter()
+                    .map(|time| (name.to_string(), self.frame_number(time), time)),
+            );
+        }
+        keys
+    }
+
+    /// Write a Shift-JIS name into a fixed-size, zero-padded field
+    fn write_name(
         cursor: &mut Cursor<Vec<u8>>,
         name: &str,
-        frame: u32,
-        position: Vector3,
-        rotation: Quaternion,
+        size: usize,
     ) -> Result<(), Box<dyn std::error::Error>> {
-        // Write bone name (15 bytes)
         let (name_bytes, _, _) = SHIFT_JIS.encode(name);
-        let mut name_buffer = [0u8; 15];
+        let mut name_buffer = vec![0u8; size];
         for (i, &byte) in name_bytes.iter().enumerate() {
-            if i < 15 {
+            if i < size {
                 name_buffer[i] = byte;
             }
         }
         cursor.write_all(&name_buffer)?;
+        Ok(())
+    }
+
+    fn write_vector(
+        cursor: &mut Cursor<Vec<u8>>,
+        vector: Vector3,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        cursor.write_all(&vector.x.to_le_bytes())?;
+        cursor.write_all(&vector.y.to_le_bytes())?;
+        cursor.write_all(&vector.z.to_le_bytes())?;
+        Ok(())
+    }
+
+    /// Write a bone frame to the buffer
+    fn write_bone_frame(
+        cursor: &mut Cursor<Vec<u8>>,
+        name: &str,
+        frame: u32,
+        position: Vector3,
+        rotation: Quaternion,
+        interpolation: BoneInterpolation,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        // Write bone name (15 bytes)
+        Self::write_name(cursor, name, 15)?;
 
         // Write frame number (4 bytes, little endian)
         cursor.write_all(&frame.to_le_bytes())?;
 
         // Write position (12 bytes: 3 x f32, little endian)
-        cursor.write_all(&position.x.to_le_bytes())?;
-        cursor.write_all(&position.y.to_le_bytes())?;
-        cursor.write_all(&position.z.to_le_bytes())?;
+        Self::write_vector(cursor, position)?;
 
         // Write rotation quaternion (16 bytes: 4 x f32, little endian)
         cursor.write_all(&rotation.x.to_le_bytes())?;
@@ -48,14 +309,13 @@ impl VMDWriter {
         cursor.write_all(&rotation.z.to_le_bytes())?;
         cursor.write_all(&rotation.w.to_le_bytes())?;
 
-        // Write interpolation parameters (64 bytes, all set to 20)
-        let interpolation = [20u8; 64];
-        cursor.write_all(&interpolation)?;
+        // Write interpolation parameters (64 bytes)
+        cursor.write_all(&interpolation.to_bytes())?;
 
         Ok(())
     }
 
-    /// Write a morph frame to the buffer  
+    /// Write a morph frame to the buffer
     fn write_morph_frame(
         cursor: &mut Cursor<Vec<u8>>,
         name: &str,
@@ -63,14 +323,7 @@ impl VMDWriter {
         weight: f32,
     ) -> Result<(), Box<dyn std::error::Error>> {
         // Write morph name (15 bytes)
-        let (name_bytes, _, _) = SHIFT_JIS.encode(name);
-        let mut name_buffer = [0u8; 15];
-        for (i, &byte) in name_bytes.iter().enumerate() {
-            if i < 15 {
-                name_buffer[i] = byte;
-            }
-        }
-        cursor.write_all(&name_buffer)?;
+        Self::write_name(cursor, name, 15)?;
 
         // Write frame number (4 bytes, little endian)
         cursor.write_all(&frame.to_le_bytes())?;
@@ -81,12 +334,56 @@ impl VMDWriter {
         Ok(())
     }
 
+    /// Write a camera frame to the buffer (61 bytes)
+    fn write_camera_frame(
+        cursor: &mut Cursor<Vec<u8>>,
+        frame: u32,
+        camera: &MPLCameraFrame,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        cursor.write_all(&frame.to_le_bytes())?;
+        cursor.write_all(&camera.distance.to_le_bytes())?;
+        Self::write_vector(cursor, camera.target)?;
+        Self::write_vector(cursor, camera.rotation)?;
+        cursor.write_all(&camera.interpolation.to_bytes())?;
+        cursor.write_all(&camera.fov.to_le_bytes())?;
+        // 0 means perspective on
+        cursor.write_all(&[u8::from(!camera.perspective)])?;
+        Ok(())
+    }
+
+    /// Write a show/IK frame to the buffer
+    fn write_ik_frame(
+        cursor: &mut Cursor<Vec<u8>>,
+        frame: u32,
+        ik_frame: &MPLIkFrame,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        cursor.write_all(&frame.to_le_bytes())?;
+        cursor.write_all(&[u8::from(ik_frame.show)])?;
+        cursor.write_all(&(ik_frame.ik_states.len() as u32).to_le_bytes())?;
+        for state in &ik_frame.ik_states {
+            Self::write_name(cursor, &state.name_jp, 20)?;
+            cursor.write_all(&[u8::from(state.enabled)])?;
+        }
+        Ok(())
+    }
+
     /// Create VMD file data from recorded frames
     pub fn create_vmd(&self) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
-        if self.key_frames.is_empty() {
+        check_fps(self.fps)?;
+        if self.key_frames.is_empty()
+            && self.camera_frames.is_empty()
+            && self.light_frames.is_empty()
+            && self.shadow_frames.is_empty()
+            && self.ik_frames.is_empty()
+        {
             return Ok(Vec::new());
         }
 
+        let conflicts = self.conflicts();
+        if !conflicts.is_empty() {
+            return Err(Box::new(FrameConflictError(conflicts)));
+        }
+
         // Count total bone frame entries across all keyframes
         let total_bone_frames: u32 = self
             .key_frames
@@ -105,35 +402,38 @@ impl VMDWriter {
         let header_size = 30 + 20; // Header + model name
         let bone_frame_size = 15 + 4 + 12 + 16 + 64; // 111 bytes per bone frame
         let morph_frame_size = 15 + 4 + 4; // 23 bytes per morph frame
+        let camera_frame_size = 61;
+        let light_frame_size = 28;
+        let shadow_frame_size = 9;
+        let ik_size: usize = self
+            .ik_frames
+            .iter()
+            .map(|f| 4 + 1 + 4 + 21 * f.ik_states.len())
+            .sum();
         let total_size = header_size + 4 + // bone frame count
                     (bone_frame_size * total_bone_frames) as usize +
-                    4 + // morph frame count  
+                    4 + // morph frame count
                     (morph_frame_size * total_morph_frames) as usize +
-                    4 + 4 + 4; // camera, light, self shadow counts
+                    4 + camera_frame_size * self.camera_frames.len() +
+                    4 + light_frame_size * self.light_frames.len() +
+                    4 + shadow_frame_size * self.shadow_frames.len() +
+                    4 + ik_size;
 
         let buffer = Vec::with_capacity(total_size);
         let mut cursor = Cursor::new(buffer);
 
         // Write header (30 bytes)
-        let header = "Vocaloid Motion Data 0002";
-        let mut header_buffer = [0u8; 30];
-        for (i, byte) in header.bytes().enumerate() {
-            if i < 30 {
-                header_buffer[i] = byte;
-            }
-        }
-        cursor.write_all(&header_buffer)?;
+        Self::write_name(&mut cursor, HEADER_V2, 30)?;
 
-        // Write model name (20 bytes, empty)
-        let model_name_buffer = [0u8; 20];
-        cursor.write_all(&model_name_buffer)?;
+        // Write model name (20 bytes)
+        Self::write_name(&mut cursor, &self.model_name, 20)?;
 
         // Write bone frame count
         cursor.write_all(&total_bone_frames.to_le_bytes())?;
 
         // Write bone frames
         for frame in &self.key_frames {
-            let frame_number = (frame.time * 60.0) as u32; // Convert seconds to frame at 60 fps
+            let frame_number = self.frame_number(frame.time);
             for bone_frame in &frame.bone_frames {
                 Self::write_bone_frame(
                     &mut cursor,
@@ -141,6 +441,7 @@ impl VMDWriter {
                     frame_number,
                     bone_frame.position(),
                     bone_frame.rotation(),
+                    bone_frame.interpolation(),
                 )?;
             }
         }
@@ -150,7 +451,7 @@ impl VMDWriter {
 
         // Write morph frames
         for frame in &self.key_frames {
-            let frame_number = (frame.time * 60.0) as u32; // Convert seconds to frame at 60 fps
+            let frame_number = self.frame_number(frame.time);
             for morph_frame in &frame.morph_frames {
                 Self::write_morph_frame(
                     &mut cursor,
@@ -161,11 +462,459 @@ impl VMDWriter {
             }
         }
 
-        // Write counts for other frame types (all 0)
-        cursor.write_all(&0u32.to_le_bytes())?; // Camera keyframe count
-        cursor.write_all(&0u32.to_le_bytes())?; // Light keyframe count
-        cursor.write_all(&0u32.to_le_bytes())?; // Self shadow keyframe count
+        // Write camera frames
+        cursor.write_all(&(self.camera_frames.len() as u32).to_le_bytes())?;
+        for camera in &self.camera_frames {
+            Self::write_camera_frame(&mut cursor, self.frame_number(camera.time), camera)?;
+        }
+
+        // Write light frames (28 bytes each)
+        cursor.write_all(&(self.light_frames.len() as u32).to_le_bytes())?;
+        for light in &self.light_frames {
+            cursor.write_all(&self.frame_number(light.time).to_le_bytes())?;
+            Self::write_vector(&mut cursor, light.color)?;
+            Self::write_vector(&mut cursor, light.direction)?;
+        }
+
+        // Write self shadow frames (9 bytes each)
+        cursor.write_all(&(self.shadow_frames.len() as u32).to_le_bytes())?;
+        for shadow in &self.shadow_frames {
+            cursor.write_all(&self.frame_number(shadow.time).to_le_bytes())?;
+            cursor.write_all(&[shadow.mode])?;
+            cursor.write_all(&shadow.distance.to_le_bytes())?;
+        }
+
+        // Write show/IK frames
+        cursor.write_all(&(self.ik_frames.len() as u32).to_le_bytes())?;
+        for ik_frame in &self.ik_frames {
+            Self::write_ik_frame(&mut cursor, self.frame_number(ik_frame.time), ik_frame)?;
+        }
 
         Ok(cursor.into_inner())
     }
 }
+
+/// Parses VMD motion data back into keyframes.
+///
+/// Bone frames that share a frame number and sit next to each other in the file form one
+/// `MPLKeyFrame`, and morph frames are grouped the same way, so writing the result with
+/// `VMDWriter` at the same fps reproduces the original bytes for files with zero-padded
+/// names. Sections missing from the end of older files read as empty.
+pub struct VMDReader<'a> {
+    cursor: Cursor<&'a [u8]>,
+    fps: f32,
+}
+
+impl<'a> VMDReader<'a> {
+    pub fn new(data: &'a [u8]) -> Self {
+        Self {
+            cursor: Cursor::new(data),
+            fps: DEFAULT_FPS,
+        }
+    }
+
+    /// Frame rate used to convert frame numbers to seconds
+    pub fn with_fps(mut self, fps: f32) -> Self {
+        self.fps = fps;
+        self
+    }
+
+    pub fn read(mut self) -> Result<VMDMotion, Box<dyn std::error::Error>> {
+        check_fps(self.fps)?;
+        let header = self.read_name(30, "header")?;
+        let model_name_size = match header.as_str() {
+            HEADER_V1 => 10,
+            HEADER_V2 => 20,
+            _ => return Err(format!("Not a VMD file (header '{}')", header).into()),
+        };
+        let model_name = self.read_name(model_name_size, "model name")?;
+
+        let mut motion = VMDMotion {
+            model_name,
+            fps: self.fps,
+            key_frames: vec![],
+            camera_frames: vec![],
+            light_frames: vec![],
+            shadow_frames: vec![],
+            ik_frames: vec![],
+        };
+
+        for _ in 0..self.read_u32("bone frame count")? {
+            let (frame, bone_frame) = self.read_bone_frame()?;
+            let time = self.time(frame);
+            match motion.key_frames.last_mut() {
+                Some(key_frame) if key_frame.time == time => key_frame.bone_frames.push(bone_frame),
+                _ => motion
+                    .key_frames
+                    .push(MPLKeyFrame::new(time, vec![bone_frame], vec![])),
+            }
+        }
+
+        let Some(count) = self.read_section_count("morph frame count")? else {
+            return Ok(motion);
+        };
+        let mut morph_key_frames: Vec<MPLKeyFrame> = vec![];
+        for _ in 0..count {
+            let name_jp = self.read_name(15, "morph name")?;
+            let frame = self.read_u32("morph frame number")?;
+            let weight = self.read_f32("morph weight")?;
+            let morph_frame = MPLMorphFrame {
+                name_en: morph_english_name(&name_jp),
+                name_jp,
+                weight,
+            };
+            let time = self.time(frame);
+            match morph_key_frames.last_mut() {
+                Some(key_frame) if key_frame.time == time => {
+                    key_frame.morph_frames.push(morph_frame)
+                }
+                _ => morph_key_frames.push(MPLKeyFrame::new(time, vec![], vec![morph_frame])),
+            }
+        }
+        motion.key_frames.extend(morph_key_frames);
+
+        let Some(count) = self.read_section_count("camera frame count")? else {
+            return Ok(motion);
+        };
+        for _ in 0..count {
+            let frame = self.read_u32("camera frame number")?;
+            let distance = self.read_f32("camera distance")?;
+            let target = self.read_vector("camera target")?;
+            let rotation = self.read_vector("camera rotation")?;
+            let interpolation =
+                CameraInterpolation::from_bytes(&self.read_bytes("camera interpolation")?);
+            let fov = self.read_u32("camera fov")?;
+            let [perspective] = self.read_bytes("camera perspective")?;
+            motion.camera_frames.push(MPLCameraFrame {
+                time: self.time(frame),
+                distance,
+                target,
+                rotation,
+                interpolation,
+                fov,
+                perspective: perspective == 0,
+            });
+        }
+
+        let Some(count) = self.read_section_count("light frame count")? else {
+            return Ok(motion);
+        };
+        for _ in 0..count {
+            let frame = self.read_u32("light frame number")?;
+            motion.light_frames.push(MPLLightFrame {
+                time: self.time(frame),
+                color: self.read_vector("light color")?,
+                direction: self.read_vector("light direction")?,
+            });
+        }
+
+        let Some(count) = self.read_section_count("self shadow frame count")? else {
+            return Ok(motion);
+        };
+        for _ in 0..count {
+            let frame = self.read_u32("self shadow frame number")?;
+            let [mode] = self.read_bytes("self shadow mode")?;
+            motion.shadow_frames.push(MPLShadowFrame {
+                time: self.time(frame),
+                mode,
+                distance: self.read_f32("self shadow distance")?,
+            });
+        }
+
+        let Some(count) = self.read_section_count("show/IK frame count")? else {
+            return Ok(motion);
+        };
+        for _ in 0..count {
+            let frame = self.read_u32("show/IK frame number")?;
+            let [show] = self.read_bytes("show flag")?;
+            let mut ik_states = vec![];
+            for _ in 0..self.read_u32("IK count")? {
+                let name_jp = self.read_name(20, "IK bone name")?;
+                let [enabled] = self.read_bytes("IK flag")?;
+                ik_states.push(MPLIkState {
+                    name_en: with_bone_db(|db| db.ik_english_name(&name_jp).map(str::to_string))
+                        .unwrap_or_else(|| english_name(&name_jp)),
+                    name_jp,
+                    enabled: enabled != 0,
+                });
+            }
+            motion.ik_frames.push(MPLIkFrame {
+                time: self.time(frame),
+                show: show != 0,
+                ik_states,
+            });
+        }
+
+        Ok(motion)
+    }
+
+    fn read_bone_frame(&mut self) -> Result<(u32, MPLBoneFrame), Box<dyn std::error::Error>> {
+        let name_jp = self.read_name(15, "bone name")?;
+        let frame = self.read_u32("bone frame number")?;
+        let position = self.read_vector("bone position")?;
+        let rotation = Quaternion::new(
+            self.read_f32("bone rotation")?,
+            self.read_f32("bone rotation")?,
+            self.read_f32("bone rotation")?,
+            self.read_f32("bone rotation")?,
+        );
+        let interpolation = BoneInterpolation::from_bytes(&self.read_bytes("bone interpolation")?);
+
+        let bone_frame = MPLBoneFrame::new(english_name(&name_jp), name_jp, position, rotation)
+            .with_interpolation(interpolation);
+        Ok((frame, bone_frame))
+    }
+
+    fn time(&self, frame: u32) -> f32 {
+        frame as f32 / self.fps
+    }
+
+    fn read_bytes<const N: usize>(
+        &mut self,
+        what: &str,
+    ) -> Result<[u8; N], Box<dyn std::error::Error>> {
+        let mut buffer = [0u8; N];
+        self.fill(&mut buffer, what)?;
+        Ok(buffer)
+    }
+
+    fn fill(&mut self, buffer: &mut [u8], what: &str) -> Result<(), Box<dyn std::error::Error>> {
+        let position = self.cursor.position();
+        self.cursor.read_exact(buffer).map_err(|_| {
+            format!(
+                "Unexpected end of VMD data while reading {} at byte {}",
+                what, position
+            )
+        })?;
+        Ok(())
+    }
+
+    fn read_u32(&mut self, what: &str) -> Result<u32, Box<dyn std::error::Error>> {
+        Ok(u32::from_le_bytes(self.read_bytes(what)?))
+    }
+
+    fn read_f32(&mut self, what: &str) -> Result<f32, Box<dyn std::error::Error>> {
+        Ok(f32::from_le_bytes(self.read_bytes(what)?))
+    }
+
+    fn read_vector(&mut self, what: &str) -> Result<Vector3, Box<dyn std::error::Error>> {
+        Ok(Vector3::new(
+            self.read_f32(what)?,
+            self.read_f32(what)?,
+            self.read_f32(what)?,
+        ))
+    }
+
+    /// Count of an optional trailing section, `None` when the file ends before it
+    fn read_section_count(
+        &mut self,
+        what: &str,
+    ) -> Result<Option<u32>, Box<dyn std::error::Error>> {
+        if self.cursor.position() as usize >= self.cursor.get_ref().len() {
+            return Ok(None);
+        }
+        self.read_u32(what).map(Some)
+    }
+
+    /// Read a fixed-size Shift-JIS field, ending at the first zero byte
+    fn read_name(&mut self, size: usize, what: &str) -> Result<String, Box<dyn std::error::Error>> {
+        let mut buffer = vec![0u8; size];
+        self.fill(&mut buffer, what)?;
+        let end = buffer.iter().position(|&b| b == 0).unwrap_or(size);
+        let (name, _, _) = SHIFT_JIS.decode(&buffer[..end]);
+        Ok(name.into_owned())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::interpolation::BezierCurve;
+
+    fn morph(name_en: &str, name_jp: &str) -> MPLMorphFrame {
+        MPLMorphFrame {
+            name_en: name_en.to_string(),
+            name_jp: name_jp.to_string(),
+            weight: 0.5,
+        }
+    }
+
+    #[test]
+    fn every_section_survives_a_read_write_round_trip() {
+        let ease = BezierCurve::preset("ease_in_out").unwrap();
+        let head = MPLBoneFrame::new(
+            "head".to_string(),
+            "頭".to_string(),
+            Vector3::new(0.0, 0.5, 0.0),
+            Quaternion::from_axis_angle(Vector3::new(0.0, 1.0, 0.0), 25.0),
+        )
+        .with_interpolation(BoneInterpolation::uniform(ease));
+        let writer = VMDWriter {
+            key_frames: vec![
+                MPLKeyFrame::new(0.0, vec![head.clone()], vec![morph("blink", "まばたき")]),
+                MPLKeyFrame::new(1.0, vec![head], vec![]),
+            ],
+            camera_frames: vec![MPLCameraFrame {
+                time: 0.5,
+                distance: -45.0,
+                target: Vector3::new(0.0, 10.0, 0.0),
+                rotation: Vector3::new(0.1, 2.5, 0.0),
+                interpolation: CameraInterpolation::uniform(ease),
+                fov: 30,
+                perspective: true,
+            }],
+            light_frames: vec![MPLLightFrame {
+                time: 0.0,
+                color: Vector3::new(0.6, 0.6, 0.6),
+                direction: Vector3::new(-0.5, -1.0, 0.5),
+            }],
+            shadow_frames: vec![MPLShadowFrame {
+                time: 0.0,
+                mode: 1,
+                distance: 0.0089,
+            }],
+            ik_frames: vec![MPLIkFrame {
+                time: 0.0,
+                show: true,
+                ik_states: vec![MPLIkState {
+                    name_en: "leg_l".to_string(),
+                    name_jp: "左足ＩＫ".to_string(),
+                    enabled: false,
+                }],
+            }],
+            model_name: "初音ミク".to_string(),
+            fps: DEFAULT_FPS,
+        };
+
+        let bytes = writer.create_vmd().unwrap();
+        let motion = VMDReader::new(&bytes).read().unwrap();
+        assert_eq!(VMDWriter::from(motion).create_vmd().unwrap(), bytes);
+    }
+
+    /// A script compiled, written and read back, with the size of the written file
+    fn round_trip(text: &str) -> (usize, VMDMotion) {
+        let motion = crate::MPLCompiler::new().compile_motion(text).unwrap();
+        let bytes = VMDWriter::from(motion).create_vmd().unwrap();
+        (bytes.len(), VMDReader::new(&bytes).read().unwrap())
+    }
+
+    fn close(a: Vector3, b: Vector3) -> bool {
+        a.sub(&b).length() < 1e-4
+    }
+
+    #[test]
+    fn camera_frames_are_read_back() {
+        let (size, motion) = round_trip(
+            "@camera c { 0: distance 30, target (0, 12, 0), fov 40; \
+                         1: rotation (0, 90, 0); 2: perspective off; } \
+             main { c; }",
+        );
+        // Header, empty bone and morph sections, 61 bytes a camera frame and the empty
+        // light, shadow and show/IK sections
+        assert_eq!(size, 50 + 4 + 4 + (4 + 3 * 61) + 4 + 4 + 4);
+
+        // Properties left out keep their previous value
+        let camera = &motion.camera_frames;
+        let times: Vec<f32> = camera.iter().map(|f| f.time).collect();
+        assert_eq!(times, [0.0, 1.0, 2.0]);
+        assert!(camera.iter().all(|f| f.distance == -30.0 && f.fov == 40));
+        assert!(camera
+            .iter()
+            .all(|f| close(f.target, Vector3::new(0.0, 12.0, 0.0))));
+        assert!(close(camera[0].rotation, Vector3::new(0.0, 0.0, 0.0)));
+        let turned = Vector3::new(0.0, std::f32::consts::FRAC_PI_2, 0.0);
+        assert!(close(camera[1].rotation, turned) && close(camera[2].rotation, turned));
+        assert!(camera[1].perspective && !camera[2].perspective);
+    }
+
+    #[test]
+    fn light_and_shadow_frames_are_read_back() {
+        let (size, motion) = round_trip(
+            "@light l { 0: color (255, 51, 0); 1: direction (0, -1, 0); } \
+             @shadow s { 0: distance 5000; 1: mode 2; } \
+             main { l & s; }",
+        );
+        // 28 bytes a light frame and 9 a shadow frame
+        assert_eq!(size, 50 + 4 + 4 + 4 + (4 + 2 * 28) + (4 + 2 * 9) + 4);
+
+        // Properties left out keep their previous value
+        let light = &motion.light_frames;
+        assert!(close(light[0].color, Vector3::new(1.0, 0.2, 0.0)));
+        assert!(close(light[1].color, light[0].color));
+        assert!(close(light[0].direction, Vector3::new(-0.5, -1.0, 0.5)));
+        assert!(close(light[1].direction, Vector3::new(0.0, -1.0, 0.0)));
+
+        // MMD stores the panel's shadow distance as 0.1 - distance * 0.00001
+        let shadow = &motion.shadow_frames;
+        assert_eq!((shadow[0].mode, shadow[1].mode), (1, 2));
+        assert!(shadow.iter().all(|f| (f.distance - 0.05).abs() < 1e-6));
+    }
+
+    #[test]
+    fn show_and_ik_switches_are_read_back() {
+        let (size, motion) = round_trip(
+            "@pose kneel { ik leg_l off; } @pose hide { show off; } \
+             main { kneel; hide; }",
+        );
+        let frames = &motion.ik_frames;
+        assert_eq!(frames.len(), 2);
+        // Every chain of the model is written on each frame, 21 bytes apiece
+        let chains = frames[0].ik_states.len();
+        assert_eq!(chains, 4);
+        assert_eq!(
+            size,
+            50 + 4 + 4 + 4 + 4 + 4 + 4 + 2 * (4 + 1 + 4 + 21 * chains)
+        );
+
+        let enabled = |frame: &MPLIkFrame, ik: &str| {
+            frame
+                .ik_states
+                .iter()
+                .find(|s| s.name_en == ik)
+                .unwrap()
+                .enabled
+        };
+        assert_eq!((frames[0].time, frames[1].time), (0.0, 1.0));
+        assert!(frames[0].show && !frames[1].show);
+        // Switches carry over until changed
+        assert!(!enabled(&frames[0], "leg_l") && !enabled(&frames[1], "leg_l"));
+        assert!(enabled(&frames[0], "leg_r") && enabled(&frames[1], "toe_l"));
+    }
+
+    #[test]
+    fn frame_rates_that_are_not_positive_are_rejected() {
+        let key_frames = vec![
+            MPLKeyFrame::new(0.0, vec![], vec![morph("blink", "まばたき")]),
+            MPLKeyFrame::new(1.0, vec![], vec![morph("blink", "まばたき")]),
+        ];
+        let bytes = VMDWriter::new(key_frames.clone()).create_vmd().unwrap();
+        for fps in [0.0, -30.0, f32::NAN, f32::INFINITY] {
+            let writer = VMDWriter::new(key_frames.clone()).with_fps(fps);
+            let error = writer.create_vmd().unwrap_err();
+            assert!(
+                error.to_string().contains("Invalid frame rate"),
+                "{}",
+                error
+            );
+            assert!(VMDReader::new(&bytes).with_fps(fps).read().is_err());
+        }
+    }
+
+    #[test]
+    fn morph_names_are_read_back_in_english() {
+        let key_frames = vec![MPLKeyFrame::new(
+            0.0,
+            vec![],
+            vec![morph("blink", "まばたき"), morph("unknown", "謎")],
+        )];
+        let bytes = VMDWriter::new(key_frames).create_vmd().unwrap();
+        let motion = VMDReader::new(&bytes).read().unwrap();
+
+        let names: Vec<&str> = motion.key_frames[0]
+            .morph_frames
+            .iter()
+            .map(|frame| frame.name_en.as_str())
+            .collect();
+        assert_eq!(names, ["blink", "謎"]);
+    }
+}
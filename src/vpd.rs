@@ -0,0 +1,216 @@
+use encoding_rs::SHIFT_JIS;
+
+use crate::{
+    bone::{english_name, morph_english_name},
+    mpl::{MPLBoneFrame, MPLMorphFrame},
+    utils::{Quaternion, Vector3},
+};
+
+const HEADER: &str = "Vocaloid Pose Data file";
+
+/// A VPD pose file: MMD's Shift-JIS text format holding one frame of bone transforms
+/// and morph weights
+#[derive(Debug, Clone)]
+pub struct VPDPose {
+    /// Parent model file, e.g. `miku.osm`
+    pub model_name: String,
+    pub bone_frames: Vec<MPLBoneFrame>,
+    pub morph_frames: Vec<MPLMorphFrame>,
+}
+
+impl VPDPose {
+    pub fn new(model_name: &str, bone_frames: Vec<MPLBoneFrame>) -> Self {
+        Self {
+            model_name: model_name.to_string(),
+            bone_frames,
+            morph_frames: vec![],
+        }
+    }
+
+    pub fn parse(data: &[u8]) -> Result<Self, String> {
+        let (text, _, _) = SHIFT_JIS.decode(data);
+        let mut lines = text
+            .lines()
+            .enumerate()
+            .map(|(i, line)| {
+                let line = line.split("//").next().unwrap_or("");
+                (i + 1, line.trim())
+            })
+            .filter(|(_, line)| !line.is_empty());
+
+        match lines.next() {
+            Some((_, HEADER)) => {}
+            _ => {
+                return Err("Not a VPD file (missing 'Vocaloid Pose Data file' header)".to_string())
+            }
+        }
+
+        let (_, model_name) = lines
+            .next()
+            .ok_or("Unexpected end of VPD data while reading model name")?;
+        let model_name = model_name.trim_end_matches(';').trim().to_string();
+
+        // The declared bone count is informational, blocks are read until the end
+        lines
+            .next()
+            .ok_or("Unexpected end of VPD data while reading bone count")?;
+
+        let mut pose = Self {
+            model_name,
+            bone_frames: vec![],
+            morph_frames: vec![],
+        };
+        while let Some((line_number, line)) = lines.next() {
+            let (kind, name) = line.split_once('{').ok_or(format!(
+                "Line {}: expected 'Bone{{' or 'Morph{{' block",
+                line_number
+            ))?;
+            let name = name.trim().to_string();
+            let mut values = vec![];
+            loop {
+                let (line_number, line) = lines
+                    .next()
+                    .ok_or(format!("Unexpected end of VPD data in block '{}'", name))?;
+                if line == "}" {
+                    break;
+                }
+                values.push(parse_values(line_number, line)?);
+            }
+
+            if kind.starts_with("Bone") {
+                match values.as_slice() {
+                    [position, rotation] if position.len() == 3 && rotation.len() == 4 => {
+                        pose.bone_frames.push(MPLBoneFrame::new(
+                            english_name(&name),
+                            name,
+                            Vector3::new(position[0], position[1], position[2]),
+                            Quaternion::new(rotation[0], rotation[1], rotation[2], rotation[3]),
+                        ))
+                    }
+                    _ => {
+                        return Err(format!(
+                            "Line {}: bone '{}' needs a position and a quaternion",
+                            line_number, name
+                        ))
+                    }
+                }
+            } else if kind.starts_with("Morph") {
+                match values.as_slice() {
+                    [weight] if weight.len() == 1 => pose.morph_frames.push(MPLMorphFrame {
+                        name_en: morph_english_name(&name),
+                        name_jp: name,
+                        weight: weight[0],
+                    }),
+                    _ => {
+                        return Err(format!(
+                            "Line {}: morph '{}' needs a single weight",
+                            line_number, name
+                        ))
+                    }
+                }
+            } else {
+                return Err(format!("Line {}: unknown block '{}'", line_number, kind));
+            }
+        }
+
+        Ok(pose)
+    }
+
+    /// Shift-JIS bytes laid out the way MMD saves poses
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut text = format!(
+            "{}\r\n\r\n{};\t\t// 親ファイル名\r\n{};\t\t\t\t// 総ポーズボーン数\r\n\r\n",
+            HEADER,
+            self.model_name,
+            self.bone_frames.len()
+        );
+
+        for (i, frame) in self.bone_frames.iter().enumerate() {
+            let position = frame.position();
+            let rotation = frame.rotation();
+            text.push_str(&format!(
+                "Bone{}{{{}\r\n  {:.6},{:.6},{:.6};\t\t\t\t// trans x,y,z\r\n  {:.6},{:.6},{:.6},{:.6};\t\t// Quaternion x,y,z,w\r\n}}\r\n\r\n",
+                i,
+                frame.name_jp(),
+                position.x,
+                position.y,
+                position.z,
+                rotation.x,
+                rotation.y,
+                rotation.z,
+                rotation.w
+            ));
+        }
+
+        for (i, frame) in self.morph_frames.iter().enumerate() {
+            text.push_str(&format!(
+                "Morph{}{{{}\r\n  {:.6};\t\t\t\t// weight\r\n}}\r\n\r\n",
+                i, frame.name_jp, frame.weight
+            ));
+        }
+
+        let (bytes, _, _) = SHIFT_JIS.encode(&text);
+        bytes.into_owned()
+    }
+}
+
+/// `x,y,z;` on a single line
+fn parse_values(line_number: usize, line: &str) -> Result<Vec<f32>, String> {
+    line.trim_end_matches(';')
+        .split(',')
+        .map(|value| {
+            value
+                .trim()
+                .parse::<f32>()
+                .map_err(|_| format!("Line {}: invalid number '{}'", line_number, value.trim()))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn morph_names_are_read_back_in_english() {
+        let mut pose = VPDPose::new("model.osm", vec![]);
+        pose.morph_frames.push(MPLMorphFrame {
+            name_en: "smile".to_string(),
+            name_jp: "笑い".to_string(),
+            weight: 1.0,
+        });
+        let read = VPDPose::parse(&pose.to_bytes()).unwrap();
+        assert_eq!(read.morph_frames[0].name_en, "smile");
+        assert_eq!(read.morph_frames[0].name_jp, "笑い");
+    }
+
+    #[test]
+    fn poses_survive_a_vpd_round_trip() {
+        let program = crate::MPLCompiler::new()
+            .check(
+                "@pose a { head turn left 30; neck bend forward 10; center move up 2; \
+                 morph smile 0.5; } main { a; }",
+            )
+            .program;
+        let pose = &program.poses["a"];
+        let read = crate::MPLPose::from_vpd("a", &pose.to_vpd("model.osm")).unwrap();
+
+        let frames = pose.to_bone_frames();
+        let read_frames = read.to_bone_frames();
+        assert_eq!(frames.len(), read_frames.len());
+        for frame in &frames {
+            let read_frame = read_frames
+                .iter()
+                .find(|f| f.name_en() == frame.name_en())
+                .unwrap();
+            assert!(frame.rotation().angle_to(&read_frame.rotation()) < 1.0);
+            assert!(frame.position().sub(&read_frame.position()).length() < 0.05);
+        }
+        let morphs: Vec<(&str, f32)> = read
+            .morphs
+            .iter()
+            .map(|m| (m.morph.as_str(), m.weight))
+            .collect();
+        assert_eq!(morphs, [("smile", 0.5)]);
+    }
+}